@@ -1,15 +1,36 @@
-use std::collections::HashMap;
+use crate::capture::{self, RuntimeDiff};
+use crate::compare::{compare, Comparison};
+use crate::config::{ConfigEdit, ConfigFile};
+use crate::dialect::Dialect;
+use crate::dotenv;
+use crate::environment_d::{self, Resolved, ENVELOPE_DROP_IN};
+use crate::error::{Diagnostic, Error};
+use crate::escape::{display, escape, unescape};
+use crate::event::Event;
+use crate::history::{FileChange, History, HistoryEntry, Operation, Snapshot};
+use crate::parser::is_name;
+use crate::path_evolution::{self, PathStep};
+use crate::pending::PendingChanges;
+use crate::provenance::{self, Provenance};
+use crate::search::Search;
+use crate::shell;
+use crate::startup::{self, ChainEntry, Mode};
+use crate::writer::{is_writable, write_staged_copy};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::env::{split_paths, var_os};
-use std::error;
+use std::ffi::{OsStr, OsString};
 use std::fs::read_to_string;
-use std::io::Error;
-use std::path::PathBuf;
-pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
+use std::io::ErrorKind;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+pub type AppResult<T> = std::result::Result<T, Error>;
 
 pub struct App {
     /// Houses environment variables for the current environment.
-    pub env_vars: Vec<(String, String)>,
+    pub env_vars: Vec<(OsString, OsString)>,
     /// Houses the directories stored in the path variable.
     pub path_var_dirs: Vec<PathBuf>,
     /// Specifies which environment variable is currently being edited.
@@ -20,11 +41,17 @@ pub struct App {
     pub selected_path_dir: usize,
     /// Specifies whether or not the app is in an `editing` state.
     pub editing: bool,
+    /// Whether the value being edited isn't valid UTF-8, so the edit buffer
+    /// holds it escaped.
+    pub editing_bytes: bool,
     /// Houses the edited environment variable value string.
     pub env_var_value: String,
     /// Houses the path variable being edited.
     pub path_var_value: String,
     pub path_var_edit: String,
+    /// Where in `path_var_dirs` the directory being entered in
+    /// `path_var_edit` goes, when inserting one rather than editing.
+    pub path_insert: Option<usize>,
     /// Houses the edited environment variable key string.
     pub env_var_key: String,
     /// Holds the state of the list of environment variables
@@ -41,12 +68,145 @@ pub struct App {
     pub activated_list: ActiveList,
     /// User shell
     pub shell: String,
+    /// Syntax of the shell config file.
+    pub dialect: Dialect,
     /// Environment variables from .bashrc
     pub shell_env_vars: HashMap<String, String>,
+    /// Variables whose values are written so that references to other
+    /// variables in them expand; other values are written literally. Starts
+    /// as the variables whose definition in the config file references
+    /// another.
+    pub expanding: HashSet<String>,
     /// Shell config path
     pub config_path: PathBuf,
     /// Overwriting signifier
     pub overwrite: bool,
+    /// Edits staged for the config file but not yet written.
+    pub pending: PendingChanges,
+    /// Unified diff of what applying `pending` would change.
+    pub pending_diff: String,
+    /// Environment variables as of the last apply, restored on discard.
+    pub saved_env_vars: Vec<(OsString, OsString)>,
+    /// Path directories as of the last apply, restored on discard.
+    pub saved_path_var_dirs: Vec<PathBuf>,
+    /// Path directories as envelope started with them. `PATH` edits are
+    /// written as additions around it where they can be.
+    pub inherited_path_dirs: Vec<PathBuf>,
+    /// Operations that can be undone and redone.
+    pub history: History,
+    /// Whether the history pane is shown.
+    pub show_history: bool,
+    /// Why the name being entered for a new or renamed variable can't be
+    /// used yet.
+    pub name_error: Option<String>,
+    /// Variable being renamed; the new name is entered in `env_var_key`.
+    pub rename_from: Option<String>,
+    /// Lines that renaming `rename_from` will change, as `file:line: text`.
+    pub rename_preview: Vec<String>,
+    /// Inherited variable being deleted, awaiting confirmation to write an
+    /// explicit unset for it.
+    pub confirm_unset: Option<String>,
+    /// Whether quitting is waiting for the pending changes to be saved or
+    /// discarded.
+    pub confirm_quit: bool,
+    /// Whether edits go to the user's shell config or the system-wide file.
+    pub scope: Scope,
+    /// System-wide environment file, `/etc/environment`.
+    pub system_env_path: PathBuf,
+    /// Environment variables from the system-wide environment file.
+    pub system_env_vars: HashMap<String, String>,
+    /// Files that can't be written without root, awaiting confirmation to
+    /// stage copies of them for a privileged install.
+    pub confirm_install: Vec<PathBuf>,
+    /// Staged copies of files envelope couldn't write, each with the command
+    /// that installs it.
+    pub install_commands: Vec<(PathBuf, String)>,
+    /// systemd `environment.d` directories, highest precedence first: the
+    /// user's, then `/etc/environment.d`, then those owned by the system.
+    pub session_dirs: Vec<PathBuf>,
+    /// Effective values of the variables set by `environment.d` drop-ins,
+    /// with the drop-in that set each one last.
+    pub session_env: HashMap<String, Resolved>,
+    /// Dotenv file being edited in place of the environment, when envelope
+    /// was started with `--dotenv`.
+    pub dotenv_path: PathBuf,
+    /// Reference file the variables are compared against, such as
+    /// `.env.example`, when envelope was started with `--compare`.
+    pub compare_path: PathBuf,
+    /// Variables from `compare_path`.
+    pub compare_vars: Vec<(String, String)>,
+    /// Where each variable defined in a parsed file is defined. Variables
+    /// without an entry were inherited from the parent process.
+    pub provenance: HashMap<String, Provenance>,
+    /// Which variables the env list shows, by origin.
+    pub origin_filter: Option<OriginFilter>,
+    /// The search narrowing both lists, if one is active.
+    pub search: Option<Search>,
+    /// Whether keys go to the search query.
+    pub searching: bool,
+    /// Whether the shell whose startup is simulated is a login shell and
+    /// whether it's interactive.
+    pub shell_mode: Mode,
+    /// System configuration directory, `/etc`.
+    pub etc_dir: PathBuf,
+    /// Files the shell reads at startup in `shell_mode`, in order.
+    pub startup_chain: Vec<ChainEntry>,
+    /// Whether the startup files pane is shown.
+    pub show_chain: bool,
+    /// `PATH` after each assignment to it in the startup chain.
+    pub path_steps: Vec<PathStep>,
+    /// The step of `path_steps` the path pane shows, when stepping through
+    /// them instead of listing `path_var_dirs`.
+    pub path_step: Option<usize>,
+    /// Shell started to capture the runtime environment. When unset, it's
+    /// `$SHELL` if that speaks `dialect`, or else the dialect's usual shell.
+    pub shell_program: Option<PathBuf>,
+    /// Variables whose value in a freshly started shell disagrees with the
+    /// startup files or the current environment, from the last capture.
+    pub runtime_diff: Vec<RuntimeDiff>,
+    /// Why the last capture failed.
+    pub capture_error: Option<String>,
+    /// Whether the runtime capture pane is shown.
+    pub show_capture: bool,
+    /// Whether a capture is running on its own thread.
+    pub capturing: bool,
+    /// Where work done on other threads, like a runtime capture, reports
+    /// back. Without it, that work runs on the UI thread.
+    pub events: Option<Sender<Event>>,
+    /// Shells configured in the home directory, `dialect` first.
+    pub shells: Vec<Dialect>,
+    /// Other shells in `shells` whose config files user scope edits also go
+    /// to.
+    pub extra_targets: Vec<Dialect>,
+    /// The entry of `shells` selected in the target picker, while it's open.
+    pub picker: Option<usize>,
+    /// Problems found reading the environment, and the last failed
+    /// operation, shown in the status area.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Narrows the env list to variables of one origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginFilter {
+    /// Variables no parsed file defines.
+    Inherited,
+    /// Variables defined in this file.
+    File(PathBuf),
+}
+
+/// Where variable edits are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// The user's shell config and startup files.
+    User,
+    /// The system-wide environment file.
+    System,
+    /// The user's `environment.d` drop-ins for graphical sessions.
+    SessionUser,
+    /// The system's `/etc/environment.d` drop-ins for graphical sessions.
+    SessionSystem,
+    /// A project's dotenv file, opened with `--dotenv`.
+    Dotenv,
 }
 #[derive(Debug, PartialEq)]
 pub enum ActiveList {
@@ -60,13 +220,48 @@ impl Default for App {
         env_list_state.select(Some(0));
         let mut path_list_state = ratatui::widgets::ListState::default();
         path_list_state.select(Some(0));
-        let env_vars = env::vars().collect();
+        let env_vars: Vec<(OsString, OsString)> = env::vars_os().collect();
         let mut path_var_dirs = Vec::new();
-        let shell = get_shell_config().unwrap();
-        let config_path = get_config_path();
+        let mut diagnostics = Vec::new();
+        let home = var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from);
+        let detected = shell::detect(&shell::candidates());
+        let mut shells = home.as_deref().map(shell::configured).unwrap_or_default();
+        let dialect = match &detected {
+            Some((_, dialect)) => *dialect,
+            None => {
+                let dialect = shells.first().copied().unwrap_or(Dialect::Posix);
+                diagnostics.push(Diagnostic::ShellGuessed(dialect));
+                dialect
+            }
+        };
+        shells.retain(|&configured| configured != dialect);
+        shells.insert(0, dialect);
+        let shell = dialect.config_file().to_owned();
+        // Without a home directory there's no config file, and edits to the
+        // user scope are refused.
+        let config_path = home
+            .as_ref()
+            .map(|home| home.join(&shell))
+            .unwrap_or_default();
+        let shell_env_vars = match &home {
+            Some(_) => {
+                let (vars, diagnostic) = get_shell_vars(&config_path, dialect);
+                diagnostics.extend(diagnostic);
+                vars
+            }
+            None => {
+                diagnostics.push(Diagnostic::NoHome);
+                HashMap::new()
+            }
+        };
+        let expanding = expanding_vars(&config_path, dialect);
         let key = "PATH";
         let path_var = var_os(key);
-        let shell_env_vars = get_shell_vars();
+        let session_dirs = home.as_deref().map(environment_d::dirs).unwrap_or_default();
+        let system_env_path = PathBuf::from(Dialect::Environment.config_file());
+        let system_env_vars = environment_file_vars(&system_env_path);
 
         match path_var {
             Some(paths) => {
@@ -74,15 +269,19 @@ impl Default for App {
                     path_var_dirs.push(path);
                 }
             }
-            None => println!("{key} not set in current environment."),
+            None => diagnostics.push(Diagnostic::NoPath),
         }
         App {
+            saved_env_vars: env_vars.clone(),
+            saved_path_var_dirs: path_var_dirs.clone(),
+            inherited_path_dirs: path_var_dirs.clone(),
             env_vars,
             path_var_dirs,
             selected_env_var: 0,
             selected_env_key: String::new(),
             selected_path_dir: 0,
             editing: false,
+            editing_bytes: false,
             env_var_value: String::new(),
             env_var_key: String::new(),
             env_list_state,
@@ -93,25 +292,1305 @@ impl Default for App {
             activated_list: ActiveList::EnvList,
             path_var_value: String::new(),
             path_var_edit: String::new(),
+            path_insert: None,
             shell,
+            dialect,
             shell_env_vars,
+            expanding,
             config_path,
             overwrite: false,
+            pending: PendingChanges::default(),
+            pending_diff: String::new(),
+            history: History::default(),
+            show_history: false,
+            name_error: None,
+            confirm_unset: None,
+            confirm_quit: false,
+            rename_from: None,
+            rename_preview: Vec::new(),
+            scope: Scope::User,
+            system_env_path,
+            system_env_vars,
+            confirm_install: Vec::new(),
+            install_commands: Vec::new(),
+            session_dirs,
+            session_env: HashMap::new(),
+            dotenv_path: PathBuf::new(),
+            compare_path: PathBuf::new(),
+            compare_vars: Vec::new(),
+            provenance: HashMap::new(),
+            origin_filter: None,
+            search: None,
+            searching: false,
+            shell_mode: Mode::default(),
+            etc_dir: PathBuf::from("/etc"),
+            startup_chain: Vec::new(),
+            show_chain: false,
+            path_steps: Vec::new(),
+            path_step: None,
+            shell_program: detected.map(|(program, _)| program),
+            runtime_diff: Vec::new(),
+            capture_error: None,
+            show_capture: false,
+            capturing: false,
+            events: None,
+            shells,
+            extra_targets: Vec::new(),
+            picker: None,
+            diagnostics,
         }
     }
 }
 impl App {
     pub fn new() -> Self {
-        App::default()
+        let mut app = App::default();
+        app.refresh_session_env();
+        app.refresh_provenance();
+        app.refresh_case_collisions();
+        app
+    }
+
+    /// Opens a dotenv file instead of the environment. Its variables fill
+    /// the list, interpolated against the environment, and edits are saved
+    /// back to it. A missing file is created on the first apply.
+    pub fn with_dotenv(path: PathBuf) -> AppResult<Self> {
+        let mut app = App::new();
+        app.open_dotenv(path)?;
+        Ok(app)
+    }
+
+    /// Replaces the variables with those of a dotenv file, interpolated
+    /// against the current ones, and switches to editing it.
+    pub fn open_dotenv(&mut self, path: PathBuf) -> AppResult<()> {
+        let base = utf8_vars(&self.env_vars);
+        let vars = dotenv::resolve(&dotenv::read(&path)?, &|name: &str| {
+            base.get(name).map(|value| value.to_string())
+        });
+        self.path_var_dirs = vars
+            .iter()
+            .find(|(key, _)| key == "PATH")
+            .map(|(_, value)| split_paths(value).collect())
+            .unwrap_or_default();
+        self.env_vars = vars
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        self.saved_env_vars = self.env_vars.clone();
+        self.saved_path_var_dirs = self.path_var_dirs.clone();
+        self.inherited_path_dirs = self.path_var_dirs.clone();
+        self.scope = Scope::Dotenv;
+        self.dotenv_path = path;
+        self.refresh_provenance();
+        self.refresh_case_collisions();
+        Ok(())
+    }
+
+    /// Loads a dotenv file to compare the variables against, interpolated
+    /// against them.
+    pub fn compare_with(&mut self, path: PathBuf) -> AppResult<()> {
+        let base = utf8_vars(&self.env_vars);
+        self.compare_vars = dotenv::resolve(&dotenv::read(&path)?, &|name: &str| {
+            base.get(name).map(|value| value.to_string())
+        });
+        self.compare_path = path;
+        Ok(())
+    }
+
+    /// How the variables differ from the reference file, if there is one.
+    pub fn comparison(&self) -> Option<Comparison> {
+        if self.compare_path.as_os_str().is_empty() {
+            return None;
+        }
+        let vars: Vec<(String, String)> = self
+            .env_vars
+            .iter()
+            .map(|(key, value)| (escape(key), escape(value)))
+            .collect();
+        Some(compare(&vars, &self.compare_vars))
+    }
+
+    /// Adds the keys the reference file has and the variables lack, with
+    /// the reference's values, staging their definitions.
+    pub fn add_missing_keys(&mut self) {
+        let Some(comparison) = self.comparison() else {
+            return;
+        };
+        if comparison.missing.is_empty() {
+            return;
+        }
+        let before = self.snapshot();
+        let count = comparison.missing.len();
+        for (name, value) in comparison.missing {
+            self.env_vars
+                .push((name.clone().into(), value.clone().into()));
+            let expand = self.expanding.contains(&name);
+            self.stage(ConfigEdit::SetVar {
+                name,
+                value,
+                expand,
+            });
+        }
+        let from = self.compare_path.display().to_string();
+        self.record(Operation::AddMissing { count, from }, before, Vec::new());
+    }
+
+    pub fn selected_value(&self) -> &OsStr {
+        self.env_vars
+            .get(self.selected_env_var)
+            .map_or(OsStr::new(""), |(_, value)| value)
+    }
+
+    /// The value typed into an edit buffer, unescaped if the value being
+    /// edited wasn't valid UTF-8.
+    pub fn edited_value(&self, text: &str) -> OsString {
+        if self.editing_bytes {
+            unescape(text)
+        } else {
+            text.into()
+        }
+    }
+
+    /// Where the variable `name` is defined, if in a parsed file.
+    pub fn origin_of(&self, name: &OsStr) -> Option<&Provenance> {
+        name.to_str().and_then(|name| self.provenance.get(name))
+    }
+
+    /// Stages `edit` against the file of the current scope without writing it.
+    pub fn stage(&mut self, edit: ConfigEdit) {
+        let path = match &edit {
+            ConfigEdit::SetVar { name, .. } => self.target_path_for(name),
+            _ => self.target_path(),
+        };
+        self.stage_in(path, edit.clone());
+        if self.scope == Scope::User {
+            self.stage_in_extra_targets(edit);
+        }
+    }
+
+    /// Stages a `PATH` edit against `path_config` and, outside a dotenv
+    /// file, the other target shells' config files.
+    pub fn stage_path(&mut self, edit: ConfigEdit) {
+        self.stage_in(self.path_config(), edit.clone());
+        if self.scope != Scope::Dotenv {
+            self.stage_in_extra_targets(edit);
+        }
+    }
+
+    fn stage_in_extra_targets(&mut self, edit: ConfigEdit) {
+        for path in self.extra_target_paths() {
+            self.stage_in(path, edit.clone());
+        }
+    }
+
+    /// The config files of the other target shells.
+    pub fn extra_target_paths(&self) -> Vec<PathBuf> {
+        let Some(home) = self.home() else {
+            return Vec::new();
+        };
+        self.extra_targets
+            .iter()
+            .map(|dialect| home.join(dialect.config_file()))
+            .collect()
+    }
+
+    /// The config file of each configured shell.
+    pub fn shell_configs(&self) -> Vec<(Dialect, PathBuf)> {
+        let home = self.home().unwrap_or_default();
+        self.shells
+            .iter()
+            .map(|&dialect| (dialect, home.join(dialect.config_file())))
+            .collect()
+    }
+
+    /// Stages `edit` against a particular file, unless it's read-only.
+    pub fn stage_in(&mut self, path: PathBuf, edit: ConfigEdit) {
+        if self.is_read_only(&path) {
+            self.report(Error::ReadOnly(path));
+            return;
+        }
+        let dialect = self.dialect_for(&path);
+        if let Err(error) = edit.check(dialect) {
+            self.report(error);
+            return;
+        }
+        self.pending.stage(path, dialect, edit);
+        self.refresh_pending_diff();
+    }
+
+    /// Whether edits to `path` are refused: it's the config file when that
+    /// couldn't be read, or there's no file because `$HOME` isn't set.
+    pub fn is_read_only(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty()
+            || (path == self.config_path && self.diagnostics.iter().any(Diagnostic::read_only))
+    }
+
+    /// Replaces the case collision warnings with those for the current
+    /// variables.
+    pub fn refresh_case_collisions(&mut self) {
+        self.diagnostics
+            .retain(|diagnostic| !matches!(diagnostic, Diagnostic::CaseCollision(_)));
+        self.diagnostics.extend(
+            case_collisions(&self.env_vars)
+                .into_iter()
+                .map(Diagnostic::CaseCollision),
+        );
+    }
+
+    /// Switches whether references to other variables in the selected
+    /// variable's value expand when it's next written.
+    pub fn toggle_expansion(&mut self) {
+        let Some((name, _)) = self.env_vars.get(self.selected_env_var) else {
+            return;
+        };
+        let name = escape(name);
+        if !self.expanding.remove(&name) {
+            self.expanding.insert(name);
+        }
+    }
+
+    /// Shows `error` in the status area in place of the last failure.
+    pub fn report(&mut self, error: Error) {
+        self.diagnostics
+            .retain(|diagnostic| !matches!(diagnostic, Diagnostic::Failed(_)));
+        self.diagnostics.push(Diagnostic::Failed(error.to_string()));
+    }
+
+    /// The file new definitions go to in the current scope.
+    pub fn target_path(&self) -> PathBuf {
+        match self.scope {
+            Scope::User => self.config_path.clone(),
+            Scope::System => self.system_env_path.clone(),
+            Scope::SessionUser | Scope::SessionSystem => self
+                .session_dir()
+                .map(|dir| dir.join(ENVELOPE_DROP_IN))
+                .unwrap_or_default(),
+            Scope::Dotenv => self.dotenv_path.clone(),
+        }
+    }
+
+    /// The file `PATH` edits go to: the dotenv file being edited, otherwise
+    /// the user's shell config whatever the scope.
+    pub fn path_config(&self) -> PathBuf {
+        match self.scope {
+            Scope::Dotenv => self.dotenv_path.clone(),
+            _ => self.config_path.clone(),
+        }
+    }
+
+    /// The file setting `name` should change: in a session scope, the
+    /// drop-in that currently wins for it if it's in the scope's directory.
+    pub fn target_path_for(&self, name: &str) -> PathBuf {
+        let winner = self.session_env.get(name).map(|resolved| &resolved.path);
+        match (winner, self.session_dir()) {
+            (Some(winner), Some(dir)) if winner.parent() == Some(dir.as_path()) => winner.clone(),
+            _ => self.target_path(),
+        }
+    }
+
+    /// Every file that may define variables in the current scope.
+    pub fn target_files(&self) -> Vec<PathBuf> {
+        match self.scope {
+            Scope::User => self.rc_files(),
+            Scope::System => vec![self.system_env_path.clone()],
+            Scope::SessionUser | Scope::SessionSystem => self
+                .session_dir()
+                .and_then(|dir| environment_d::discover(&[dir]).ok())
+                .unwrap_or_default(),
+            Scope::Dotenv => vec![self.dotenv_path.clone()],
+        }
+    }
+
+    /// The `environment.d` directory edited in a session scope.
+    fn session_dir(&self) -> Option<PathBuf> {
+        let index = match self.scope {
+            Scope::SessionUser => 0,
+            Scope::SessionSystem => 1,
+            Scope::User | Scope::System | Scope::Dotenv => return None,
+        };
+        self.session_dirs.get(index).cloned()
+    }
+
+    /// The syntax of `path`.
+    pub fn dialect_for(&self, path: &Path) -> Dialect {
+        if path == self.system_env_path {
+            Dialect::Environment
+        } else if self.scope == Scope::Dotenv && path == self.dotenv_path {
+            Dialect::Dotenv
+        } else if Dialect::from_config_name(&path.to_string_lossy()) == Dialect::EnvironmentD {
+            Dialect::EnvironmentD
+        } else {
+            self.shell_files()
+                .into_iter()
+                .find(|(file, _)| file == path)
+                .map_or(self.dialect, |(_, dialect)| dialect)
+        }
+    }
+
+    /// Makes `program` the user's shell, as if it had been detected.
+    pub fn use_shell(&mut self, program: PathBuf) -> AppResult<()> {
+        let Some(dialect) = Dialect::from_shell(&program.to_string_lossy()) else {
+            return Err(Error::UnsupportedShell(program));
+        };
+        let home = self.home();
+        self.dialect = dialect;
+        self.shell = dialect.config_file().to_owned();
+        self.diagnostics.retain(|diagnostic| {
+            matches!(
+                diagnostic,
+                Diagnostic::NoHome | Diagnostic::NoPath | Diagnostic::CaseCollision(_)
+            )
+        });
+        if let Some(home) = home {
+            self.config_path = home.join(&self.shell);
+            let (vars, diagnostic) = get_shell_vars(&self.config_path, dialect);
+            self.shell_env_vars = vars;
+            self.expanding = expanding_vars(&self.config_path, dialect);
+            self.diagnostics.extend(diagnostic);
+        }
+        self.shell_program = Some(program);
+        self.shells.retain(|&shell| shell != dialect);
+        self.shells.insert(0, dialect);
+        self.extra_targets.retain(|&shell| shell != dialect);
+        self.refresh_provenance();
+        Ok(())
+    }
+
+    /// Opens or closes the target picker.
+    pub fn toggle_picker(&mut self) {
+        self.picker = match self.picker {
+            Some(_) => None,
+            None => Some(0),
+        };
+    }
+
+    /// Moves the target picker's selection by `step` entries.
+    pub fn move_picker(&mut self, step: isize) {
+        if let Some(selected) = self.picker {
+            self.picker = Some(
+                selected
+                    .saturating_add_signed(step)
+                    .min(self.shells.len().saturating_sub(1)),
+            );
+        }
+    }
+
+    /// Adds the shell selected in the picker to the targets of user scope
+    /// edits, or removes it. The user's own shell is always a target.
+    pub fn toggle_picked_target(&mut self) {
+        let Some(&dialect) = self.picker.and_then(|selected| self.shells.get(selected)) else {
+            return;
+        };
+        if dialect == self.dialect {
+            return;
+        }
+        match self
+            .extra_targets
+            .iter()
+            .position(|&shell| shell == dialect)
+        {
+            Some(index) => {
+                self.extra_targets.remove(index);
+            }
+            None => self.extra_targets.push(dialect),
+        }
+    }
+
+    pub fn toggle_scope(&mut self) {
+        self.scope = match self.scope {
+            Scope::User => Scope::System,
+            Scope::System => Scope::SessionUser,
+            Scope::SessionUser => Scope::SessionSystem,
+            Scope::SessionSystem => Scope::User,
+            // A dotenv file is the only thing open.
+            Scope::Dotenv => Scope::Dotenv,
+        };
+    }
+
+    /// Re-reads the `environment.d` drop-ins, expanding references against
+    /// the environment envelope was started with.
+    pub fn refresh_session_env(&mut self) {
+        let base = utf8_vars(&self.saved_env_vars);
+        self.session_env = environment_d::discover(&self.session_dirs)
+            .and_then(|files| {
+                environment_d::resolve(&files, &|name: &str| {
+                    base.get(name).map(|value| value.to_string())
+                })
+            })
+            .unwrap_or_default();
+    }
+
+    /// Files that define the listed variables, in the order they're read.
+    pub fn provenance_files(&self) -> Vec<(PathBuf, Dialect)> {
+        if self.scope == Scope::Dotenv {
+            return vec![(self.dotenv_path.clone(), Dialect::Dotenv)];
+        }
+        let mut files = vec![(self.system_env_path.clone(), Dialect::Environment)];
+        files.extend(
+            self.startup_chain
+                .iter()
+                .map(|entry| (entry.path.clone(), self.dialect)),
+        );
+        files
+    }
+
+    /// Re-reads the startup chain and where each variable is defined.
+    pub fn refresh_provenance(&mut self) {
+        self.startup_chain = match self.home() {
+            Some(home) => startup::chain(self.dialect, self.shell_mode, &home, &self.etc_dir),
+            None => Vec::new(),
+        };
+        self.provenance = provenance::trace(&self.provenance_files());
+        let home = self.home().map(|home| home.to_string_lossy().into_owned());
+        let base = utf8_vars(&self.saved_env_vars);
+        self.path_steps =
+            path_evolution::replay(&self.provenance_files(), &|name: &str| match name {
+                "HOME" => home.clone(),
+                _ => base.get(name).map(|value| value.to_string()),
+            });
+        self.path_step = self
+            .path_step
+            .map(|step| step.min(self.path_steps.len().saturating_sub(1)));
+        self.sync_env_list_state();
+    }
+
+    /// Switches the path pane between the current `PATH` and stepping
+    /// through how the startup chain builds it, starting from the end.
+    pub fn toggle_path_steps(&mut self) {
+        self.path_step = match self.path_step {
+            Some(_) => None,
+            None => Some(self.path_steps.len().saturating_sub(1)),
+        };
+    }
+
+    /// Shows the step `step` places later in the `PATH` replay.
+    pub fn move_path_step(&mut self, step: isize) {
+        if let Some(current) = self.path_step {
+            self.path_step = Some(
+                current
+                    .saturating_add_signed(step)
+                    .min(self.path_steps.len().saturating_sub(1)),
+            );
+        }
+    }
+
+    /// Simulates the next combination of login and interactive shell.
+    pub fn cycle_shell_mode(&mut self) {
+        self.shell_mode = self.shell_mode.next();
+        self.refresh_provenance();
+    }
+
+    /// The shell to start to capture the runtime environment.
+    pub fn capture_program(&self) -> Option<PathBuf> {
+        if let Some(program) = &self.shell_program {
+            return Some(program.clone());
+        }
+        match var_os("SHELL").map(PathBuf::from) {
+            Some(shell) if Dialect::from_shell(&shell.to_string_lossy()) == Some(self.dialect) => {
+                Some(shell)
+            }
+            _ => capture::default_program(self.dialect).map(PathBuf::from),
+        }
+    }
+
+    /// Starts the shell in `shell_mode` from a clean environment and shows
+    /// the capture pane. The shell runs on its own thread, which reports
+    /// back through `events` for `finish_capture`.
+    pub fn capture_runtime(&mut self) {
+        self.show_capture = true;
+        if self.capturing {
+            return;
+        }
+        let (Some(program), Some(home)) = (self.capture_program(), self.home()) else {
+            self.capture_error = Some(format!("No shell to start for {:?}", self.dialect));
+            return;
+        };
+        let command = capture::command(
+            &program.to_string_lossy(),
+            self.dialect,
+            self.shell_mode,
+            &home,
+        );
+        let run = move || {
+            capture::capture(command, capture::TIMEOUT)
+                .map_err(|e| format!("Couldn't run {}: {}", program.display(), e))
+        };
+        match self.events.clone() {
+            Some(events) => {
+                self.capturing = true;
+                thread::spawn(move || events.send(Event::Captured(run())));
+            }
+            None => self.finish_capture(run()),
+        }
+    }
+
+    /// Compares the environment a captured shell ended up with against the
+    /// startup files and the current environment.
+    pub fn finish_capture(&mut self, result: Result<Vec<(String, String)>, String>) {
+        self.capturing = false;
+        match result {
+            Ok(runtime) => {
+                let declared: HashSet<String> = self.provenance.keys().cloned().collect();
+                let current: Vec<(String, String)> = env::vars_os()
+                    .map(|(key, value)| (escape(&key), escape(&value)))
+                    .collect();
+                self.runtime_diff = capture::diff(&runtime, &declared, &current);
+                self.capture_error = None;
+            }
+            Err(error) => {
+                self.runtime_diff.clear();
+                self.capture_error = Some(error);
+            }
+        }
+    }
+
+    /// Indices into `env_vars` of the variables the env list shows.
+    pub fn visible_env_vars(&self) -> Vec<usize> {
+        (0..self.env_vars.len())
+            .filter(|&i| {
+                let origin = self.origin_of(&self.env_vars[i].0);
+                match (&self.origin_filter, origin) {
+                    (None, _) => true,
+                    (Some(OriginFilter::Inherited), origin) => origin.is_none(),
+                    (Some(OriginFilter::File(path)), Some(origin)) => &origin.path == path,
+                    (Some(OriginFilter::File(_)), None) => false,
+                }
+            })
+            .filter(|&i| {
+                let Some(search) = &self.search else {
+                    return true;
+                };
+                let (key, value) = &self.env_vars[i];
+                search.find(&display(key)).is_some()
+                    || (search.searches_values() && search.find(&display(value)).is_some())
+            })
+            .collect()
+    }
+
+    /// Indices into `path_var_dirs` of the directories the path list shows.
+    pub fn visible_path_dirs(&self) -> Vec<usize> {
+        (0..self.path_var_dirs.len())
+            .filter(|&i| {
+                self.search.as_ref().is_none_or(|search| {
+                    search
+                        .find(&display(self.path_var_dirs[i].as_os_str()))
+                        .is_some()
+                })
+            })
+            .collect()
+    }
+
+    /// Opens the search query for typing, keeping the current one.
+    pub fn start_search(&mut self) {
+        self.search.get_or_insert_with(Search::default);
+        self.searching = true;
+    }
+
+    /// Changes the search with `change` and narrows both lists to match.
+    pub fn update_search(&mut self, change: impl FnOnce(&mut Search)) {
+        if let Some(search) = &mut self.search {
+            change(search);
+        }
+        self.sync_env_list_state();
+        self.sync_path_list_state();
+    }
+
+    /// Drops the search, showing everything again.
+    pub fn clear_search(&mut self) {
+        self.search = None;
+        self.searching = false;
+        self.sync_env_list_state();
+        self.sync_path_list_state();
+    }
+
+    /// Moves the selection in the active list to the next search hit, or
+    /// the previous one with a negative `step`, wrapping around the ends.
+    pub fn jump_to_hit(&mut self, step: isize) {
+        if self.search.is_none() {
+            return;
+        }
+        let (visible, selected) = match self.activated_list {
+            ActiveList::EnvList => (self.visible_env_vars(), self.selected_env_var),
+            ActiveList::PathList => (self.visible_path_dirs(), self.selected_path_dir),
+        };
+        let Some(position) = visible.iter().position(|&i| i == selected) else {
+            return;
+        };
+        let index = visible[(position as isize + step).rem_euclid(visible.len() as isize) as usize];
+        match self.activated_list {
+            ActiveList::EnvList => {
+                self.selected_env_var = index;
+                self.sync_env_list_state();
+            }
+            ActiveList::PathList => {
+                self.selected_path_dir = index;
+                self.sync_path_list_state();
+            }
+        }
+    }
+
+    /// Steps the origin filter through every file that defines a listed
+    /// variable, then inherited variables, then back to showing all.
+    pub fn cycle_origin_filter(&mut self) {
+        let mut filters: Vec<OriginFilter> = Vec::new();
+        for (path, _) in self.provenance_files() {
+            let defines = self.env_vars.iter().any(|(key, _)| {
+                self.origin_of(key)
+                    .is_some_and(|origin| origin.path == path)
+            });
+            if defines && !filters.contains(&OriginFilter::File(path.clone())) {
+                filters.push(OriginFilter::File(path));
+            }
+        }
+        filters.push(OriginFilter::Inherited);
+        self.origin_filter = match &self.origin_filter {
+            None => filters.into_iter().next(),
+            Some(current) => filters
+                .iter()
+                .position(|filter| filter == current)
+                .and_then(|i| filters.get(i + 1).cloned()),
+        };
+        self.sync_env_list_state();
+    }
+
+    /// Moves the env list selection by `step` shown variables.
+    pub fn move_env_selection(&mut self, step: isize) {
+        let visible = self.visible_env_vars();
+        let Some(position) = visible.iter().position(|&i| i == self.selected_env_var) else {
+            return;
+        };
+        if let Some(&index) = position
+            .checked_add_signed(step)
+            .and_then(|position| visible.get(position))
+        {
+            self.selected_env_var = index;
+            self.sync_env_list_state();
+        }
+    }
+
+    /// Moves the path list selection by `step` shown directories.
+    pub fn move_path_selection(&mut self, step: isize) {
+        let visible = self.visible_path_dirs();
+        let Some(position) = visible.iter().position(|&i| i == self.selected_path_dir) else {
+            return;
+        };
+        if let Some(&index) = position
+            .checked_add_signed(step)
+            .and_then(|position| visible.get(position))
+        {
+            self.selected_path_dir = index;
+            self.sync_path_list_state();
+        }
+    }
+
+    /// Opens an empty directory for entering, to be inserted before the
+    /// selected one, or after it with `after`.
+    pub fn start_path_insert(&mut self, after: bool) {
+        let index = match self.path_list_state.selected() {
+            Some(_) => self.selected_path_dir + usize::from(after),
+            None => self.path_var_dirs.len(),
+        };
+        self.path_insert = Some(index.min(self.path_var_dirs.len()));
+        self.path_var_edit.clear();
+        self.editing = true;
+        self.editing_bytes = false;
+    }
+
+    /// Inserts the directory entered since `start_path_insert` and stages
+    /// the new `PATH`. Nothing is inserted if it was left empty.
+    pub fn insert_path_dir(&mut self) {
+        self.editing = false;
+        let Some(index) = self.path_insert.take() else {
+            return;
+        };
+        if self.path_var_edit.is_empty() {
+            return;
+        }
+        let before = self.snapshot();
+        let dir = self.edited_value(&self.path_var_edit);
+        self.path_var_dirs.insert(index, dir.into());
+        self.selected_path_dir = index;
+        let operation = Operation::InsertPath {
+            dir: self.path_var_edit.clone(),
+            position: index + 1,
+        };
+        self.stage_path_dirs(operation, before);
+    }
+
+    /// Removes the selected directory and stages the new `PATH`.
+    pub fn remove_path_dir(&mut self) {
+        if self.selected_path_dir >= self.path_var_dirs.len() {
+            return;
+        }
+        let before = self.snapshot();
+        let dir = self.path_var_dirs.remove(self.selected_path_dir);
+        self.selected_path_dir = self
+            .selected_path_dir
+            .min(self.path_var_dirs.len().saturating_sub(1));
+        let dir = escape(dir.as_os_str());
+        self.stage_path_dirs(Operation::RemovePath { dir }, before);
+    }
+
+    /// Moves the selected directory `step` places later in `PATH`, or
+    /// earlier with a negative `step`, and stages the new `PATH`.
+    pub fn move_path_dir(&mut self, step: isize) {
+        let from = self.selected_path_dir;
+        let Some(to) = from
+            .checked_add_signed(step)
+            .filter(|&to| to < self.path_var_dirs.len() && from < self.path_var_dirs.len())
+        else {
+            return;
+        };
+        let before = self.snapshot();
+        let dir = self.path_var_dirs.remove(from);
+        self.path_var_dirs.insert(to, dir);
+        self.selected_path_dir = to;
+        let operation = Operation::MovePath {
+            dir: escape(self.path_var_dirs[to].as_os_str()),
+            position: to + 1,
+        };
+        self.stage_path_dirs(operation, before);
+    }
+
+    /// Drops every directory that's already earlier in `PATH`, which never
+    /// wins a lookup, and stages the new `PATH`.
+    pub fn dedupe_path(&mut self) {
+        let before = self.snapshot();
+        let selected = self.path_var_dirs.get(self.selected_path_dir).cloned();
+        let mut seen = HashSet::new();
+        self.path_var_dirs.retain(|dir| seen.insert(dir.clone()));
+        let removed = before.path_var_dirs.len() - self.path_var_dirs.len();
+        if removed == 0 {
+            return;
+        }
+        self.selected_path_dir = selected
+            .and_then(|selected| self.path_var_dirs.iter().position(|dir| *dir == selected))
+            .unwrap_or_default();
+        self.stage_path_dirs(Operation::DedupePath { removed }, before);
+    }
+
+    /// Stages `PATH` as `path_var_dirs` now has it and records `operation`
+    /// as the change from `before`.
+    pub fn stage_path_dirs(&mut self, operation: Operation, before: Snapshot) {
+        let dirs: Option<Vec<String>> = self
+            .path_var_dirs
+            .iter()
+            .map(|dir| dir.to_str().map(str::to_owned))
+            .collect();
+        match dirs {
+            Some(dirs) => {
+                let inherited = self
+                    .inherited_path_dirs
+                    .iter()
+                    .map(|dir| dir.to_string_lossy().into_owned())
+                    .collect();
+                self.stage_path(ConfigEdit::SetPath { dirs, inherited });
+            }
+            None => self.report(Error::NotUtf8("PATH".to_owned())),
+        }
+        self.sync_path_list_state();
+        self.record(operation, before, Vec::new());
+    }
+
+    /// Points the env list at the selected variable, moving the selection
+    /// to the nearest shown one if it's filtered out. Nothing is selected
+    /// when nothing is shown.
+    fn sync_env_list_state(&mut self) {
+        let visible = self.visible_env_vars();
+        let position = visible
+            .iter()
+            .position(|&i| i >= self.selected_env_var)
+            .unwrap_or(visible.len().saturating_sub(1));
+        if let Some(&index) = visible.get(position) {
+            self.selected_env_var = index;
+        }
+        self.env_list_state
+            .select((!visible.is_empty()).then_some(position));
+    }
+
+    /// Points the path list at the selected directory the way
+    /// `sync_env_list_state` does for the env list.
+    fn sync_path_list_state(&mut self) {
+        let visible = self.visible_path_dirs();
+        let position = visible
+            .iter()
+            .position(|&i| i >= self.selected_path_dir)
+            .unwrap_or(visible.len().saturating_sub(1));
+        if let Some(&index) = visible.get(position) {
+            self.selected_path_dir = index;
+            self.path_var_value = display(self.path_var_dirs[index].as_os_str());
+        }
+        self.path_list_state
+            .select((!visible.is_empty()).then_some(position));
+    }
+
+    /// The home directory the config file is in.
+    fn home(&self) -> Option<PathBuf> {
+        let depth = Path::new(self.dialect.config_file()).components().count();
+        self.config_path
+            .ancestors()
+            .nth(depth)
+            .map(Path::to_path_buf)
+    }
+
+    /// Every startup file of the shell and the other target shells, with
+    /// the dialect it's read in. A file shared by two shells is listed once,
+    /// for the first.
+    fn shell_files(&self) -> Vec<(PathBuf, Dialect)> {
+        let Some(home) = self.home() else {
+            return Vec::new();
+        };
+        let mut files: Vec<(PathBuf, Dialect)> = Vec::new();
+        for dialect in iter::once(self.dialect).chain(self.extra_targets.iter().copied()) {
+            for file in dialect.startup_files() {
+                let path = home.join(file);
+                if !files.iter().any(|(known, _)| known == &path) {
+                    files.push((path, dialect));
+                }
+            }
+        }
+        files
+    }
+
+    /// The target shells' startup files that exist, plus the config file.
+    pub fn rc_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self
+            .shell_files()
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| path.is_file())
+            .collect();
+        if !files.contains(&self.config_path) {
+            files.push(self.config_path.clone());
+        }
+        files
+    }
+
+    /// Deletes the selected variable and stages the removal of its
+    /// definitions from every startup file. A variable none of them define
+    /// is inherited, so the user is asked whether to unset it explicitly.
+    pub fn delete_selected_var(&mut self) -> AppResult<()> {
+        let Some((name, _)) = self.env_vars.get(self.selected_env_var) else {
+            return Ok(());
+        };
+        let name = escape(name);
+        let mut defining = Vec::new();
+        for path in self.target_files() {
+            if ConfigFile::load(&path, self.dialect_for(&path))?.defines(&name) {
+                defining.push(path);
+            }
+        }
+        if defining.is_empty() {
+            self.confirm_unset = Some(name);
+            return Ok(());
+        }
+        let before = self.snapshot();
+        for path in defining {
+            self.stage_in(path, ConfigEdit::RemoveVar(name.clone()));
+        }
+        self.remove_selected_var();
+        self.record(Operation::DeleteVar { name }, before, Vec::new());
+        Ok(())
+    }
+
+    /// Answers the prompt raised by `delete_selected_var`. The variable is
+    /// removed from the list either way; `unset` also stages an explicit
+    /// unset in the config file.
+    pub fn confirm_delete(&mut self, unset: bool) {
+        let Some(name) = self.confirm_unset.take() else {
+            return;
+        };
+        let before = self.snapshot();
+        if unset {
+            self.stage(ConfigEdit::UnsetVar(name.clone()));
+        }
+        self.remove_selected_var();
+        self.record(Operation::DeleteVar { name }, before, Vec::new());
+    }
+
+    /// Starts renaming the selected variable, listing every line of the
+    /// startup files that mentions it.
+    pub fn start_rename(&mut self) -> AppResult<()> {
+        let Some((name, _)) = self.env_vars.get(self.selected_env_var) else {
+            return Ok(());
+        };
+        let name = escape(name);
+        self.rename_preview.clear();
+        for path in self.target_files() {
+            let config = ConfigFile::load(&path, self.dialect_for(&path))?;
+            let mut lines: Vec<usize> = config
+                .name_occurrences(&name)
+                .iter()
+                .map(|range| config.contents[..range.start].matches('\n').count())
+                .collect();
+            lines.dedup();
+            for line in lines {
+                let text = config.contents.lines().nth(line).unwrap_or_default();
+                self.rename_preview.push(format!(
+                    "{}:{}: {}",
+                    path.display(),
+                    line + 1,
+                    text.trim()
+                ));
+            }
+        }
+        self.editing = true;
+        self.env_var_key = name.clone();
+        self.rename_from = Some(name);
+        self.name_error = None;
+        Ok(())
+    }
+
+    /// Renames the variable to the name in `env_var_key`, staging the change
+    /// in every startup file that mentions it. A variable none of them
+    /// mention is moved by defining the new name and unsetting the old one.
+    pub fn rename_selected_var(&mut self) -> AppResult<()> {
+        let Some(from) = self.rename_from.clone() else {
+            return Ok(());
+        };
+        let to = self.env_var_key.clone();
+        if let Err(error) = self.validate_new_var_name(&to) {
+            self.name_error = Some(error);
+            return Ok(());
+        }
+        let before = self.snapshot();
+        let mut mentioned = false;
+        for path in self.target_files() {
+            if !ConfigFile::load(&path, self.dialect_for(&path))?
+                .name_occurrences(&from)
+                .is_empty()
+            {
+                mentioned = true;
+                self.stage_in(
+                    path,
+                    ConfigEdit::RenameVar {
+                        from: from.clone(),
+                        to: to.clone(),
+                    },
+                );
+            }
+        }
+        let Some(index) = self
+            .env_vars
+            .iter()
+            .position(|(key, _)| escape(key) == from)
+        else {
+            return Ok(());
+        };
+        if !mentioned {
+            match self.env_vars[index].1.clone().into_string() {
+                Ok(value) => {
+                    // The value is what the shell ended up with, so it's
+                    // taken literally.
+                    self.stage(ConfigEdit::SetVar {
+                        name: to.clone(),
+                        value,
+                        expand: false,
+                    });
+                    self.stage(ConfigEdit::UnsetVar(from.clone()));
+                }
+                Err(_) => self.report(Error::NotUtf8(to.clone())),
+            }
+        }
+        self.env_vars[index].0 = to.clone().into();
+        self.record(Operation::RenameVar { from, to }, before, Vec::new());
+        self.cancel_rename();
+        Ok(())
+    }
+
+    pub fn cancel_rename(&mut self) {
+        self.editing = false;
+        self.rename_from = None;
+        self.rename_preview.clear();
+        self.name_error = None;
+    }
+
+    fn remove_selected_var(&mut self) {
+        self.env_vars.remove(self.selected_env_var);
+        self.selected_env_var = self
+            .selected_env_var
+            .min(self.env_vars.len().saturating_sub(1));
+        self.sync_env_list_state();
+    }
+
+    /// Starts the new variable flow with empty name and value fields.
+    pub fn start_new_var(&mut self) {
+        self.editing = true;
+        self.activated_list = ActiveList::EnvList;
+        self.list_index = 0;
+        self.currently_editing = Some(CurrentlyEditing::EnvVarName);
+        self.env_var_key.clear();
+        self.env_var_value.clear();
+        self.name_error = None;
+    }
+
+    /// Whether `name` can be used for a new variable, or why not.
+    pub fn validate_new_var_name(&self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Enter a name".to_owned());
+        }
+        if !is_name(name) {
+            return Err(format!(
+                "{} is not a valid name: use letters, digits and underscores, not starting with a digit",
+                name
+            ));
+        }
+        if self.env_vars.iter().any(|(key, _)| key == name) {
+            return Err(format!("{} already exists in the environment", name));
+        }
+        if self.scope == Scope::System && self.system_env_vars.contains_key(name) {
+            return Err(format!(
+                "{} is already defined in {}",
+                name,
+                self.system_env_path.display()
+            ));
+        }
+        if let (Some(resolved), Some(_)) = (self.session_env.get(name), self.session_dir()) {
+            return Err(format!(
+                "{} is already defined in {}",
+                name,
+                resolved.path.display()
+            ));
+        }
+        if self.scope != Scope::Dotenv && self.shell_env_vars.contains_key(name) {
+            return Err(format!(
+                "{} is already defined in {}",
+                name,
+                self.config_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds the variable in the name and value fields to the list and stages
+    /// its definition. Leaves the flow open with `name_error` set when the
+    /// name is rejected.
+    pub fn create_new_var(&mut self) {
+        let name = self.env_var_key.clone();
+        if let Err(error) = self.validate_new_var_name(&name) {
+            self.name_error = Some(error);
+            return;
+        }
+        let before = self.snapshot();
+        let value = self.env_var_value.clone();
+        self.env_vars
+            .push((name.clone().into(), value.clone().into()));
+        self.selected_env_var = self.env_vars.len() - 1;
+        self.sync_env_list_state();
+        self.stage(ConfigEdit::SetVar {
+            name: name.clone(),
+            value: value.clone(),
+            expand: self.expanding.contains(&name),
+        });
+        self.record(Operation::CreateVar { name, value }, before, Vec::new());
+        self.cancel_new_var();
+    }
+
+    pub fn cancel_new_var(&mut self) {
+        self.editing = false;
+        self.currently_editing = None;
+        self.name_error = None;
+    }
+
+    /// Writes every staged edit to disk. If some files can't be written
+    /// without root, nothing is written and the user is asked whether to
+    /// stage copies of them instead.
+    pub fn apply_pending(&mut self) -> AppResult<()> {
+        let blocked: Vec<PathBuf> = self
+            .pending
+            .paths()
+            .into_iter()
+            .filter(|path| !is_writable(path))
+            .collect();
+        if !blocked.is_empty() {
+            self.confirm_install = blocked;
+            return Ok(());
+        }
+        let before = self.snapshot();
+        // Files written before a failed one are recorded so they can be
+        // undone, and their edits aren't applied again.
+        let (changes, result) = match self.pending.apply() {
+            Ok(changes) => (changes, Ok(())),
+            Err(partial) => (partial.written, Err(partial.error)),
+        };
+        for change in &changes {
+            let vars = self
+                .dialect_for(&change.path)
+                .parse(&change.after)
+                .map(|script| script.exported_vars().into_iter().collect());
+            if let Ok(vars) = vars {
+                if change.path == self.config_path {
+                    self.shell_env_vars = vars;
+                    self.diagnostics.retain(|diagnostic| {
+                        !matches!(
+                            diagnostic,
+                            Diagnostic::ConfigMissing(_) | Diagnostic::ConfigUnparsable { .. }
+                        )
+                    });
+                } else if change.path == self.system_env_path {
+                    self.system_env_vars = vars;
+                }
+            }
+        }
+        self.saved_env_vars = self.env_vars.clone();
+        self.saved_path_var_dirs = self.path_var_dirs.clone();
+        self.refresh_pending_diff();
+        self.refresh_session_env();
+        self.refresh_provenance();
+        if before != self.snapshot() {
+            let files = changes.len();
+            self.record(Operation::Apply { files }, before, changes);
+        }
+        Ok(result?)
+    }
+
+    /// Answers the prompt raised by `apply_pending`. When accepted, the edits
+    /// to files that need root are written to staged copies, with the
+    /// commands that install them kept in `install_commands`, and the rest
+    /// are applied.
+    pub fn confirm_staged_install(&mut self, accept: bool) -> AppResult<()> {
+        let blocked = std::mem::take(&mut self.confirm_install);
+        if !accept || blocked.is_empty() {
+            return Ok(());
+        }
+        let before = self.snapshot();
+        // The edits stay staged unless every copy is written.
+        let mut remaining = self.pending.clone();
+        let mut commands = Vec::new();
+        for path in &blocked {
+            for (_, after) in remaining.split_off_path(path).preview()? {
+                commands.push(write_staged_copy(&after.path, after.contents.as_bytes())?);
+            }
+        }
+        self.pending = remaining;
+        self.install_commands.extend(commands);
+        self.refresh_pending_diff();
+        let files = blocked.len();
+        self.record(Operation::StageInstall { files }, before, Vec::new());
+        if !self.pending.is_empty() {
+            self.apply_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Drops every staged edit and restores the lists to their last applied state.
+    pub fn discard_pending(&mut self) {
+        let before = self.snapshot();
+        let edits = self.pending.len();
+        self.pending.discard();
+        self.env_vars = self.saved_env_vars.clone();
+        self.path_var_dirs = self.saved_path_var_dirs.clone();
+        self.refresh_pending_diff();
+        if before != self.snapshot() {
+            self.record(Operation::Discard { edits }, before, Vec::new());
+        }
+    }
+
+    /// Captures the state an operation can change, for recording in history.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            env_vars: self.env_vars.clone(),
+            path_var_dirs: self.path_var_dirs.clone(),
+            shell_env_vars: self.shell_env_vars.clone(),
+            system_env_vars: self.system_env_vars.clone(),
+            pending: self.pending.clone(),
+            saved_env_vars: self.saved_env_vars.clone(),
+            saved_path_var_dirs: self.saved_path_var_dirs.clone(),
+        }
+    }
+
+    /// Records `operation` as taking the app from `before` to its current
+    /// state, writing `files` along the way. Every change to the variables
+    /// is recorded, so the case collision warnings are refreshed here.
+    pub fn record(&mut self, operation: Operation, before: Snapshot, files: Vec<FileChange>) {
+        self.refresh_case_collisions();
+        let after = self.snapshot();
+        self.history.push(HistoryEntry {
+            operation,
+            before,
+            after,
+            files,
+        });
+    }
+
+    /// Reverts the most recent operation, including any config file writes.
+    pub fn undo(&mut self) -> AppResult<()> {
+        if let Some(snapshot) = self.history.undo()? {
+            self.restore(snapshot);
+        }
+        Ok(())
     }
-    pub fn selected_value(&self) -> &str {
-        &self.env_vars[self.selected_env_var].1
+
+    /// Reapplies the most recently undone operation.
+    pub fn redo(&mut self) -> AppResult<()> {
+        if let Some(snapshot) = self.history.redo()? {
+            self.restore(snapshot);
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.env_vars = snapshot.env_vars;
+        self.refresh_case_collisions();
+        self.path_var_dirs = snapshot.path_var_dirs;
+        self.shell_env_vars = snapshot.shell_env_vars;
+        self.system_env_vars = snapshot.system_env_vars;
+        self.pending = snapshot.pending;
+        self.saved_env_vars = snapshot.saved_env_vars;
+        self.saved_path_var_dirs = snapshot.saved_path_var_dirs;
+        self.selected_env_var = self
+            .selected_env_var
+            .min(self.env_vars.len().saturating_sub(1));
+        self.selected_path_dir = self
+            .selected_path_dir
+            .min(self.path_var_dirs.len().saturating_sub(1));
+        self.sync_env_list_state();
+        self.sync_path_list_state();
+        self.editing = false;
+        self.currently_editing = None;
+        self.rename_from = None;
+        self.path_insert = None;
+        self.refresh_pending_diff();
+        self.refresh_session_env();
+        self.refresh_provenance();
+    }
+
+    fn refresh_pending_diff(&mut self) {
+        self.pending_diff = self
+            .pending
+            .diff()
+            .unwrap_or_else(|error| format!("Unable to preview changes: {}", error));
     }
 
     pub fn quit(&mut self) {
         self.running = false;
     }
 
+    /// Quits, unless there are pending changes, which are first offered to
+    /// be saved or discarded.
+    pub fn request_quit(&mut self) {
+        if self.pending.is_empty() {
+            self.quit();
+        } else {
+            self.confirm_quit = true;
+        }
+    }
+
+    /// Settles a quit that was waiting on the pending changes: they are
+    /// saved with `save`, or discarded. Quitting doesn't happen if saving
+    /// is held up.
+    pub fn quit_with_pending(&mut self, save: bool) -> AppResult<()> {
+        self.confirm_quit = false;
+        if save {
+            self.apply_pending()?;
+        } else {
+            self.discard_pending();
+        }
+        if self.pending.is_empty() {
+            self.quit();
+        }
+        Ok(())
+    }
+
     pub fn toggle_active(&mut self) {
         let active_index = self.list_index;
         match self.activated_list {
@@ -127,63 +1606,150 @@ impl App {
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub enum CurrentlyEditing {
     EnvVarValue,
     EnvVarName,
     PathVar,
 }
 
-// Reference code that may be deleted soon.
-
-fn get_shell_config() -> Result<String, Error> {
-    let home = std::env::var("HOME").expect("Couldn't get user home directory");
-    let mut home_path = std::path::PathBuf::from(home);
-    let mut shell = String::new();
-    for entry in home_path.read_dir().expect("read dir failed") {
-        let entry = entry?;
-        let file_name = entry.file_name();
-        let file_name = file_name.to_str().unwrap();
-        match file_name {
-            ".bashrc" => shell = String::from(file_name),
-            ".zshrc" => shell = String::from(file_name),
-            ".cshrc" => shell = String::from(file_name),
-            ".kshrc" => shell = String::from(file_name),
-            _ => {
-            }
-        }
-    }
-
-    Ok(shell)
+/// The variables whose name and value are valid UTF-8, for expanding
+/// references in config files.
+fn utf8_vars(vars: &[(OsString, OsString)]) -> HashMap<&str, &str> {
+    vars.iter()
+        .filter_map(|(key, value)| Some((key.to_str()?, value.to_str()?)))
+        .collect()
 }
 
-fn get_config_path() -> PathBuf {
-    let shell = get_shell_config().expect("Could not get shell config");
-    let home = std::env::var("HOME").expect("Couldn't get user home directory");
-
-    let mut home_path = PathBuf::from(home);
-    home_path.push(shell);
-    home_path
+/// Groups of names in `vars` that differ only in case, where the variables
+/// don't all have the same value. Groups are ordered by lowercase name and
+/// names within a group sorted.
+pub fn case_collisions(vars: &[(OsString, OsString)]) -> Vec<Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<(String, &OsStr)>> = BTreeMap::new();
+    for (name, value) in vars {
+        let name = escape(name);
+        groups
+            .entry(name.to_lowercase())
+            .or_default()
+            .push((name, value));
+    }
+    groups
+        .into_values()
+        .filter(|group| group.iter().any(|(_, value)| *value != group[0].1))
+        .map(|group| {
+            let mut names: Vec<String> = group.into_iter().map(|(name, _)| name).collect();
+            names.sort();
+            names
+        })
+        .collect()
 }
-pub fn get_shell_vars() -> HashMap<String, String> {
-    let mut config_map = HashMap::new();
-    let config_path = get_config_path();
 
-    let config_file_contents = read_to_string(config_path).expect("Could not read file to string");
+/// The variables whose definition in `path` references another variable.
+pub fn expanding_vars(path: &Path, dialect: Dialect) -> HashSet<String> {
+    let Some(script) = read_to_string(path)
+        .ok()
+        .and_then(|contents| dialect.parse(&contents).ok())
+    else {
+        return HashSet::new();
+    };
+    script
+        .assignments()
+        .filter(|(_, assignment)| {
+            assignment
+                .value
+                .as_ref()
+                .is_some_and(|value| !value.parameters().is_empty())
+        })
+        .map(|(_, assignment)| assignment.name.clone())
+        .collect()
+}
 
-    for mut line in config_file_contents.lines() {
-        if line.starts_with("export") {
-            let mut env_tuple = line.trim_start_matches("export ").split_once('=');
-            let (env_var_name, env_var_value) = env_tuple.unwrap();
-            config_map.insert(env_var_name.to_owned(), env_var_value.to_owned());
+/// The variables `path` exports, with the problem reading it if there
+/// was one.
+pub fn get_shell_vars(
+    path: &Path,
+    dialect: Dialect,
+) -> (HashMap<String, String>, Option<Diagnostic>) {
+    let contents = match read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return (
+                HashMap::new(),
+                Some(Diagnostic::ConfigMissing(path.to_path_buf())),
+            )
+        }
+        Err(error) => {
+            let diagnostic = Diagnostic::ConfigUnreadable {
+                path: path.to_path_buf(),
+                error: error.to_string(),
+            };
+            return (HashMap::new(), Some(diagnostic));
+        }
+    };
+    match dialect.parse(&contents) {
+        Ok(script) => (script.exported_vars().into_iter().collect(), None),
+        Err(error) => {
+            let diagnostic = Diagnostic::ConfigUnparsable {
+                path: path.to_path_buf(),
+                error: error.to_string(),
+            };
+            (HashMap::new(), Some(diagnostic))
         }
     }
+}
+/// The variables an `/etc/environment` style file sets, none if it can't be
+/// read.
+fn environment_file_vars(path: &Path) -> HashMap<String, String> {
+    read_to_string(path)
+        .ok()
+        .and_then(|contents| Dialect::Environment.parse(&contents).ok())
+        .map(|script| script.exported_vars().into_iter().collect())
+        .unwrap_or_default()
+}
 
-    config_map
+#[cfg(test)]
+impl App {
+    /// An app that knows nothing of the host, for tests. The user runs bash
+    /// with `home` as their home directory, the system files are under
+    /// `home/etc`, and the environment holds only
+    /// `PATH=/usr/bin:/bin`.
+    pub(crate) fn in_home(home: &Path) -> App {
+        let mut app = App {
+            env_vars: vec![("PATH".into(), "/usr/bin:/bin".into())],
+            path_var_dirs: vec![PathBuf::from("/usr/bin"), PathBuf::from("/bin")],
+            dialect: Dialect::Posix,
+            shell: Dialect::Posix.config_file().to_owned(),
+            shells: vec![Dialect::Posix],
+            shell_program: None,
+            config_path: home.join(Dialect::Posix.config_file()),
+            etc_dir: home.join("etc"),
+            system_env_path: home.join("etc/environment"),
+            session_dirs: vec![
+                home.join(".config/environment.d"),
+                home.join("etc/environment.d"),
+            ],
+            ..App::default()
+        };
+        app.saved_env_vars = app.env_vars.clone();
+        app.saved_path_var_dirs = app.path_var_dirs.clone();
+        app.inherited_path_dirs = app.path_var_dirs.clone();
+        let (vars, diagnostic) = get_shell_vars(&app.config_path, app.dialect);
+        app.shell_env_vars = vars;
+        app.diagnostics = diagnostic.into_iter().collect();
+        app.expanding = expanding_vars(&app.config_path, app.dialect);
+        app.system_env_vars = environment_file_vars(&app.system_env_path);
+        app.refresh_session_env();
+        app.refresh_provenance();
+        app.refresh_case_collisions();
+        app
+    }
 }
+
 #[cfg(test)]
+// The toggle tests set up an `App` the way they always have.
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
-    use ratatui::widgets::ListState;
 
     #[test]
     fn test_toggle_active_from_env_list() {
@@ -256,4 +1822,985 @@ mod tests {
         assert_eq!(app.env_list_state.selected(), Some(3));
         assert_eq!(app.path_list_state.selected(), Some(2));
     }
+
+    #[test]
+    fn test_stage_in_reports_values_environment_files_cant_hold() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+
+        app.stage_in(
+            app.system_env_path.clone(),
+            ConfigEdit::SetVar {
+                name: "A".to_owned(),
+                value: "a\nLD_PRELOAD=/evil.so".to_owned(),
+                expand: false,
+            },
+        );
+
+        assert!(app.pending.is_empty());
+        assert_eq!(
+            app.diagnostics.last(),
+            Some(&Diagnostic::Failed(
+                Error::ControlCharacters("A".to_owned()).to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_quit_asks_about_pending_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = dir.path().join(".bashrc");
+        let mut app = App::in_home(dir.path());
+        let edit = ConfigEdit::SetVar {
+            name: "EDITOR".to_owned(),
+            value: "vi".to_owned(),
+            expand: false,
+        };
+
+        app.request_quit();
+        assert!(!app.running);
+
+        app.running = true;
+        app.stage_in(config.clone(), edit.clone());
+        app.request_quit();
+        assert!(app.running && app.confirm_quit);
+
+        app.quit_with_pending(false).unwrap();
+        assert!(!app.running && !app.confirm_quit);
+        assert!(app.pending.is_empty());
+        assert!(!config.exists());
+
+        app.running = true;
+        app.stage_in(config.clone(), edit);
+        app.request_quit();
+        app.quit_with_pending(true).unwrap();
+        assert!(!app.running);
+        assert!(std::fs::read_to_string(&config)
+            .unwrap()
+            .contains("export EDITOR='vi'"));
+    }
+
+    #[test]
+    fn test_quit_waits_when_saving_fails() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.config_path = dir.path().to_owned();
+        app.stage_in(
+            app.config_path.clone(),
+            ConfigEdit::UnsetVar("PAGER".to_owned()),
+        );
+
+        app.request_quit();
+        assert!(app.quit_with_pending(true).is_err());
+
+        assert!(app.running && !app.confirm_quit);
+        assert!(!app.pending.is_empty());
+    }
+
+    #[test]
+    fn test_finish_capture_flags_runtime_only_vars() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.provenance.insert(
+            "ENVELOPE_DECLARED".to_owned(),
+            Provenance {
+                path: PathBuf::from(".bashrc"),
+                line: 1,
+                statement: "export ENVELOPE_DECLARED=1".to_owned(),
+            },
+        );
+        app.capturing = true;
+
+        app.finish_capture(Ok(vec![
+            ("ENVELOPE_COMPUTED".to_owned(), "yes".to_owned()),
+            ("SHLVL".to_owned(), "1".to_owned()),
+        ]));
+
+        assert!(!app.capturing);
+        assert_eq!(app.capture_error, None);
+        let names: Vec<&str> = app
+            .runtime_diff
+            .iter()
+            .map(|diff| diff.name.as_str())
+            .collect();
+        assert_eq!(names, ["ENVELOPE_COMPUTED", "ENVELOPE_DECLARED"]);
+        assert!(app.runtime_diff[0].runtime_only());
+        assert!(app.runtime_diff[1].missing_at_runtime());
+
+        app.finish_capture(Err("Couldn't run zsh: not found".to_owned()));
+        assert!(app.runtime_diff.is_empty());
+        assert_eq!(
+            app.capture_error.as_deref(),
+            Some("Couldn't run zsh: not found")
+        );
+    }
+
+    #[test]
+    fn test_capture_runs_off_the_ui_thread() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut app = App::in_home(dir.path());
+        // A shell that doesn't exist fails to start without running anything.
+        app.shell_program = Some(dir.path().join("no-such-shell"));
+        app.events = Some(sender);
+
+        app.capture_runtime();
+        assert!(app.show_capture && app.capturing);
+        // A second request while the first runs doesn't start another.
+        app.capture_runtime();
+
+        let event = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        let Event::Captured(result) = event else {
+            panic!("unexpected {:?}", event);
+        };
+        app.finish_capture(result);
+        assert!(!app.capturing);
+        assert!(app
+            .capture_error
+            .as_deref()
+            .is_some_and(|error| error.contains("no-such-shell")));
+        assert!(receiver.try_recv().is_err());
+
+        app.shell_program = None;
+        app.dialect = Dialect::Environment;
+        app.capture_runtime();
+        assert!(!app.capturing);
+        assert_eq!(
+            app.capture_error.as_deref(),
+            Some("No shell to start for Environment")
+        );
+    }
+
+    #[test]
+    fn test_failed_undo_leaves_state_alone() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.stage(ConfigEdit::SetVar {
+            name: "EDITOR".to_owned(),
+            value: "vi".to_owned(),
+            expand: false,
+        });
+        app.apply_pending().unwrap();
+        std::fs::write(&app.config_path, "edited by hand\n").unwrap();
+        let before = app.snapshot();
+
+        assert!(app.undo().is_err());
+
+        assert_eq!(app.snapshot(), before);
+        assert_eq!(app.history.undo.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(&app.config_path).unwrap(),
+            "edited by hand\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_new_var_name() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let home = dir.path();
+        std::fs::write(home.join(".bashrc"), "export EDITOR=vi\n").unwrap();
+        std::fs::create_dir_all(home.join("etc")).unwrap();
+        std::fs::write(home.join("etc/environment"), "LANG=C\n").unwrap();
+        std::fs::create_dir_all(home.join(".config/environment.d")).unwrap();
+        std::fs::write(
+            home.join(".config/environment.d/10-pager.conf"),
+            "PAGER=less\n",
+        )
+        .unwrap();
+        let mut app = App::in_home(home);
+        let error = |app: &App, name: &str| app.validate_new_var_name(name).unwrap_err();
+
+        assert_eq!(error(&app, ""), "Enter a name");
+        for name in ["1BAD", "ENVELOPE-TEST", "ÉDITEUR", "A B"] {
+            assert!(error(&app, name).contains("not a valid name"), "{}", name);
+        }
+        assert!(error(&app, "PATH").contains("already exists in the environment"));
+        assert!(error(&app, "EDITOR").ends_with(".bashrc"));
+        assert!(app.validate_new_var_name("_ENVELOPE_TEST2").is_ok());
+        // Names are case-sensitive.
+        assert!(app.validate_new_var_name("editor").is_ok());
+
+        // The system file's variables only clash in system scope.
+        assert!(app.validate_new_var_name("LANG").is_ok());
+        app.scope = Scope::System;
+        assert!(error(&app, "LANG").ends_with("etc/environment"));
+        assert!(app.validate_new_var_name("PAGER").is_ok());
+
+        app.scope = Scope::SessionUser;
+        assert!(error(&app, "PAGER").ends_with("10-pager.conf"));
+
+        app.scope = Scope::Dotenv;
+        assert!(app.validate_new_var_name("EDITOR").is_ok());
+    }
+
+    #[test]
+    fn test_rejected_new_var_name_keeps_the_form_open() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.start_new_var();
+        app.env_var_key = "PATH".to_owned();
+        app.env_var_value = "/bin".to_owned();
+
+        app.create_new_var();
+
+        assert!(app.editing && app.name_error.is_some());
+        assert_eq!(app.env_var_value, "/bin");
+        assert!(app.pending.is_empty() && app.history.undo.is_empty());
+        app.cancel_new_var();
+        assert!(!app.editing && app.name_error.is_none());
+        assert_eq!(app.env_vars.len(), 1);
+    }
+
+    fn select_var(app: &mut App, name: &str) {
+        app.env_vars.push((name.into(), "1".into()));
+        app.selected_env_var = app.env_vars.len() - 1;
+    }
+
+    #[test]
+    fn test_delete_var_only_a_function_defines_asks_to_unset() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join(".bashrc"),
+            "f() { local ENVELOPE_TEST=1; }\n",
+        )
+        .unwrap();
+        let mut app = App::in_home(dir.path());
+        select_var(&mut app, "ENVELOPE_TEST");
+
+        app.delete_selected_var().unwrap();
+        assert_eq!(app.confirm_unset.as_deref(), Some("ENVELOPE_TEST"));
+
+        // Declining only drops it from the list.
+        app.confirm_delete(false);
+        assert_eq!(app.confirm_unset, None);
+        assert_eq!(app.env_vars.len(), 1);
+        assert!(app.pending.is_empty());
+        assert_eq!(app.history.undo.len(), 1);
+
+        app.confirm_delete(true);
+        assert!(app.pending.is_empty());
+        assert_eq!(app.history.undo.len(), 1);
+    }
+
+    #[test]
+    fn test_delete_var_stops_at_unreadable_startup_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export ENVELOPE_TEST=1\n").unwrap();
+        std::fs::write(dir.path().join(".profile"), b"export A=\xff\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        select_var(&mut app, "ENVELOPE_TEST");
+
+        assert!(app.delete_selected_var().is_err());
+
+        assert_eq!(app.env_vars.len(), 2);
+        assert!(app.pending.is_empty());
+        assert!(app.history.undo.is_empty());
+    }
+
+    #[test]
+    fn test_rename_inherited_var_defines_new_and_unsets_old() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        select_var(&mut app, "ENVELOPE_OLD");
+
+        app.start_rename().unwrap();
+        assert!(app.rename_preview.is_empty());
+        app.env_var_key = "ENVELOPE_NEW".to_owned();
+        app.rename_selected_var().unwrap();
+
+        assert_eq!(app.rename_from, None);
+        assert_eq!(app.env_vars[1].0, "ENVELOPE_NEW");
+        assert!(app.pending_diff.contains("+export ENVELOPE_NEW='1'\n"));
+        assert!(app.pending_diff.contains("+unset ENVELOPE_OLD\n"));
+    }
+
+    #[test]
+    fn test_rename_to_taken_name_keeps_the_form_open() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export ENVELOPE_OLD=1\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        select_var(&mut app, "ENVELOPE_OLD");
+        app.start_rename().unwrap();
+
+        // Its own name is taken too.
+        for taken in ["ENVELOPE_OLD", "PATH", "bad name"] {
+            app.env_var_key = taken.to_owned();
+            app.rename_selected_var().unwrap();
+            assert!(app.name_error.is_some(), "{}", taken);
+            assert_eq!(app.rename_from.as_deref(), Some("ENVELOPE_OLD"));
+        }
+        assert!(app.pending.is_empty() && app.history.undo.is_empty());
+
+        app.cancel_rename();
+        assert!(!app.editing && app.rename_preview.is_empty());
+        assert_eq!(app.env_vars[1].0, "ENVELOPE_OLD");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_values_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.env_vars
+            .push(("BIN".into(), OsStr::from_bytes(b"/opt/\xff").to_owned()));
+        app.env_vars
+            .push((OsStr::from_bytes(b"K\xe9Y").to_owned(), "1".into()));
+        let path = dir.path().join(".env");
+        std::fs::write(&path, "OPT=$BIN\nPLAIN=${PATH}\n").unwrap();
+
+        // Only UTF-8 variables expand; the others are as if unset.
+        app.compare_with(path).unwrap();
+        assert_eq!(app.compare_vars[0], ("OPT".to_owned(), String::new()));
+        assert_eq!(app.compare_vars[1].1, "/usr/bin:/bin");
+        let comparison = app.comparison().unwrap();
+        assert!(comparison.extra.contains(&"K\\xE9Y".to_owned()));
+        assert_eq!(app.origin_of(&app.env_vars[2].0), None);
+
+        app.editing_bytes = true;
+        assert_eq!(app.edited_value("/opt/\\xFF"), app.env_vars[1].1);
+        app.editing_bytes = false;
+        assert_eq!(app.edited_value("/opt/\\xFF"), OsStr::new("/opt/\\xFF"));
+
+        app.selected_env_var = 1;
+        app.start_rename().unwrap();
+        app.env_var_key = "ENVELOPE_BIN".to_owned();
+        app.rename_selected_var().unwrap();
+        assert_eq!(app.env_vars[1].0, "ENVELOPE_BIN");
+        assert!(app.pending.is_empty());
+        assert!(matches!(
+            app.diagnostics.last(),
+            Some(Diagnostic::Failed(message)) if message.starts_with("ENVELOPE_BIN isn't valid UTF-8")
+        ));
+    }
+
+    #[test]
+    fn test_case_collisions_groups() {
+        let vars: Vec<(OsString, OsString)> = [
+            ("path", "/bin"),
+            ("Path", "/bin"),
+            ("PATH", "/bin"),
+            ("http_proxy", "a"),
+            ("HTTP_PROXY", "b"),
+            ("Http_Proxy", "a"),
+            ("ÉTAT", "1"),
+            ("état", "2"),
+            ("EDITOR", "vi"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect();
+
+        assert_eq!(
+            case_collisions(&vars),
+            vec![
+                vec!["HTTP_PROXY", "Http_Proxy", "http_proxy"],
+                vec!["ÉTAT", "état"],
+            ]
+        );
+        assert!(case_collisions(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_case_differing_names_are_separate_variables() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export HTTP_PROXY=http://a\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        app.diagnostics.push(Diagnostic::NoPath);
+        select_var(&mut app, "HTTP_PROXY");
+        app.env_var_key = "http_proxy".to_owned();
+        app.env_var_value = "http://b".to_owned();
+
+        app.create_new_var();
+        assert_eq!(app.name_error, None);
+        assert_eq!(
+            app.diagnostics,
+            vec![
+                Diagnostic::NoPath,
+                Diagnostic::CaseCollision(vec!["HTTP_PROXY".to_owned(), "http_proxy".to_owned()]),
+            ]
+        );
+
+        app.apply_pending().unwrap();
+        app.delete_selected_var().unwrap();
+        assert_eq!(app.confirm_unset, None);
+        assert_eq!(app.diagnostics, vec![Diagnostic::NoPath]);
+        app.apply_pending().unwrap();
+        let contents = std::fs::read_to_string(&app.config_path).unwrap();
+        assert!(contents.contains("export HTTP_PROXY=http://a\n"));
+        assert!(!contents.contains("http_proxy"));
+
+        // Undoing the apply and then the delete brings the warning back.
+        app.undo().unwrap();
+        app.undo().unwrap();
+        assert_eq!(app.diagnostics.len(), 2);
+    }
+
+    #[test]
+    fn test_expansion_follows_how_the_config_writes_values() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let bashrc = dir.path().join(".bashrc");
+        std::fs::write(
+            &bashrc,
+            "export GOPATH=\"$HOME/go\"\nexport LITERAL='$HOME'\nexport PLAIN=/opt\nexport PS=\"$(id)\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            expanding_vars(&bashrc, Dialect::Posix),
+            HashSet::from(["GOPATH".to_owned()])
+        );
+        assert!(expanding_vars(&dir.path().join("missing"), Dialect::Posix).is_empty());
+
+        let mut app = App::in_home(dir.path());
+        app.env_vars.clear();
+        app.toggle_expansion();
+        assert_eq!(app.expanding.len(), 1);
+        select_var(&mut app, "LITERAL");
+        app.toggle_expansion();
+        assert!(app.expanding.contains("LITERAL"));
+        select_var(&mut app, "GOPATH");
+        app.toggle_expansion();
+        assert_eq!(app.expanding, HashSet::from(["LITERAL".to_owned()]));
+
+        app.stage(ConfigEdit::SetVar {
+            name: "LITERAL".to_owned(),
+            value: "$HOME/x".to_owned(),
+            expand: app.expanding.contains("LITERAL"),
+        });
+        app.apply_pending().unwrap();
+        assert!(std::fs::read_to_string(&bashrc)
+            .unwrap()
+            .contains("export LITERAL=\"$HOME/x\"\n"));
+    }
+
+    #[test]
+    fn test_search_without_hits_and_with_the_origin_filter() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export GOPATH=/go\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        select_var(&mut app, "GOPATH");
+        select_var(&mut app, "GOROOT");
+        app.env_vars[2].1 = "/usr/lib/go".into();
+        app.refresh_provenance();
+        app.jump_to_hit(1);
+        assert_eq!(app.selected_env_var, 2);
+
+        app.start_search();
+        app.update_search(|search| "zzz".chars().for_each(|c| search.push(c)));
+        assert!(app.visible_env_vars().is_empty() && app.visible_path_dirs().is_empty());
+        assert_eq!(app.env_list_state.selected(), None);
+        assert_eq!(app.path_list_state.selected(), None);
+        app.jump_to_hit(1);
+        assert_eq!(app.selected_env_var, 2);
+
+        app.update_search(|search| {
+            (0..3).for_each(|_| search.pop());
+            "lib".chars().for_each(|c| search.push(c));
+        });
+        // Values are only searched once asked to.
+        assert!(app.visible_env_vars().is_empty());
+        app.update_search(Search::toggle_values);
+        assert_eq!(app.visible_env_vars(), vec![2]);
+
+        app.update_search(|search| {
+            (0..3).for_each(|_| search.pop());
+            "go".chars().for_each(|c| search.push(c));
+        });
+        app.cycle_origin_filter();
+        assert_eq!(app.visible_env_vars(), vec![1]);
+        assert_eq!(app.selected_env_var, 1);
+
+        app.clear_search();
+        app.origin_filter = None;
+        assert_eq!(app.visible_env_vars(), vec![0, 1, 2]);
+        assert_eq!(app.visible_path_dirs(), vec![0, 1]);
+        assert!(!app.searching);
+    }
+
+    #[test]
+    fn test_path_operations_that_change_nothing_record_nothing() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+
+        app.move_path_dir(-1);
+        app.selected_path_dir = 1;
+        app.move_path_dir(1);
+        app.dedupe_path();
+        app.start_path_insert(false);
+        app.insert_path_dir();
+        app.insert_path_dir();
+        assert!(!app.editing);
+        app.path_var_dirs.clear();
+        app.remove_path_dir();
+
+        assert!(app.pending.is_empty() && app.history.undo.is_empty());
+    }
+
+    #[test]
+    fn test_path_operations_keep_the_selection_on_their_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.path_var_dirs = ["/a", "/usr/bin", "/a", "/bin", "/usr/bin"]
+            .map(PathBuf::from)
+            .to_vec();
+        app.selected_path_dir = 3;
+
+        app.dedupe_path();
+        assert_eq!(
+            app.path_var_dirs,
+            ["/a", "/usr/bin", "/bin"].map(PathBuf::from)
+        );
+        assert_eq!(app.selected_path_dir, 2);
+        app.remove_path_dir();
+        assert_eq!(app.selected_path_dir, 1);
+        app.path_list_state.select(None);
+        app.start_path_insert(false);
+        app.path_var_edit = "/opt/bin".to_owned();
+        app.insert_path_dir();
+        assert_eq!(app.path_var_dirs.last(), Some(&PathBuf::from("/opt/bin")));
+        assert_eq!(app.selected_path_dir, 2);
+        // With `/bin` gone the inherited PATH isn't kept whole.
+        assert!(app
+            .pending_diff
+            .contains("+export PATH='/a:/usr/bin:/opt/bin'\n"));
+
+        app.undo().unwrap();
+        app.undo().unwrap();
+        app.undo().unwrap();
+        assert_eq!(app.path_var_dirs.len(), 5);
+        assert!(app.pending.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_with_a_directory_that_is_not_utf8_is_not_staged() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.path_var_dirs
+            .push(PathBuf::from(OsStr::from_bytes(b"/opt/\xff")));
+        app.selected_path_dir = 2;
+
+        app.move_path_dir(-2);
+
+        assert_eq!(app.selected_path_dir, 0);
+        assert!(app.pending.is_empty());
+        assert_eq!(app.history.undo.len(), 1);
+        assert!(matches!(
+            app.diagnostics.last(),
+            Some(Diagnostic::Failed(message)) if message.starts_with("PATH isn't valid UTF-8")
+        ));
+    }
+
+    #[test]
+    fn test_declined_install_keeps_edits_staged() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.scope = Scope::System;
+        app.stage(ConfigEdit::UnsetVar("PAGER".to_owned()));
+        app.confirm_install = vec![app.system_env_path.clone()];
+
+        app.confirm_staged_install(false).unwrap();
+
+        assert!(app.confirm_install.is_empty());
+        assert_eq!(app.pending.len(), 1);
+        assert!(app.install_commands.is_empty() && app.history.undo.is_empty());
+        assert!(!app.system_env_path.exists());
+    }
+
+    #[test]
+    fn test_partly_failed_apply_records_what_it_wrote() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        // fish's config directory doesn't exist, so its file can't be saved.
+        app.extra_targets = vec![Dialect::Fish];
+        app.stage(ConfigEdit::SetVar {
+            name: "ENVELOPE_TEST".to_owned(),
+            value: "1".to_owned(),
+            expand: false,
+        });
+
+        assert!(app.apply_pending().is_err());
+
+        assert!(std::fs::read_to_string(&app.config_path)
+            .unwrap()
+            .contains("ENVELOPE_TEST"));
+        assert_eq!(app.pending.paths(), app.extra_target_paths());
+        assert_eq!(app.history.undo.len(), 1);
+        app.undo().unwrap();
+        assert!(!app.config_path.exists());
+        assert_eq!(app.pending.len(), 2);
+    }
+
+    #[test]
+    fn test_failed_install_keeps_every_edit_staged() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.scope = Scope::System;
+        app.stage(ConfigEdit::UnsetVar("PAGER".to_owned()));
+        app.stage_in(
+            app.config_path.clone(),
+            ConfigEdit::UnsetVar("EDITOR".to_owned()),
+        );
+        // As if both needed root, and the second became unreadable since.
+        app.confirm_install = vec![app.system_env_path.clone(), app.config_path.clone()];
+        std::fs::write(&app.config_path, b"export A=\xff\n").unwrap();
+
+        assert!(app.confirm_staged_install(true).is_err());
+
+        assert_eq!(app.pending.len(), 2);
+        assert!(app.install_commands.is_empty() && app.history.undo.is_empty());
+    }
+
+    #[test]
+    fn test_open_dotenv_interpolates_against_environment() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(&path, "BIN=/opt/bin\nPATH=$BIN:${PATH}\nLITERAL='$PATH'\n").unwrap();
+        let mut app = App::in_home(dir.path());
+
+        app.open_dotenv(path.clone()).unwrap();
+
+        assert_eq!(app.scope, Scope::Dotenv);
+        assert_eq!(app.target_path(), path);
+        assert_eq!(
+            app.path_var_dirs,
+            ["/opt/bin", "/usr/bin", "/bin"].map(PathBuf::from)
+        );
+        assert_eq!(app.env_vars[2], ("LITERAL".into(), "$PATH".into()));
+        assert_eq!(app.saved_env_vars, app.env_vars);
+    }
+
+    #[test]
+    fn test_open_dotenv_missing_or_unreadable() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        let missing = dir.path().join("app/.env");
+        std::fs::create_dir(dir.path().join("app")).unwrap();
+
+        app.open_dotenv(missing.clone()).unwrap();
+        assert!(app.env_vars.is_empty() && app.path_var_dirs.is_empty());
+        app.stage(ConfigEdit::SetVar {
+            name: "A".to_owned(),
+            value: "1".to_owned(),
+            expand: false,
+        });
+        app.apply_pending().unwrap();
+        assert!(std::fs::read_to_string(&missing)
+            .unwrap()
+            .contains("\nA=1\n"));
+
+        let mut app = App::in_home(dir.path());
+        assert!(app.open_dotenv(dir.path().join("app")).is_err());
+        assert_eq!(app.scope, Scope::User);
+    }
+
+    #[test]
+    fn test_unreadable_config_refuses_user_edits_only() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join(".bashrc")).unwrap();
+        let mut app = App::in_home(dir.path());
+        assert!(matches!(
+            app.diagnostics.as_slice(),
+            [Diagnostic::ConfigUnreadable { .. }]
+        ));
+        assert!(app.is_read_only(&app.config_path));
+        assert!(!app.is_read_only(&app.system_env_path));
+        let set = ConfigEdit::SetVar {
+            name: "ENVELOPE_TEST".to_owned(),
+            value: "1".to_owned(),
+            expand: false,
+        };
+
+        app.stage(set.clone());
+        assert!(app.pending.is_empty());
+        app.stage_path(ConfigEdit::AppendPath("/opt/bin".to_owned()));
+        assert!(app.pending.is_empty());
+        app.scope = Scope::SessionUser;
+        app.stage(set);
+        assert_eq!(app.pending.len(), 1);
+    }
+
+    #[test]
+    fn test_unparsable_config_still_takes_new_definitions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export A=\"1\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        assert!(!app.is_read_only(&app.config_path));
+
+        app.stage(ConfigEdit::SetVar {
+            name: "ENVELOPE_TEST".to_owned(),
+            value: "1".to_owned(),
+            expand: false,
+        });
+        app.apply_pending().unwrap();
+
+        let contents = std::fs::read_to_string(&app.config_path).unwrap();
+        assert!(contents.starts_with("export A=\"1\n"));
+        assert!(contents.contains("export ENVELOPE_TEST='1'"));
+    }
+
+    #[test]
+    fn test_toggle_picked_target_keeps_the_users_own_shell() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.shells = vec![Dialect::Posix, Dialect::Zsh, Dialect::Fish];
+
+        app.toggle_picked_target();
+        assert!(app.extra_targets.is_empty());
+        app.toggle_picker();
+        app.toggle_picked_target();
+        assert!(app.extra_targets.is_empty());
+
+        app.move_picker(10);
+        assert_eq!(app.picker, Some(2));
+        app.toggle_picked_target();
+        app.move_picker(-1);
+        app.toggle_picked_target();
+        assert_eq!(app.extra_targets, vec![Dialect::Fish, Dialect::Zsh]);
+        assert_eq!(
+            app.extra_target_paths(),
+            vec![
+                dir.path().join(".config/fish/config.fish"),
+                dir.path().join(".zshrc"),
+            ]
+        );
+        app.toggle_picked_target();
+        assert_eq!(app.extra_targets, vec![Dialect::Fish]);
+        app.move_picker(-10);
+        assert_eq!(app.picker, Some(0));
+    }
+
+    #[test]
+    fn test_extra_targets_get_edits_in_their_own_syntax() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fish = dir.path().join(".config/fish/config.fish");
+        std::fs::create_dir_all(fish.parent().unwrap()).unwrap();
+        std::fs::create_dir(dir.path().join("etc")).unwrap();
+        let mut app = App::in_home(dir.path());
+        app.extra_targets = vec![Dialect::Fish];
+
+        app.stage(ConfigEdit::SetVar {
+            name: "ENVELOPE_TEST".to_owned(),
+            value: "1".to_owned(),
+            expand: false,
+        });
+        app.stage_path(ConfigEdit::AppendPath("/opt/bin".to_owned()));
+        app.scope = Scope::System;
+        app.stage(ConfigEdit::SetVar {
+            name: "ENVELOPE_SYSTEM".to_owned(),
+            value: "1".to_owned(),
+            expand: false,
+        });
+        app.scope = Scope::User;
+        app.apply_pending().unwrap();
+
+        let contents = std::fs::read_to_string(&fish).unwrap();
+        assert!(contents.contains("set -gx ENVELOPE_TEST '1'"));
+        assert!(contents.contains("/opt/bin"));
+        assert!(!contents.contains("ENVELOPE_SYSTEM"));
+        assert!(std::fs::read_to_string(&app.system_env_path)
+            .unwrap()
+            .contains("ENVELOPE_SYSTEM=1"));
+    }
+
+    #[test]
+    fn test_use_shell_without_a_config_or_support() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export EDITOR=vi\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        app.diagnostics.push(Diagnostic::NoPath);
+
+        let error = app.use_shell(PathBuf::from("/bin/nu")).unwrap_err();
+        assert!(matches!(error, Error::UnsupportedShell(_)));
+        assert_eq!(app.dialect, Dialect::Posix);
+        assert!(app.shell_env_vars.contains_key("EDITOR"));
+
+        app.use_shell(PathBuf::from("/bin/ksh")).unwrap();
+        let kshrc = dir.path().join(".kshrc");
+        assert_eq!(app.config_path, kshrc);
+        assert!(app.shell_env_vars.is_empty());
+        assert_eq!(
+            app.diagnostics,
+            vec![Diagnostic::NoPath, Diagnostic::ConfigMissing(kshrc)]
+        );
+        assert_eq!(app.capture_program(), Some(PathBuf::from("/bin/ksh")));
+    }
+
+    #[test]
+    fn test_move_path_step_stays_within_the_replay() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.move_path_step(1);
+        assert_eq!(app.path_step, None);
+        app.toggle_path_steps();
+        assert_eq!(app.path_step, Some(0));
+
+        std::fs::write(
+            dir.path().join(".bashrc"),
+            "PATH=/a\nPATH=/b:$PATH\nPATH=/c:$PATH\n",
+        )
+        .unwrap();
+        app.refresh_provenance();
+        app.move_path_step(5);
+        assert_eq!(app.path_step, Some(2));
+        app.move_path_step(-5);
+        assert_eq!(app.path_step, Some(0));
+        app.toggle_path_steps();
+        assert_eq!(app.path_step, None);
+    }
+
+    #[test]
+    fn test_cycle_shell_mode_drops_files_the_mode_skips() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join(".bashrc"),
+            "export PATH=\"/opt/a/bin:$PATH\"\nexport PATH=\"/opt/b/bin:$PATH\"\n",
+        )
+        .unwrap();
+        let mut app = App::in_home(dir.path());
+        assert_eq!(app.startup_chain.len(), 1);
+        app.toggle_path_steps();
+        assert_eq!(app.path_step, Some(1));
+
+        app.cycle_shell_mode();
+
+        // A login bash without a profile reads no user file at all.
+        assert!(app.startup_chain.is_empty());
+        assert!(app.provenance.is_empty());
+        assert_eq!(app.path_step, Some(0));
+    }
+
+    #[test]
+    fn test_cycle_origin_filter_skips_files_defining_nothing_shown() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("etc")).unwrap();
+        std::fs::write(dir.path().join("etc/environment"), "UNSHOWN=1\n").unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export ENVELOPE_A=1\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        assert!(app.provenance.contains_key("UNSHOWN"));
+
+        app.cycle_origin_filter();
+        assert_eq!(app.origin_filter, Some(OriginFilter::Inherited));
+        assert_eq!(app.visible_env_vars(), vec![0]);
+
+        select_var(&mut app, "ENVELOPE_A");
+        app.origin_filter = None;
+        app.cycle_origin_filter();
+        assert_eq!(
+            app.origin_filter,
+            Some(OriginFilter::File(app.config_path.clone()))
+        );
+        assert_eq!(app.visible_env_vars(), vec![1]);
+        app.cycle_origin_filter();
+        app.cycle_origin_filter();
+        assert_eq!(app.origin_filter, None);
+    }
+
+    #[test]
+    fn test_provenance_follows_the_dotenv_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".bashrc"), "export ENVELOPE_A=1\n").unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(&path, "# app\nENVELOPE_B=2\n").unwrap();
+        let mut app = App::in_home(dir.path());
+        assert!(app.provenance.contains_key("ENVELOPE_A"));
+
+        app.open_dotenv(path.clone()).unwrap();
+
+        assert_eq!(
+            app.provenance_files(),
+            vec![(path.clone(), Dialect::Dotenv)]
+        );
+        assert!(!app.provenance.contains_key("ENVELOPE_A"));
+        assert_eq!(
+            app.origin_of(OsStr::new("ENVELOPE_B"))
+                .map(|origin| (&origin.path, origin.line)),
+            Some((&path, 2))
+        );
+    }
+
+    #[test]
+    fn test_compare_with_missing_or_unreadable_reference() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        assert_eq!(app.comparison(), None);
+        app.add_missing_keys();
+        assert!(app.pending.is_empty() && app.history.undo.is_empty());
+
+        // A reference that isn't there yet has no keys.
+        app.compare_with(dir.path().join(".env.example")).unwrap();
+        let comparison = app.comparison().unwrap();
+        assert_eq!(comparison.extra, ["PATH"]);
+        app.add_missing_keys();
+        assert!(app.pending.is_empty() && app.history.undo.is_empty());
+
+        assert!(app.compare_with(dir.path().to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_add_missing_keys_stages_interpolated_reference_values() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let example = dir.path().join(".env.example");
+        std::fs::write(
+            &example,
+            "PATH=/usr/bin:/bin\nDATA=\"${PATH}$UNSET/data\"\n",
+        )
+        .unwrap();
+        let mut app = App::in_home(dir.path());
+        app.compare_with(example).unwrap();
+
+        app.add_missing_keys();
+
+        assert_eq!(
+            app.env_vars[1],
+            ("DATA".into(), "/usr/bin:/bin/data".into())
+        );
+        assert_eq!(app.pending.len(), 1);
+        assert!(app.comparison().unwrap().missing.is_empty());
+        assert!(matches!(
+            &app.history.undo[0].operation,
+            Operation::AddMissing { count: 1, .. }
+        ));
+        app.undo().unwrap();
+        assert_eq!(app.comparison().unwrap().missing.len(), 1);
+    }
+
+    #[test]
+    fn test_get_shell_vars_reports_problems() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let bashrc = dir.path().join(".bashrc");
+
+        let (vars, diagnostic) = get_shell_vars(&bashrc, Dialect::Posix);
+        assert!(vars.is_empty());
+        assert_eq!(diagnostic, Some(Diagnostic::ConfigMissing(bashrc.clone())));
+
+        // Only exported variables are reported.
+        std::fs::write(&bashrc, "export EDITOR=vi\nB=2\n").unwrap();
+        let (vars, diagnostic) = get_shell_vars(&bashrc, Dialect::Posix);
+        assert_eq!(diagnostic, None);
+        assert_eq!(
+            vars,
+            HashMap::from([("EDITOR".to_owned(), "vi".to_owned())])
+        );
+
+        std::fs::write(&bashrc, "export A='unterminated\n").unwrap();
+        let (_, diagnostic) = get_shell_vars(&bashrc, Dialect::Posix);
+        assert!(matches!(
+            &diagnostic,
+            Some(Diagnostic::ConfigUnparsable { path, .. }) if path == &bashrc
+        ));
+        assert!(!diagnostic.unwrap().read_only());
+
+        std::fs::write(&bashrc, b"export A=\xff\n").unwrap();
+        let (_, diagnostic) = get_shell_vars(&bashrc, Dialect::Posix);
+        assert!(diagnostic.is_some_and(|diagnostic| diagnostic.read_only()));
+
+        let (_, diagnostic) = get_shell_vars(dir.path(), Dialect::Posix);
+        assert!(diagnostic.is_some_and(|diagnostic| diagnostic.read_only()));
+    }
 }
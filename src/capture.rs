@@ -0,0 +1,375 @@
+use crate::dialect::Dialect;
+use crate::startup::Mode;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a shell gets to start up and print its environment.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long output is still read after the shell exits.
+const DRAIN: Duration = Duration::from_millis(500);
+
+/// Variables the capture seeds the shell's environment with.
+const SEEDED: &[&str] = &["HOME", "USER", "LOGNAME", "SHELL", "TERM", "PATH"];
+
+/// Variables shells maintain themselves, which no startup file sets.
+const SHELL_MAINTAINED: &[&str] = &["SHLVL", "PWD", "OLDPWD", "_"];
+
+/// The program started for a dialect when the user's own shell speaks a
+/// different one.
+pub fn default_program(dialect: Dialect) -> Option<&'static str> {
+    match dialect {
+        Dialect::Posix => Some("bash"),
+        Dialect::Zsh => Some("zsh"),
+        Dialect::Ksh => Some("ksh"),
+        Dialect::Csh => Some("tcsh"),
+        Dialect::Fish => Some("fish"),
+        Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv => None,
+    }
+}
+
+/// A command that starts `program` as a shell in `mode` from a clean
+/// environment and has it print its environment NUL-separated.
+pub fn command(program: &str, dialect: Dialect, mode: Mode, home: &Path) -> Command {
+    let mut command = Command::new(program);
+    command.env_clear();
+    for name in SEEDED {
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+    command
+        .env("HOME", home)
+        .env("SHELL", program)
+        .current_dir(home);
+    match dialect {
+        // csh only takes `-l` as its sole argument.
+        Dialect::Csh => {}
+        Dialect::Fish => {
+            if mode.login {
+                command.arg("--login");
+            }
+            if mode.interactive {
+                command.arg("--interactive");
+            }
+        }
+        _ => {
+            if mode.login {
+                command.arg("-l");
+            }
+            if mode.interactive {
+                command.arg("-i");
+            }
+        }
+    }
+    command
+        .args(["-c", "env -0"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    command
+}
+
+/// Runs `command` and parses the environment it prints, killing it if it
+/// hasn't finished within `timeout`. Output is read for at most `DRAIN`
+/// after the shell exits, since a process it started in the background may
+/// hold its stdout open for much longer.
+pub fn capture(mut command: Command, timeout: Duration) -> io::Result<Vec<(String, String)>> {
+    let mut child = command.spawn()?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("no stdout"))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 8192];
+        loop {
+            let chunk = match stdout.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => Ok(buffer[..len].to_vec()),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => Err(error),
+            };
+            let failed = chunk.is_err();
+            if sender.send(chunk).is_err() || failed {
+                break;
+            }
+        }
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("the shell didn't finish starting within {:?}", timeout),
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let drained = deadline.min(Instant::now() + DRAIN);
+    let mut output = Vec::new();
+    while let Ok(chunk) = receiver.recv_timeout(drained.saturating_duration_since(Instant::now())) {
+        output.extend(chunk?);
+    }
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "the shell exited with {}",
+            status
+        )));
+    }
+    Ok(parse_env0(&output))
+}
+
+/// Parses the output of `env -0`.
+pub fn parse_env0(output: &[u8]) -> Vec<(String, String)> {
+    output
+        .split(|&byte| byte == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (name, value) = entry.split_once('=')?;
+            Some((name.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// A variable whose runtime value disagrees with what envelope sees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeDiff {
+    pub name: String,
+    /// Value in the spawned shell.
+    pub runtime: Option<String>,
+    /// Value in the environment envelope runs in.
+    pub current: Option<String>,
+    /// Whether a parsed startup file defines it.
+    pub declared: bool,
+}
+
+impl RuntimeDiff {
+    /// Set at runtime by something static parsing can't see, such as a
+    /// conditional or a command substitution.
+    pub fn runtime_only(&self) -> bool {
+        self.runtime.is_some() && !self.declared
+    }
+
+    /// Declared in a startup file but not set at runtime.
+    pub fn missing_at_runtime(&self) -> bool {
+        self.runtime.is_none() && self.declared
+    }
+}
+
+/// Compares a captured environment against the variables startup files
+/// declare and the current environment. Only variables that are runtime
+/// only, missing at runtime or valued differently from the current
+/// environment are listed, by name. Variables the capture seeds or the
+/// shell maintains are left out.
+pub fn diff(
+    runtime: &[(String, String)],
+    declared: &HashSet<String>,
+    current: &[(String, String)],
+) -> Vec<RuntimeDiff> {
+    let runtime: HashMap<&str, &str> = runtime
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    let current: HashMap<&str, &str> = current
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    let names: BTreeSet<&str> = runtime
+        .keys()
+        .copied()
+        .chain(declared.iter().map(String::as_str))
+        .filter(|name| !SEEDED.contains(name) && !SHELL_MAINTAINED.contains(name))
+        .collect();
+    names
+        .into_iter()
+        .map(|name| RuntimeDiff {
+            name: name.to_owned(),
+            runtime: runtime.get(name).map(|value| value.to_string()),
+            current: current.get(name).map(|value| value.to_string()),
+            declared: declared.contains(name),
+        })
+        .filter(|diff| {
+            diff.runtime_only()
+                || diff.missing_at_runtime()
+                || (diff.runtime.is_some() && diff.runtime != diff.current)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn test_diff_flags_runtime_only_and_changed() {
+        let runtime = [
+            pair("EDITOR", "vim"),
+            pair("GPG_TTY", "/dev/pts/1"),
+            pair("PAGER", "less"),
+            pair("SHLVL", "1"),
+        ];
+        let declared: HashSet<String> = ["EDITOR", "PAGER", "NEVER_SET"]
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let current = [pair("EDITOR", "vi"), pair("PAGER", "less")];
+
+        let names: Vec<(String, bool, bool)> = diff(&runtime, &declared, &current)
+            .into_iter()
+            .map(|diff| {
+                (
+                    diff.name.clone(),
+                    diff.runtime_only(),
+                    diff.missing_at_runtime(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            names,
+            vec![
+                ("EDITOR".to_owned(), false, false),
+                ("GPG_TTY".to_owned(), true, false),
+                ("NEVER_SET".to_owned(), false, true),
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore = "starts sh"]
+    fn test_capture_login_shell() {
+        let home = TempDir::new().unwrap();
+        fs::write(
+            home.path().join(".profile"),
+            "if true; then export ENVELOPE_RUNTIME=\"$(echo computed)\"; fi\n",
+        )
+        .unwrap();
+        let mode = Mode {
+            login: true,
+            interactive: false,
+        };
+
+        let vars = capture(command("sh", Dialect::Posix, mode, home.path()), TIMEOUT).unwrap();
+
+        assert!(vars.contains(&pair("ENVELOPE_RUNTIME", "computed")));
+        assert!(vars.contains(&pair("HOME", &home.path().display().to_string())));
+    }
+
+    #[test]
+    #[ignore = "starts sh"]
+    fn test_capture_returns_while_a_background_job_holds_stdout() {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "sleep 5 & env -0"])
+            .env_clear()
+            .env("ENVELOPE_TEST", "1")
+            .stdout(Stdio::piped());
+        let started = Instant::now();
+
+        let vars = capture(command, Duration::from_secs(2)).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(vars.contains(&pair("ENVELOPE_TEST", "1")));
+    }
+
+    #[test]
+    fn test_parse_env0_edge_cases() {
+        assert_eq!(
+            parse_env0(b"A=1\0B=x=y\0"),
+            vec![pair("A", "1"), pair("B", "x=y")]
+        );
+        assert!(parse_env0(b"").is_empty());
+        assert_eq!(
+            parse_env0(b"NO_EQUALS\0EMPTY=\0MULTI=a\nb\0BYTES=\xff"),
+            vec![
+                pair("EMPTY", ""),
+                pair("MULTI", "a\nb"),
+                pair("BYTES", "\u{fffd}")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_arguments_by_dialect() {
+        let home = Path::new("/home/me");
+        let args = |dialect: Dialect, login: bool, interactive: bool| -> Vec<String> {
+            let mode = Mode { login, interactive };
+            command("sh", dialect, mode, home)
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            args(Dialect::Posix, true, true),
+            ["-l", "-i", "-c", "env -0"]
+        );
+        assert_eq!(args(Dialect::Zsh, false, false), ["-c", "env -0"]);
+        assert_eq!(
+            args(Dialect::Fish, true, true),
+            ["--login", "--interactive", "-c", "env -0"]
+        );
+        assert_eq!(args(Dialect::Csh, true, true), ["-c", "env -0"]);
+
+        let command = command("sh", Dialect::Posix, Mode::default(), home);
+        assert_eq!(command.get_current_dir(), Some(home));
+        let envs: HashMap<_, _> = command.get_envs().collect();
+        assert_eq!(envs[OsStr::new("HOME")], Some(OsStr::new("/home/me")));
+        assert_eq!(envs[OsStr::new("SHELL")], Some(OsStr::new("sh")));
+        assert!(envs
+            .keys()
+            .all(|name| SEEDED.iter().any(|seeded| OsStr::new(seeded) == *name)));
+    }
+
+    #[test]
+    fn test_capture_reports_a_missing_program() {
+        let home = TempDir::new().unwrap();
+        let command = command(
+            "envelope-no-such-shell",
+            Dialect::Posix,
+            Mode::default(),
+            home.path(),
+        );
+
+        let error = capture(command, TIMEOUT).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_diff_leaves_out_seeded_and_shell_maintained() {
+        let runtime = [
+            pair("HOME", "/elsewhere"),
+            pair("SHLVL", "2"),
+            pair("PWD", "/"),
+            pair("LANG", "C"),
+        ];
+        let declared: HashSet<String> = ["PATH".to_owned(), "LANG".to_owned()].into();
+
+        let diffs = diff(&runtime, &declared, &[pair("LANG", "C")]);
+        assert!(diffs.is_empty());
+
+        let diffs = diff(&runtime, &declared, &[]);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].current, None);
+        assert!(!diffs[0].runtime_only() && !diffs[0].missing_at_runtime());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+/// How a set of variables differs from a reference such as `.env.example`.
+/// Every list is in the order the keys appear in their source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Comparison {
+    /// Keys the reference has that the variables lack, with the reference's
+    /// value.
+    pub missing: Vec<(String, String)>,
+    /// Keys the variables have that the reference doesn't.
+    pub extra: Vec<String>,
+    /// Keys whose value is empty.
+    pub empty: Vec<String>,
+    /// Keys whose value is still the reference's placeholder.
+    pub placeholders: Vec<String>,
+}
+
+impl Comparison {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.empty.is_empty()
+            && self.placeholders.is_empty()
+    }
+}
+
+/// Compares `vars` against `reference`. A value counts as a placeholder
+/// when it is non-empty and identical to the reference's.
+pub fn compare(vars: &[(String, String)], reference: &[(String, String)]) -> Comparison {
+    let values: HashMap<&str, &str> = vars
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let reference_values: HashMap<&str, &str> = reference
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let mut comparison = Comparison {
+        missing: reference
+            .iter()
+            .filter(|(key, _)| !values.contains_key(key.as_str()))
+            .cloned()
+            .collect(),
+        ..Comparison::default()
+    };
+    for (key, value) in vars {
+        match reference_values.get(key.as_str()) {
+            None => comparison.extra.push(key.clone()),
+            Some(placeholder) if !value.is_empty() && value == placeholder => {
+                comparison.placeholders.push(key.clone())
+            }
+            Some(_) => {}
+        }
+        if value.is_empty() {
+            comparison.empty.push(key.clone());
+        }
+    }
+    comparison
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare_against_example() {
+        let env = vars(&[
+            ("DB_HOST", "localhost"),
+            ("API_KEY", "changeme"),
+            ("SECRET", ""),
+            ("DEBUG", "1"),
+        ]);
+        let example = vars(&[
+            ("DB_HOST", "db.example.com"),
+            ("DB_PORT", "5432"),
+            ("API_KEY", "changeme"),
+            ("SECRET", ""),
+            ("SENTRY_DSN", ""),
+        ]);
+
+        let comparison = compare(&env, &example);
+
+        assert_eq!(
+            comparison,
+            Comparison {
+                missing: vars(&[("DB_PORT", "5432"), ("SENTRY_DSN", "")]),
+                extra: vec!["DEBUG".to_owned()],
+                empty: vec!["SECRET".to_owned()],
+                placeholders: vec!["API_KEY".to_owned()],
+            }
+        );
+        assert_eq!(compare(&env, &env).placeholders.len(), 3);
+        assert!(compare(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_compare_is_case_sensitive_and_keeps_order() {
+        let env = vars(&[("db_host", "x"), ("B", "2"), ("A", "1")]);
+        let example = vars(&[("Z", "z"), ("DB_HOST", "x"), ("A", "")]);
+
+        let comparison = compare(&env, &example);
+
+        assert_eq!(comparison.missing, vars(&[("Z", "z"), ("DB_HOST", "x")]));
+        assert_eq!(comparison.extra, ["db_host", "B"]);
+        // An empty reference value isn't a placeholder to fill in.
+        assert!(comparison.placeholders.is_empty());
+        assert!(comparison.empty.is_empty());
+    }
+
+    #[test]
+    fn test_compare_with_empty_sides() {
+        let env = vars(&[("A", ""), ("B", "2")]);
+        let comparison = compare(&env, &[]);
+        assert_eq!(comparison.extra, ["A", "B"]);
+        assert_eq!(comparison.empty, ["A"]);
+        assert!(comparison.missing.is_empty());
+
+        let comparison = compare(&[], &env);
+        assert_eq!(comparison.missing, env);
+        assert!(comparison.extra.is_empty() && comparison.empty.is_empty());
+    }
+}
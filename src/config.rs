@@ -0,0 +1,1102 @@
+use crate::dialect::{Dialect, DEFAULT_ENVIRONMENT_PATH};
+use crate::error::Error;
+use crate::parser::{Assignment, AssignmentCommand, StatementKind, Value, Word};
+use crate::writer::{write_atomic, DEFAULT_BACKUPS};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// First line of the block envelope owns in a config file.
+pub const BLOCK_START: &str = "# >>> envelope >>>";
+/// Last line of the block envelope owns in a config file.
+pub const BLOCK_END: &str = "# <<< envelope <<<";
+const BLOCK_NOTE: &str = "# Managed by envelope; edits inside this block are overwritten.";
+
+/// A change envelope can make to a shell configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigEdit {
+    /// Export `name` with `value`, replacing its existing definition if any.
+    /// References to other variables in the value are expanded only with
+    /// `expand`.
+    SetVar {
+        name: String,
+        value: String,
+        expand: bool,
+    },
+    /// Append a directory to `PATH`.
+    AppendPath(String),
+    /// Set `PATH` to exactly `dirs`, in order. `inherited` is `PATH` as
+    /// envelope found it; directories added around it are written around
+    /// `$PATH` rather than replacing it.
+    SetPath {
+        dirs: Vec<String>,
+        inherited: Vec<String>,
+    },
+    /// Remove every definition of a variable.
+    RemoveVar(String),
+    /// Explicitly unset a variable that is inherited from elsewhere.
+    UnsetVar(String),
+    /// Rename a variable everywhere it is defined or referenced.
+    RenameVar { from: String, to: String },
+}
+
+impl ConfigEdit {
+    /// Refuses an edit that `dialect` can't write faithfully: environment
+    /// files are read a line at a time, so their values can't hold newlines
+    /// or other control characters.
+    pub fn check(&self, dialect: Dialect) -> Result<(), Error> {
+        if !matches!(dialect, Dialect::Environment | Dialect::EnvironmentD) {
+            return Ok(());
+        }
+        let (name, values): (&str, Vec<&str>) = match self {
+            ConfigEdit::SetVar { name, value, .. } => (name, vec![value]),
+            ConfigEdit::AppendPath(dir) => ("PATH", vec![dir]),
+            ConfigEdit::SetPath { dirs, .. } => ("PATH", dirs.iter().map(String::as_str).collect()),
+            ConfigEdit::RemoveVar(_) | ConfigEdit::UnsetVar(_) | ConfigEdit::RenameVar { .. } => {
+                return Ok(())
+            }
+        };
+        if values.iter().any(|value| value.contains(char::is_control)) {
+            return Err(Error::ControlCharacters(name.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// A shell configuration file loaded into memory for editing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub dialect: Dialect,
+    pub contents: String,
+    /// How many timestamped backups to keep when saving.
+    pub backups: usize,
+}
+
+impl ConfigFile {
+    pub fn new(path: impl Into<PathBuf>, dialect: Dialect, contents: impl Into<String>) -> Self {
+        ConfigFile {
+            path: path.into(),
+            dialect,
+            contents: contents.into(),
+            backups: DEFAULT_BACKUPS,
+        }
+    }
+
+    /// Reads `path`, treating a missing file as empty.
+    pub fn load(path: &Path, dialect: Dialect) -> io::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+        Ok(ConfigFile::new(path, dialect, contents))
+    }
+
+    /// Atomically replaces the file on disk, backing up the previous version.
+    pub fn save(&self) -> io::Result<()> {
+        write_atomic(&self.path, self.contents.as_bytes(), self.backups)
+    }
+
+    pub fn apply(&mut self, edit: &ConfigEdit) {
+        match edit {
+            ConfigEdit::SetVar {
+                name,
+                value,
+                expand,
+            } => self.set_var(name, value, *expand),
+            ConfigEdit::AppendPath(dir) => {
+                // pam_env can't expand `$PATH`, so the block starts from the
+                // `PATH` the file sets.
+                let start = match self.dialect {
+                    Dialect::Environment => (self.environment_path(), false),
+                    _ => ("$PATH".to_owned(), true),
+                };
+                self.update_block(|vars, _| {
+                    let (path, _) = vars.entry("PATH".to_owned()).or_insert(start);
+                    path.push(':');
+                    path.push_str(dir);
+                })
+            }
+            ConfigEdit::SetPath { dirs, inherited } => self.set_path(dirs, inherited),
+            ConfigEdit::RemoveVar(name) => self.remove_var(name),
+            // Environment files have no unset; the closest is dropping it.
+            ConfigEdit::UnsetVar(name)
+                if matches!(
+                    self.dialect,
+                    Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv
+                ) =>
+            {
+                self.remove_var(name)
+            }
+            ConfigEdit::UnsetVar(name) => self.update_block(|vars, unsets| {
+                vars.remove(name);
+                unsets.insert(name.clone());
+            }),
+            ConfigEdit::RenameVar { from, to } => self.rename_var(from, to),
+        }
+    }
+
+    /// The `PATH` an environment file sets, or the usual default if it
+    /// doesn't set one.
+    fn environment_path(&self) -> String {
+        self.dialect
+            .parse(&self.contents)
+            .ok()
+            .and_then(|script| {
+                script
+                    .exported_vars()
+                    .into_iter()
+                    .find(|(name, _)| name == "PATH")
+            })
+            .map_or_else(|| DEFAULT_ENVIRONMENT_PATH.to_owned(), |(_, value)| value)
+    }
+
+    /// Byte range of the envelope block, including both marker lines.
+    pub fn managed_block(&self) -> Option<Range<usize>> {
+        let mut offset = 0;
+        let mut start = None;
+        for line in self.contents.split_inclusive('\n') {
+            match line.trim_end() {
+                BLOCK_START if start.is_none() => start = Some(offset),
+                BLOCK_END if start.is_some() => {
+                    return start.map(|start| start..offset + line.len())
+                }
+                _ => {}
+            }
+            offset += line.len();
+        }
+        None
+    }
+
+    /// Variables defined inside the envelope block, keyed by name, with
+    /// whether their values reference other variables.
+    pub fn managed_vars(&self) -> BTreeMap<String, (String, bool)> {
+        let Some(script) = self
+            .managed_block()
+            .and_then(|block| self.dialect.parse(&self.contents[block]).ok())
+        else {
+            return BTreeMap::new();
+        };
+        let expanding: BTreeSet<&str> = script
+            .assignments()
+            .filter(|(_, assignment)| {
+                assignment
+                    .value
+                    .as_ref()
+                    .is_some_and(|value| !value.parameters().is_empty())
+            })
+            .map(|(_, assignment)| assignment.name.as_str())
+            .collect();
+        script
+            .exported_vars()
+            .into_iter()
+            .map(|(name, value)| {
+                let expand = expanding.contains(name.as_str());
+                (name, (value, expand))
+            })
+            .collect()
+    }
+
+    /// Variables the envelope block explicitly unsets.
+    pub fn managed_unsets(&self) -> BTreeSet<String> {
+        self.managed_block()
+            .and_then(|block| self.dialect.parse(&self.contents[block]).ok())
+            .map(|script| {
+                script
+                    .statements
+                    .into_iter()
+                    .flat_map(|statement| match statement.kind {
+                        StatementKind::Unset(names) => names,
+                        _ => Vec::new(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Regenerates the envelope block from its variables and unsets after
+    /// `update` runs, removing it when it becomes empty and creating it at
+    /// the end of the file when missing.
+    fn update_block(
+        &mut self,
+        update: impl FnOnce(&mut BTreeMap<String, (String, bool)>, &mut BTreeSet<String>),
+    ) {
+        let mut vars = self.managed_vars();
+        let mut unsets = self.managed_unsets();
+        update(&mut vars, &mut unsets);
+        let block = if vars.is_empty() && unsets.is_empty() {
+            String::new()
+        } else {
+            let mut block = format!("{}\n{}\n", BLOCK_START, BLOCK_NOTE);
+            for (name, (value, expand)) in &vars {
+                block.push_str(&self.dialect.assignment(name, value, *expand));
+                block.push('\n');
+            }
+            for name in &unsets {
+                block.push_str(&self.dialect.unset(name));
+                block.push('\n');
+            }
+            block.push_str(BLOCK_END);
+            block.push('\n');
+            block
+        };
+        match self.managed_block() {
+            Some(range) => self.contents.replace_range(range, &block),
+            None if block.is_empty() => {}
+            None => {
+                if !self.contents.is_empty() {
+                    if !self.contents.ends_with('\n') {
+                        self.contents.push('\n');
+                    }
+                    self.contents.push('\n');
+                }
+                self.contents.push_str(&block);
+            }
+        }
+    }
+
+    /// The definition outside the envelope block that currently determines
+    /// the value of `name`: the last plain assignment to it, ignoring `+=`
+    /// and function locals.
+    pub fn definition(&self, name: &str) -> Option<Assignment> {
+        let script = self.dialect.parse(&self.contents).ok()?;
+        let block = self.managed_block().unwrap_or_default();
+        script
+            .statements
+            .iter()
+            .filter(|statement| {
+                !matches!(
+                    statement.kind,
+                    StatementKind::Assignment {
+                        command: AssignmentCommand::Local,
+                        ..
+                    }
+                )
+            })
+            .flat_map(|statement| match &statement.kind {
+                StatementKind::Assignment { assignments, .. } => assignments.as_slice(),
+                _ => &[],
+            })
+            .rev()
+            .find(|assignment| {
+                assignment.name == name
+                    && !assignment.append
+                    && assignment.value.is_some()
+                    && !block.contains(&assignment.span.start)
+            })
+            .cloned()
+    }
+
+    /// Sets `name` to `value`, expanding references in it only with
+    /// `expand`. Variables already in the envelope block are updated there;
+    /// otherwise an existing definition is rewritten in place, leaving the
+    /// surrounding text untouched. Variables without a definition are added
+    /// to the block.
+    pub fn set_var(&mut self, name: &str, value: &str, expand: bool) {
+        if self.managed_vars().contains_key(name) || self.managed_unsets().contains(name) {
+            return self.update_block(|vars, unsets| {
+                unsets.remove(name);
+                vars.insert(name.to_owned(), (value.to_owned(), expand));
+            });
+        }
+        match self.definition(name) {
+            Some(Assignment {
+                value: Some(original),
+                value_span,
+                ..
+            }) => {
+                let replacement = self.dialect.replacement_value(&original, value, expand);
+                self.contents.replace_range(value_span, &replacement);
+            }
+            _ => self.update_block(|vars, _| {
+                vars.insert(name.to_owned(), (value.to_owned(), expand));
+            }),
+        }
+    }
+
+    /// Sets `PATH` to `dirs` with a single assignment in the envelope block.
+    /// When `dirs` is `inherited` with directories added before or after
+    /// it, only those are written, around `$PATH`, so changes made to
+    /// `PATH` elsewhere still apply; otherwise the whole list is written.
+    /// `inherited` includes what the block already adds around `$PATH`,
+    /// which is taken off first. When nothing is added the block's `PATH`
+    /// is dropped. Files without `$PATH` always get the whole list.
+    pub fn set_path(&mut self, dirs: &[String], inherited: &[String]) {
+        if matches!(
+            self.dialect,
+            Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv
+        ) {
+            return self.set_var("PATH", &dirs.join(":"), false);
+        }
+        let inherited = match self.managed_vars().get("PATH") {
+            Some((value, true)) => without_additions(inherited, value),
+            _ => inherited,
+        };
+        let value = match additions(dirs, inherited) {
+            Some((before, after)) if before.is_empty() && after.is_empty() => None,
+            Some((before, after)) => {
+                let mut around: Vec<&str> = before.iter().map(String::as_str).collect();
+                around.push("$PATH");
+                around.extend(after.iter().map(String::as_str));
+                Some((around.join(":"), true))
+            }
+            None => Some((dirs.join(":"), false)),
+        };
+        self.update_block(|vars, unsets| {
+            unsets.remove("PATH");
+            match value {
+                Some(value) => vars.insert("PATH".to_owned(), value),
+                None => vars.remove("PATH"),
+            };
+        });
+    }
+
+    /// Whether any statement in the file assigns to `name`, ignoring
+    /// function locals.
+    pub fn defines(&self, name: &str) -> bool {
+        self.dialect
+            .parse(&self.contents)
+            .map(|script| {
+                script
+                    .statements
+                    .iter()
+                    .any(|statement| match &statement.kind {
+                        StatementKind::Assignment {
+                            command,
+                            assignments,
+                            ..
+                        } => {
+                            !matches!(command, AssignmentCommand::Local)
+                                && assignments.iter().any(|assignment| assignment.name == name)
+                        }
+                        _ => false,
+                    })
+            })
+            .unwrap_or(false)
+    }
+
+    /// Removes every definition of `name`. It is dropped from the envelope
+    /// block, and statements elsewhere are commented out when they sit on
+    /// lines of their own, behind a `:` when they are all there is to an
+    /// `if` or function body. A definition sharing a statement with other
+    /// assignments is cut out of it, and one sharing a line with other
+    /// commands is replaced by an explicit unset.
+    pub fn remove_var(&mut self, name: &str) {
+        self.update_block(|vars, _| {
+            vars.remove(name);
+        });
+        let Ok(script) = self.dialect.parse(&self.contents) else {
+            return;
+        };
+        let block = self.managed_block().unwrap_or_default();
+        let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+        let mut commented: Vec<Range<usize>> = Vec::new();
+        for statement in &script.statements {
+            let StatementKind::Assignment {
+                command,
+                assignments,
+                ..
+            } = &statement.kind
+            else {
+                continue;
+            };
+            if matches!(command, AssignmentCommand::Local) || block.contains(&statement.span.start)
+            {
+                continue;
+            }
+            let matching: Vec<&Assignment> = assignments
+                .iter()
+                .filter(|assignment| assignment.name == name)
+                .collect();
+            if matching.is_empty() {
+                continue;
+            }
+            if matching.len() < assignments.len() {
+                for assignment in matching {
+                    // The first word of a statement takes the space after it
+                    // along, the others the space before.
+                    let range = if assignment.span.start == statement.span.start {
+                        let rest = &self.contents[assignment.span.end..];
+                        let space = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+                        assignment.span.start..assignment.span.end + space
+                    } else {
+                        let start = self.contents[..assignment.span.start]
+                            .trim_end_matches([' ', '\t'])
+                            .len();
+                        start..assignment.span.end
+                    };
+                    edits.push((range, String::new()));
+                }
+            } else if let Some(lines) = self.whole_lines(&statement.span) {
+                commented.push(lines);
+            } else {
+                edits.push((statement.span.clone(), self.dialect.unset(name)));
+            }
+        }
+        for lines in &commented {
+            let text = &self.contents[lines.clone()];
+            // sh refuses a body left empty, so `:` stands in for it.
+            if self.sole_body(lines, &commented) {
+                let indent = lines.start + text.len() - text.trim_start().len();
+                edits.push((indent..indent, ": # ".to_owned()));
+            } else {
+                edits.push((lines.start..lines.start, "# ".to_owned()));
+            }
+            for (i, _) in text.match_indices('\n') {
+                let start = lines.start + i + 1;
+                edits.push((start..start, "# ".to_owned()));
+            }
+        }
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for (range, replacement) in edits {
+            self.contents.replace_range(range, &replacement);
+        }
+    }
+
+    /// Byte ranges of every occurrence of `name` as a variable: assignments
+    /// to it, unsets of it and `$name`/`${name}` references in words.
+    /// Single-quoted text, comments and heredocs are left alone.
+    pub fn name_occurrences(&self, name: &str) -> Vec<Range<usize>> {
+        let Ok(script) = self.dialect.parse(&self.contents) else {
+            return Vec::new();
+        };
+        let mut ranges = Vec::new();
+        let scan_word = |word: &Word, ranges: &mut Vec<Range<usize>>| {
+            let text = &self.contents[word.span.clone()];
+            ranges.extend(
+                references(text, name)
+                    .into_iter()
+                    .map(|range| word.span.start + range.start..word.span.start + range.end),
+            );
+        };
+        for statement in &script.statements {
+            match &statement.kind {
+                StatementKind::Assignment { assignments, .. } => {
+                    for assignment in assignments {
+                        let start = assignment.span.start;
+                        if starts_with_name(&self.contents[start..], name) {
+                            ranges.push(start..start + name.len());
+                        }
+                        for word in assignment.value.iter().flat_map(Value::words) {
+                            scan_word(word, &mut ranges);
+                        }
+                    }
+                }
+                StatementKind::Unset(names) if names.iter().any(|unset| unset == name) => {
+                    let text = &self.contents[statement.span.clone()];
+                    ranges.extend(
+                        text.match_indices(name)
+                            .map(|(i, _)| statement.span.start + i)
+                            .filter(|&start| {
+                                !self.contents[..start].ends_with(is_name_char)
+                                    && starts_with_name(&self.contents[start..], name)
+                            })
+                            .map(|start| start..start + name.len()),
+                    );
+                }
+                StatementKind::Source(word) => scan_word(word, &mut ranges),
+                StatementKind::Command(words) => {
+                    for word in words {
+                        scan_word(word, &mut ranges);
+                    }
+                }
+                _ => {}
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        ranges.dedup();
+        ranges
+    }
+
+    /// Renames the variable `from` to `to` in every assignment, unset and
+    /// reference.
+    pub fn rename_var(&mut self, from: &str, to: &str) {
+        for range in self.name_occurrences(from).into_iter().rev() {
+            self.contents.replace_range(range, to);
+        }
+        if self.managed_block().is_some() {
+            // Keep the block sorted under the new name.
+            self.update_block(|_, _| {});
+        }
+    }
+
+    /// The lines `span` covers, if nothing but whitespace, a `;` or a
+    /// comment shares them.
+    fn whole_lines(&self, span: &Range<usize>) -> Option<Range<usize>> {
+        let start = self.contents[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.contents[span.end..]
+            .find('\n')
+            .map_or(self.contents.len(), |i| span.end + i);
+        let before = self.contents[start..span.start].trim();
+        let after = self.contents[span.end..end].trim_start();
+        let after = after.strip_prefix(';').unwrap_or(after).trim_start();
+        (before.is_empty() && (after.is_empty() || after.starts_with('#'))).then_some(start..end)
+    }
+
+    /// Whether the statement on `lines` is all there is between a compound
+    /// command's keywords, as in `then`…`fi` or `{`…`}`, once the other
+    /// `commented` lines are gone. Only sh-like dialects mind an empty body.
+    fn sole_body(&self, lines: &Range<usize>, commented: &[Range<usize>]) -> bool {
+        if !matches!(self.dialect, Dialect::Posix | Dialect::Zsh | Dialect::Ksh) {
+            return false;
+        }
+        let code = |start: usize, line: &'_ str| {
+            let line = line.trim();
+            !line.is_empty()
+                && !line.starts_with('#')
+                && !commented.iter().any(|range| range.contains(&start))
+        };
+        let mut previous = None;
+        let mut end = lines.start;
+        while end > 0 {
+            let start = self.contents[..end - 1].rfind('\n').map_or(0, |i| i + 1);
+            let line = &self.contents[start..end - 1];
+            if code(start, line) {
+                previous = Some(line);
+                break;
+            }
+            end = start;
+        }
+        let mut next = None;
+        let mut start = lines.end + 1;
+        while start < self.contents.len() {
+            let end = self.contents[start..]
+                .find('\n')
+                .map_or(self.contents.len(), |i| start + i);
+            let line = &self.contents[start..end];
+            if code(start, line) {
+                next = Some(line);
+                break;
+            }
+            start = end + 1;
+        }
+        let (Some(previous), Some(next)) = (previous, next) else {
+            return false;
+        };
+        let previous = previous.split(" #").next().unwrap_or_default().trim_end();
+        let last = previous
+            .rsplit(|c: char| c.is_whitespace() || c == ';')
+            .next()
+            .unwrap_or_default();
+        let opens = matches!(last, "then" | "do" | "else" | "{" | "(") || last.ends_with(')');
+        let next = next.trim_start();
+        let first = next
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .next()
+            .unwrap_or_default();
+        let closes = matches!(first, "fi" | "done" | "else" | "elif" | "esac")
+            || next.starts_with(['}', ')'])
+            || next.starts_with(";;");
+        opens && closes
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Whether `text` starts with the whole variable name `name`.
+fn starts_with_name(text: &str, name: &str) -> bool {
+    text.strip_prefix(name)
+        .is_some_and(|rest| !rest.starts_with(is_name_char))
+}
+
+/// Ranges of the name in each `$name` or `${name...}` expansion in `text`,
+/// which is the source of a single word.
+fn references(text: &str, name: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let (mut single, mut double) = (false, false);
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if !single => {
+                chars.next();
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '$' if !single => {
+                let rest = &text[i + 1..];
+                if starts_with_name(rest, name) {
+                    ranges.push(i + 1..i + 1 + name.len());
+                } else if let Some(braced) = rest.strip_prefix('{') {
+                    let prefix = usize::from(braced.starts_with(['#', '!']));
+                    let after = braced[prefix..].strip_prefix(name);
+                    if after.is_some_and(|after| {
+                        after.starts_with(['}', ':', '-', '=', '+', '?', '#', '%', '/', '['])
+                    }) {
+                        let start = i + 2 + prefix;
+                        ranges.push(start..start + name.len());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// The directories `dirs` has before and after a run of exactly
+/// `inherited`, if it has one and they can be written around `$PATH`
+/// without any of them expanding.
+fn additions<'a>(dirs: &'a [String], inherited: &[String]) -> Option<(&'a [String], &'a [String])> {
+    if inherited.is_empty() || dirs.len() < inherited.len() {
+        return None;
+    }
+    let start = (0..=dirs.len() - inherited.len())
+        .find(|&start| dirs[start..start + inherited.len()] == *inherited)?;
+    let (before, rest) = dirs.split_at(start);
+    let after = &rest[inherited.len()..];
+    let literal = |dirs: &[String]| !dirs.iter().any(|dir| dir.contains('$'));
+    (literal(before) && literal(after)).then_some((before, after))
+}
+
+/// `inherited` without the directories `value`, a `PATH` written around
+/// `$PATH`, adds before and after it, when it starts and ends with them.
+fn without_additions<'a>(inherited: &'a [String], value: &str) -> &'a [String] {
+    let dirs: Vec<&str> = value.split(':').filter(|dir| !dir.is_empty()).collect();
+    let mut around = dirs.split(|&dir| dir == "$PATH");
+    let (Some(before), Some(after), None) = (around.next(), around.next(), around.next()) else {
+        return inherited;
+    };
+    let stripped = inherited.len() >= before.len() + after.len()
+        && inherited
+            .iter()
+            .zip(before)
+            .all(|(dir, added)| dir == added)
+        && inherited
+            .iter()
+            .rev()
+            .zip(after.iter().rev())
+            .all(|(dir, added)| dir == added);
+    match stripped {
+        true => &inherited[before.len()..inherited.len() - after.len()],
+        false => inherited,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(dialect: Dialect, contents: &str, name: &str, value: &str) -> String {
+        let mut config = ConfigFile::new("rc", dialect, contents);
+        config.set_var(name, value, false);
+        config.contents
+    }
+
+    #[test]
+    fn test_set_var_rewrites_in_place() {
+        let contents = "# editor\n  export EDITOR=vi # keep me\nexport A=1 B=2\n";
+        assert_eq!(
+            set(Dialect::Posix, contents, "EDITOR", "nvim"),
+            "# editor\n  export EDITOR='nvim' # keep me\nexport A=1 B=2\n"
+        );
+        assert_eq!(
+            set(Dialect::Posix, contents, "B", "3"),
+            "# editor\n  export EDITOR=vi # keep me\nexport A=1 B='3'\n"
+        );
+    }
+
+    #[test]
+    fn test_set_var_targets_last_definition() {
+        let contents = "export A=1\nexport A=2\nexport A+=:x\nlocal_fn() { local A=3; }\n";
+        assert_eq!(
+            set(Dialect::Posix, contents, "A", "new"),
+            "export A=1\nexport A='new'\nexport A+=:x\nlocal_fn() { local A=3; }\n"
+        );
+    }
+
+    fn block(lines: &str) -> String {
+        format!("{}\n{}\n{}{}\n", BLOCK_START, BLOCK_NOTE, lines, BLOCK_END)
+    }
+
+    #[test]
+    fn test_set_var_adds_new_variable_to_block() {
+        assert_eq!(
+            set(Dialect::Posix, "export A=1", "B", "2"),
+            format!("export A=1\n\n{}", block("export B='2'\n"))
+        );
+        assert_eq!(set(Dialect::Fish, "", "B", "2"), block("set -gx B '2'\n"));
+    }
+
+    #[test]
+    fn test_block_is_regenerated_deterministically() {
+        let mut config = ConfigFile::new("rc", Dialect::Posix, "# top\n");
+        config.set_var("ZED", "z", false);
+        config.set_var("ALPHA", "a", false);
+        config.set_var("ZED", "zz", false);
+        config.contents.push_str("# bottom\n");
+        config.set_var("ALPHA", "aa", false);
+        assert_eq!(
+            config.contents,
+            format!(
+                "# top\n\n{}# bottom\n",
+                block("export ALPHA='aa'\nexport ZED='zz'\n")
+            )
+        );
+        assert_eq!(
+            config.managed_vars().into_iter().collect::<Vec<_>>(),
+            vec![
+                ("ALPHA".to_owned(), ("aa".to_owned(), false)),
+                ("ZED".to_owned(), ("zz".to_owned(), false))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_takes_precedence_over_outside_definition() {
+        let contents = format!("export A=1\n{}", block("export A='2'\n"));
+        assert_eq!(
+            set(Dialect::Posix, &contents, "A", "3"),
+            format!("export A=1\n{}", block("export A='3'\n"))
+        );
+    }
+
+    #[test]
+    fn test_set_var_keeps_dialect_shape() {
+        assert_eq!(
+            set(Dialect::Zsh, "path=(/a /b)\n", "PATH", "/c:/d"),
+            "path=('/c' '/d')\n"
+        );
+        assert_eq!(
+            set(Dialect::Csh, "setenv EDITOR vi\n", "EDITOR", "nvim"),
+            "setenv EDITOR 'nvim'\n"
+        );
+        assert_eq!(
+            set(Dialect::Csh, "set path = ( /a $path )\n", "PATH", "/c:/d"),
+            "set path = ( '/c' '/d' )\n"
+        );
+        assert_eq!(
+            set(Dialect::Fish, "set -gx PATH /a $PATH\n", "PATH", "/c:/d"),
+            "set -gx PATH '/c' '/d'\n"
+        );
+    }
+
+    fn remove(dialect: Dialect, contents: &str, name: &str) -> String {
+        let mut config = ConfigFile::new("rc", dialect, contents);
+        config.remove_var(name);
+        config.contents
+    }
+
+    #[test]
+    fn test_remove_var_comments_out_and_cuts_definitions() {
+        let contents = "export A=1 # note\n  A=2\nexport B=2 A=3\nA=0 C=1\nA=4; echo hi\nlocal_fn() { local A=5; }\n";
+        assert_eq!(
+            remove(Dialect::Posix, contents, "A"),
+            "# export A=1 # note\n#   A=2\nexport B=2\nC=1\nunset A; echo hi\nlocal_fn() { local A=5; }\n"
+        );
+        assert_eq!(
+            remove(Dialect::Fish, "set -gx A 1\n", "A"),
+            "# set -gx A 1\n"
+        );
+
+        let contents = "A=0 B=1 A=2 C=3\nexport A=0 B=1\n";
+        assert_eq!(
+            remove(Dialect::Posix, contents, "A"),
+            "B=1 C=3\nexport B=1\n"
+        );
+
+        let contents = format!("export A=1\n{}", block("export A='2'\nexport B='3'\n"));
+        assert_eq!(
+            remove(Dialect::Posix, &contents, "A"),
+            format!("# export A=1\n{}", block("export B='3'\n"))
+        );
+    }
+
+    #[test]
+    fn test_fish_queries_and_locals_are_left_alone() {
+        let contents = "set -gx EDITOR vim\nif not set -q EDITOR\n    set -gx EDITOR vi\nend\nset --query EDITOR; or echo unset\nfunction f\n    set -lx EDITOR nano\nend\n";
+        assert_eq!(
+            set(Dialect::Fish, contents, "EDITOR", "nvim"),
+            contents.replace("EDITOR vi\n", "EDITOR 'nvim'\n")
+        );
+        assert_eq!(
+            remove(Dialect::Fish, contents, "EDITOR"),
+            contents
+                .replace("set -gx EDITOR vim", "# set -gx EDITOR vim")
+                .replace("    set -gx EDITOR vi\n", "#     set -gx EDITOR vi\n")
+        );
+        let config = ConfigFile::new(
+            "rc",
+            Dialect::Fish,
+            "function f\n    set -l A 1\nend\nset -q A\n",
+        );
+        assert!(!config.defines("A"));
+    }
+
+    #[test]
+    fn test_remove_var_keeps_bodies_from_going_empty() {
+        let contents = concat!(
+            "if [ -d /opt ]; then\n",
+            "  export A=1\n",
+            "fi\n",
+            "f() {\n",
+            "  A=2\n",
+            "  # kept\n",
+            "  A=3\n",
+            "}\n",
+            "g() {\n",
+            "  A=4\n",
+            "  echo g\n",
+            "}\n",
+            "case $x in\n",
+            "  y)\n",
+            "    A=5\n",
+            "    ;;\n",
+            "esac\n",
+            "A=6\n",
+        );
+        assert_eq!(
+            remove(Dialect::Posix, contents, "A"),
+            concat!(
+                "if [ -d /opt ]; then\n",
+                "  : # export A=1\n",
+                "fi\n",
+                "f() {\n",
+                "  : # A=2\n",
+                "  # kept\n",
+                "  : # A=3\n",
+                "}\n",
+                "g() {\n",
+                "#   A=4\n",
+                "  echo g\n",
+                "}\n",
+                "case $x in\n",
+                "  y)\n",
+                "    : # A=5\n",
+                "    ;;\n",
+                "esac\n",
+                "# A=6\n",
+            )
+        );
+
+        let contents = "if test -d /opt\n  set -gx A 1\nend\n";
+        assert_eq!(
+            remove(Dialect::Fish, contents, "A"),
+            "if test -d /opt\n#   set -gx A 1\nend\n"
+        );
+    }
+
+    #[test]
+    fn test_unset_var_is_kept_in_block() {
+        let mut config = ConfigFile::new("rc", Dialect::Csh, "");
+        config.apply(&ConfigEdit::UnsetVar("PAGER".to_owned()));
+        assert_eq!(config.contents, block("unsetenv PAGER\n"));
+        assert!(config.managed_unsets().contains("PAGER"));
+
+        config.set_var("PAGER", "less", false);
+        assert_eq!(config.contents, block("setenv PAGER 'less'\n"));
+    }
+
+    #[test]
+    fn test_rename_var_updates_definitions_and_references() {
+        let contents = concat!(
+            "export FOO_URL=http://a # $FOO_URL in a comment\n",
+            "echo \"$FOO_URL\" ${FOO_URL:-x} '$FOO_URL' $FOO_URLS\n",
+            "BAR=\"${FOO_URL}/api\"\n",
+            "unset FOO_URL\n",
+        );
+        let mut config = ConfigFile::new("rc", Dialect::Posix, contents);
+        config.apply(&ConfigEdit::RenameVar {
+            from: "FOO_URL".to_owned(),
+            to: "FOO_ENDPOINT".to_owned(),
+        });
+        assert_eq!(
+            config.contents,
+            concat!(
+                "export FOO_ENDPOINT=http://a # $FOO_URL in a comment\n",
+                "echo \"$FOO_ENDPOINT\" ${FOO_ENDPOINT:-x} '$FOO_URL' $FOO_URLS\n",
+                "BAR=\"${FOO_ENDPOINT}/api\"\n",
+                "unset FOO_ENDPOINT\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_rename_var_resorts_block() {
+        let contents = format!("setenv A $ZED\n{}", block("setenv B '1'\nsetenv ZED '2'\n"));
+        let mut config = ConfigFile::new("rc", Dialect::Csh, contents);
+        config.rename_var("ZED", "ALPHA");
+        assert_eq!(
+            config.contents,
+            format!(
+                "setenv A $ALPHA\n{}",
+                block("setenv ALPHA '2'\nsetenv B '1'\n")
+            )
+        );
+    }
+
+    #[test]
+    fn test_apply_append_path() {
+        let mut config = ConfigFile::new("rc", Dialect::Posix, "export A=1\n");
+        config.apply(&ConfigEdit::AppendPath("/opt/bin".to_owned()));
+        config.apply(&ConfigEdit::AppendPath("/usr/local/go/bin".to_owned()));
+        assert_eq!(
+            config.contents,
+            format!(
+                "export A=1\n\n{}",
+                block("export PATH=\"$PATH:/opt/bin:/usr/local/go/bin\"\n")
+            )
+        );
+
+        let mut config = ConfigFile::new("rc", Dialect::Zsh, "");
+        config.apply(&ConfigEdit::AppendPath("/opt/bin".to_owned()));
+        assert_eq!(config.contents, block("path=($path '/opt/bin')\n"));
+    }
+
+    #[test]
+    fn test_apply_append_path_writes_environment_path_out() {
+        let mut config =
+            ConfigFile::new("environment", Dialect::Environment, "PATH=/usr/bin:/bin\n");
+        config.apply(&ConfigEdit::AppendPath("/opt".to_owned()));
+        config.apply(&ConfigEdit::AppendPath("/opt/go bin".to_owned()));
+        assert_eq!(
+            config.contents,
+            format!(
+                "PATH=/usr/bin:/bin\n\n{}",
+                block("PATH=\"/usr/bin:/bin:/opt:/opt/go bin\"\n")
+            )
+        );
+
+        let mut config = ConfigFile::new("environment", Dialect::Environment, "LANG=C\n");
+        config.apply(&ConfigEdit::AppendPath("/opt".to_owned()));
+        assert_eq!(
+            config.contents,
+            format!(
+                "LANG=C\n\n{}",
+                block(&format!("PATH={}:/opt\n", DEFAULT_ENVIRONMENT_PATH))
+            )
+        );
+    }
+
+    #[test]
+    fn test_check_refuses_control_characters_in_environment_files() {
+        let injected = ConfigEdit::SetVar {
+            name: "A".to_owned(),
+            value: "a\nLD_PRELOAD=/evil.so".to_owned(),
+            expand: false,
+        };
+        for dialect in [Dialect::Environment, Dialect::EnvironmentD] {
+            assert!(matches!(
+                injected.check(dialect),
+                Err(Error::ControlCharacters(name)) if name == "A"
+            ));
+            assert!(matches!(
+                ConfigEdit::AppendPath("/opt\tbin".to_owned()).check(dialect),
+                Err(Error::ControlCharacters(name)) if name == "PATH"
+            ));
+            assert!(matches!(
+                ConfigEdit::SetPath {
+                    dirs: vec!["/bin".to_owned(), "/x\r".to_owned()],
+                    inherited: Vec::new(),
+                }
+                .check(dialect),
+                Err(Error::ControlCharacters(_))
+            ));
+        }
+        assert!(injected.check(Dialect::Posix).is_ok());
+        assert!(ConfigEdit::AppendPath("/opt".to_owned())
+            .check(Dialect::Environment)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_set_path_writes_additions_around_path_or_whole_list() {
+        let strings = |dirs: &[&str]| dirs.iter().map(|&dir| dir.to_owned()).collect::<Vec<_>>();
+        let set_path = |config: &mut ConfigFile, dirs: &[&str], inherited: &[&str]| {
+            config.apply(&ConfigEdit::SetPath {
+                dirs: strings(dirs),
+                inherited: strings(inherited),
+            })
+        };
+        let inherited = ["/usr/bin", "/bin"];
+
+        let mut config = ConfigFile::new("rc", Dialect::Posix, "");
+        set_path(
+            &mut config,
+            &["/opt/bin", "/usr/bin", "/bin", "/x"],
+            &inherited,
+        );
+        assert_eq!(
+            config.contents,
+            block("export PATH=\"/opt/bin:$PATH:/x\"\n")
+        );
+        set_path(&mut config, &["/bin", "/usr/bin"], &inherited);
+        assert_eq!(config.contents, block("export PATH='/bin:/usr/bin'\n"));
+        set_path(&mut config, &inherited, &inherited);
+        assert_eq!(config.contents, "");
+
+        // Started again with the block in effect, its additions are part of
+        // the inherited PATH.
+        let mut config = ConfigFile::new("rc", Dialect::Fish, "");
+        set_path(&mut config, &["/opt/bin", "/usr/bin", "/bin"], &inherited);
+        assert_eq!(config.contents, block("set -gx PATH '/opt/bin' $PATH\n"));
+        let inherited = ["/opt/bin", "/usr/bin", "/bin"];
+        set_path(
+            &mut config,
+            &["/opt/bin", "/usr/bin", "/bin", "/y"],
+            &inherited,
+        );
+        assert_eq!(
+            config.contents,
+            block("set -gx PATH '/opt/bin' $PATH '/y'\n")
+        );
+
+        let mut config = ConfigFile::new("rc", Dialect::Csh, "");
+        set_path(
+            &mut config,
+            &["/a b", "/usr/bin", "/bin"],
+            &["/usr/bin", "/bin"],
+        );
+        assert_eq!(config.contents, block("set path = ( '/a b' $path )\n"));
+
+        let mut config = ConfigFile::new(".env", Dialect::Dotenv, "PATH=/usr/bin\n");
+        set_path(&mut config, &["/x", "/usr/bin"], &["/usr/bin"]);
+        assert_eq!(config.contents, "PATH=/x:/usr/bin\n");
+    }
+
+    #[test]
+    fn test_set_path_falls_back_to_the_whole_list() {
+        let strings = |dirs: &[&str]| dirs.iter().map(|&dir| dir.to_owned()).collect::<Vec<_>>();
+        let set_path = |dialect: Dialect, dirs: &[&str], inherited: &[&str]| {
+            let mut config = ConfigFile::new("rc", dialect, "");
+            config.apply(&ConfigEdit::SetPath {
+                dirs: strings(dirs),
+                inherited: strings(inherited),
+            });
+            config.contents
+        };
+
+        // No inherited PATH, inherited directories split up or dropped, and
+        // a directory that looks like a reference all need the full list.
+        for (dirs, inherited) in [
+            (&["/a"][..], &[][..]),
+            (&["/usr/bin", "/a", "/bin"], &["/usr/bin", "/bin"]),
+            (&["/bin"], &["/usr/bin", "/bin"]),
+        ] {
+            assert_eq!(
+                set_path(Dialect::Posix, dirs, inherited),
+                block(&format!("export PATH='{}'\n", dirs.join(":")))
+            );
+        }
+        assert_eq!(
+            set_path(Dialect::Posix, &["/$HOME", "/bin"], &["/bin"]),
+            block("export PATH='/$HOME:/bin'\n")
+        );
+        assert_eq!(
+            set_path(Dialect::Environment, &["/a", "/bin"], &["/bin"]),
+            block("PATH=/a:/bin\n")
+        );
+
+        // A block PATH that isn't written around `$PATH` once leaves the
+        // inherited directories as they are.
+        let inherited = strings(&["/a", "/bin"]);
+        assert_eq!(without_additions(&inherited, "/a:/bin"), inherited);
+        assert_eq!(without_additions(&inherited, "/a:$PATH:$PATH"), inherited);
+        assert_eq!(without_additions(&inherited, "/x:$PATH"), inherited);
+        assert_eq!(without_additions(&inherited, "/a:$PATH"), &inherited[1..]);
+    }
+}
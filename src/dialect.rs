@@ -0,0 +1,1268 @@
+use crate::parser::{
+    self, is_name, simple_commands, split_assignment_word, Assignment, AssignmentCommand,
+    ParseError, Script, SimpleCommand, Statement, StatementKind, Value, Word, WordPart,
+};
+use crate::quote::shell_word;
+use std::ops::Range;
+use std::path::Path;
+
+/// Shell syntaxes that envelope can read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    /// sh, bash and dash.
+    Posix,
+    Zsh,
+    Ksh,
+    /// csh and tcsh.
+    Csh,
+    Fish,
+    /// pam_env's `/etc/environment`: `KEY=value` lines without expansion.
+    Environment,
+    /// systemd `environment.d` drop-ins: `KEY=value` lines with `$VAR`,
+    /// `${VAR}`, `${VAR:-default}` and `${VAR:+alternate}` expanded.
+    EnvironmentD,
+    /// Project `.env` files, as read by dotenv libraries.
+    Dotenv,
+}
+
+/// `PATH` as Debian's `/etc/environment` sets it, assumed for one that
+/// doesn't set it yet, since pam_env can't extend the `PATH` it inherits.
+pub const DEFAULT_ENVIRONMENT_PATH: &str =
+    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// zsh and csh arrays that are tied to a colon-separated environment variable.
+const TIED_ARRAYS: &[(&str, &str)] = &[
+    ("path", "PATH"),
+    ("manpath", "MANPATH"),
+    ("fpath", "FPATH"),
+    ("cdpath", "CDPATH"),
+    ("mailpath", "MAILPATH"),
+];
+
+impl Dialect {
+    /// The dialect of a configuration file, judged by its name.
+    pub fn from_config_name(name: &str) -> Dialect {
+        let in_environment_d = Path::new(name)
+            .parent()
+            .is_some_and(|dir| dir.ends_with("environment.d"));
+        let file_name = Path::new(name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(name);
+        match file_name {
+            ".zshrc" | ".zshenv" | ".zprofile" | ".zlogin" | ".zlogout" => Dialect::Zsh,
+            ".kshrc" => Dialect::Ksh,
+            ".cshrc" | ".tcshrc" | ".login" | ".logout" => Dialect::Csh,
+            name if name.ends_with(".fish") => Dialect::Fish,
+            "environment" => Dialect::Environment,
+            name if name.ends_with(".conf") && in_environment_d => Dialect::EnvironmentD,
+            ".env" => Dialect::Dotenv,
+            name if name.starts_with(".env.") || name.ends_with(".env") => Dialect::Dotenv,
+            _ => Dialect::Posix,
+        }
+    }
+
+    /// The dialect spoken by a shell executable such as `/bin/zsh`.
+    pub fn from_shell(shell: &str) -> Option<Dialect> {
+        let name = Path::new(shell).file_name()?.to_str()?;
+        match name.trim_start_matches('-') {
+            "sh" | "bash" | "dash" | "ash" => Some(Dialect::Posix),
+            "zsh" => Some(Dialect::Zsh),
+            "ksh" | "ksh93" | "mksh" | "pdksh" => Some(Dialect::Ksh),
+            "csh" | "tcsh" => Some(Dialect::Csh),
+            "fish" => Some(Dialect::Fish),
+            _ => None,
+        }
+    }
+
+    /// The interactive configuration file, relative to `$HOME`.
+    pub fn config_file(self) -> &'static str {
+        match self {
+            Dialect::Posix => ".bashrc",
+            Dialect::Zsh => ".zshrc",
+            Dialect::Ksh => ".kshrc",
+            Dialect::Csh => ".cshrc",
+            Dialect::Fish => ".config/fish/config.fish",
+            Dialect::Environment => "/etc/environment",
+            Dialect::EnvironmentD => ".config/environment.d/60-envelope.conf",
+            Dialect::Dotenv => ".env",
+        }
+    }
+
+    /// Every file, relative to `$HOME`, that the shell reads at startup.
+    pub fn startup_files(self) -> &'static [&'static str] {
+        match self {
+            Dialect::Posix => &[".profile", ".bash_profile", ".bash_login", ".bashrc"],
+            Dialect::Zsh => &[".zshenv", ".zprofile", ".zshrc", ".zlogin"],
+            Dialect::Ksh => &[".profile", ".kshrc"],
+            Dialect::Csh => &[".cshrc", ".tcshrc", ".login"],
+            Dialect::Fish => &[".config/fish/config.fish"],
+            Dialect::Environment => &["/etc/environment"],
+            Dialect::EnvironmentD => &[".config/environment.d/60-envelope.conf"],
+            Dialect::Dotenv => &[".env"],
+        }
+    }
+
+    /// Parses a configuration file written in this dialect.
+    pub fn parse(self, source: &str) -> Result<Script, ParseError> {
+        match self {
+            Dialect::Posix | Dialect::Ksh => parser::parse(source),
+            Dialect::Zsh => parser::parse(source).map(tie_zsh_arrays),
+            Dialect::Csh => parse_csh(source),
+            Dialect::Fish => parse_fish(source),
+            Dialect::Environment => Ok(parse_environment(source, false)),
+            Dialect::EnvironmentD => Ok(parse_environment(source, true)),
+            Dialect::Dotenv => Ok(parse_dotenv(source)),
+        }
+    }
+
+    /// `value` as a single word this dialect reads back unchanged. In the
+    /// shells, references to other variables are expanded only with
+    /// `expand`, and nothing in the value can run a command.
+    pub fn quote(self, value: &str, expand: bool) -> String {
+        match self {
+            // systemd reads `$$` as a `$` that doesn't start a reference.
+            Dialect::EnvironmentD if !expand => environment_value(&value.replace('$', "$$")),
+            Dialect::Environment | Dialect::EnvironmentD => environment_value(value),
+            Dialect::Dotenv => dotenv_value(value, expand),
+            _ => shell_word(self, value, expand),
+        }
+    }
+
+    /// A statement that exports `name` with `value`, quoted as `quote` does.
+    pub fn assignment(self, name: &str, value: &str, expand: bool) -> String {
+        let is_path = name == "PATH";
+        let quoted = self.quote(value, expand);
+        match self {
+            Dialect::Posix => format!("export {}={}", name, quoted),
+            Dialect::Ksh => format!("typeset -x {}={}", name, quoted),
+            Dialect::Zsh if is_path => format!("path=({})", self.quoted_list(value, expand)),
+            Dialect::Zsh => format!("export {}={}", name, quoted),
+            Dialect::Csh if is_path => {
+                format!("set path = ( {} )", self.quoted_list(value, expand))
+            }
+            Dialect::Csh => format!("setenv {} {}", name, quoted),
+            Dialect::Fish if is_path => {
+                format!("set -gx PATH {}", self.quoted_list(value, expand))
+            }
+            Dialect::Fish => format!("set -gx {} {}", name, quoted),
+            Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv => {
+                format!("{}={}", name, quoted)
+            }
+        }
+    }
+
+    /// Text that replaces the value of an existing definition, keeping the
+    /// shape (scalar, list or array) the definition was written in.
+    pub fn replacement_value(self, original: &Value, value: &str, expand: bool) -> String {
+        let (list, open, close) = match (self, original) {
+            (Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv, _)
+            | (_, Value::Scalar(_)) => return self.quote(value, expand),
+            (_, Value::List(_)) => (self.quoted_list(value, expand), "(", ")"),
+            (_, Value::Array(_)) => (self.quoted_words(value, expand), "(", ")"),
+        };
+        match self {
+            Dialect::Fish => list,
+            Dialect::Csh => format!("{} {} {}", open, list, close),
+            _ => format!("{}{}{}", open, list, close),
+        }
+    }
+
+    /// A statement that removes `name` from the environment.
+    pub fn unset(self, name: &str) -> String {
+        match self {
+            Dialect::Csh => format!("unsetenv {}", name),
+            // There is no unset syntax; commenting out is the closest thing.
+            Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv => {
+                format!("# {} unset by envelope", name)
+            }
+            Dialect::Fish => format!("set -e {}", name),
+            _ => format!("unset {}", name),
+        }
+    }
+
+    /// A statement that appends `dir` to `PATH`. The directory is taken
+    /// literally. pam_env can't expand `$PATH`, so `/etc/environment` gets
+    /// `current`, the `PATH` it sets now, written out.
+    pub fn path_append(self, dir: &str, current: &str) -> String {
+        let quoted = self.quote(dir, false);
+        match self {
+            Dialect::Posix => format!("export PATH=\"$PATH\":{}", quoted),
+            Dialect::Ksh => format!("typeset -x PATH=\"$PATH\":{}", quoted),
+            Dialect::Zsh => format!("path+=({})", quoted),
+            Dialect::Csh => format!("set path = ( $path {} )", quoted),
+            Dialect::Fish => format!("set -gx PATH $PATH {}", quoted),
+            Dialect::Environment => {
+                format!(
+                    "PATH={}",
+                    environment_value(&format!("{}:{}", current, dir))
+                )
+            }
+            Dialect::EnvironmentD => format!("PATH=$PATH:{}", environment_value(dir)),
+            Dialect::Dotenv => format!("PATH={}", dotenv_value(&format!("$PATH:{}", dir), true)),
+        }
+    }
+
+    /// Writes a colon-separated value as list elements. With `expand`, a
+    /// reference to a whole list variable such as `$PATH` is spliced in
+    /// unquoted, spelled as the tied array (`$path`) where the dialect has
+    /// one.
+    fn quoted_list(self, value: &str, expand: bool) -> String {
+        value
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| {
+                let tied = dir.strip_prefix('$').filter(|_| expand).and_then(|name| {
+                    TIED_ARRAYS
+                        .iter()
+                        .find(|(_, scalar)| *scalar == name)
+                        .map(|(array, _)| *array)
+                });
+                match (self, tied) {
+                    (Dialect::Zsh | Dialect::Csh, Some(array)) => format!("${}", array),
+                    (_, Some(_)) => dir.to_owned(),
+                    _ => self.quote(dir, expand),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Writes a space-separated value as array elements.
+    fn quoted_words(self, value: &str, expand: bool) -> String {
+        value
+            .split_whitespace()
+            .map(|word| self.quote(word, expand))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn tied_name(name: &str) -> Option<&'static str> {
+    TIED_ARRAYS
+        .iter()
+        .find(|(array, _)| *array == name)
+        .map(|(_, scalar)| *scalar)
+}
+
+/// Rewrites zsh's tied arrays (`path=(...)`) as assignments to the
+/// environment variable they mirror.
+fn tie_zsh_arrays(mut script: Script) -> Script {
+    for statement in &mut script.statements {
+        if let StatementKind::Assignment {
+            command,
+            exported,
+            assignments,
+            ..
+        } = &mut statement.kind
+        {
+            for assignment in assignments.iter_mut() {
+                let Some(scalar) = tied_name(&assignment.name) else {
+                    continue;
+                };
+                assignment.name = scalar.to_owned();
+                assignment.value = match assignment.value.take() {
+                    Some(Value::Array(words)) => Some(Value::List(tie_parameters(words))),
+                    Some(Value::Scalar(word)) => Some(Value::List(vec![word])),
+                    value => value,
+                };
+                if *command == AssignmentCommand::Bare && matches!(scalar, "PATH" | "MANPATH") {
+                    *exported = true;
+                }
+            }
+        }
+    }
+    script
+}
+
+/// Renames elements such as `$path` inside a tied list to the scalar they
+/// mirror, so the list reads back as `$PATH:...`.
+fn tie_parameters(mut words: Vec<Word>) -> Vec<Word> {
+    for word in &mut words {
+        if let [WordPart::Parameter { name, .. }] = word.parts.as_mut_slice() {
+            if let Some(scalar) = tied_name(name) {
+                *name = scalar.to_owned();
+            }
+        }
+    }
+    words
+}
+
+fn literal(word: &Word) -> Option<String> {
+    word.is_static().then(|| word.to_literal())
+}
+
+fn synthetic_parameter(name: &str) -> Word {
+    Word {
+        parts: vec![WordPart::Parameter {
+            name: name.to_owned(),
+            modifier: None,
+            braced: false,
+        }],
+        span: 0..0,
+    }
+}
+
+fn statement(
+    command: &SimpleCommand,
+    assignment_command: AssignmentCommand,
+    exported: bool,
+    assignments: Vec<Assignment>,
+) -> Statement {
+    Statement {
+        kind: StatementKind::Assignment {
+            command: assignment_command,
+            exported,
+            unexported: false,
+            assignments,
+        },
+        span: command.span.clone(),
+        line: command.line,
+    }
+}
+
+fn assignment(
+    name: String,
+    span: Range<usize>,
+    words: &[Word],
+    value: Value,
+    line: usize,
+) -> Assignment {
+    let value_span = match (words.first(), words.last()) {
+        (Some(first), Some(last)) => first.span.start..last.span.end,
+        _ => span.end..span.end,
+    };
+    Assignment {
+        name,
+        value: Some(value),
+        append: false,
+        span,
+        value_span,
+        line,
+    }
+}
+
+fn other_statement(command: &SimpleCommand, words: &[Word]) -> Statement {
+    let kind = match words.first().and_then(literal).as_deref() {
+        Some("source" | ".") if words.len() > 1 => StatementKind::Source(words[1].clone()),
+        _ => StatementKind::Command(words.to_vec()),
+    };
+    Statement {
+        kind,
+        span: command.span.clone(),
+        line: command.line,
+    }
+}
+
+/// Parses `/etc/environment` as pam_env does: one `KEY=value` per line, an
+/// optional `export` prefix, and quotes around the whole value stripped.
+/// Lines that don't fit are ignored. With `expand`, values may reference
+/// other variables the way systemd's `environment.d` allows.
+fn parse_environment(source: &str, expand: bool) -> Script {
+    let mut statements = Vec::new();
+    let mut offset = 0;
+    for (index, raw) in source.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += raw.len();
+        let line = raw.trim_end_matches(['\n', '\r']);
+        let text = line.trim_start();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let statement_start = line_start + line.len() - text.len();
+        let text = text
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with([' ', '\t']))
+            .map_or(text, str::trim_start);
+        let Some((name, raw_value)) = text.split_once('=') else {
+            continue;
+        };
+        if !is_name(name) {
+            continue;
+        }
+        let name_start = line_start + line.len() - text.len();
+        let value_start = name_start + name.len() + 1;
+        let raw_value = raw_value.trim_end();
+        let value_end = value_start + raw_value.len();
+        let quote = raw_value.chars().next().filter(|quote| {
+            matches!(quote, '"' | '\'') && raw_value.len() > 1 && raw_value.ends_with(*quote)
+        });
+        let parts = match quote {
+            _ if expand => {
+                let inner = match quote {
+                    Some(_) => &raw_value[1..raw_value.len() - 1],
+                    None => raw_value,
+                };
+                expansion_parts(inner)
+            }
+            Some('"') => vec![WordPart::DoubleQuoted(vec![WordPart::Literal(
+                raw_value[1..raw_value.len() - 1].to_owned(),
+            )])],
+            Some(_) => vec![WordPart::SingleQuoted(
+                raw_value[1..raw_value.len() - 1].to_owned(),
+            )],
+            None if raw_value.is_empty() => Vec::new(),
+            None => vec![WordPart::Literal(raw_value.to_owned())],
+        };
+        let word = Word {
+            parts,
+            span: value_start..value_end,
+        };
+        statements.push(Statement {
+            kind: StatementKind::Assignment {
+                command: AssignmentCommand::Bare,
+                exported: true,
+                unexported: false,
+                assignments: vec![Assignment {
+                    name: name.to_owned(),
+                    value: Some(Value::Scalar(word)),
+                    append: false,
+                    span: name_start..value_end,
+                    value_span: value_start..value_end,
+                    line: index + 1,
+                }],
+            },
+            span: statement_start..value_end,
+            line: index + 1,
+        });
+    }
+    Script { statements }
+}
+
+/// Splits an `environment.d` value into literal text and parameter
+/// expansions. A backslash escapes the next character.
+fn expansion_parts(text: &str) -> Vec<WordPart> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\\' => {
+                if let Some(escaped) = rest.chars().next() {
+                    literal.push(escaped);
+                    rest = &rest[escaped.len_utf8()..];
+                }
+            }
+            '$' if rest.starts_with('$') => {
+                literal.push('$');
+                rest = &rest[1..];
+            }
+            '$' => match parameter(rest) {
+                Some((part, after)) => {
+                    rest = after;
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                }
+                None => literal.push('$'),
+            },
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(literal));
+    }
+    parts
+}
+
+/// Reads the parameter expansion following a `$`: `NAME`, `{NAME}` or
+/// `{NAME<modifier>}`. Returns it with the text after it.
+fn parameter(rest: &str) -> Option<(WordPart, &str)> {
+    let name_len = |text: &str| {
+        text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(text.len())
+    };
+    match rest
+        .strip_prefix('{')
+        .and_then(|inner| inner.split_once('}'))
+    {
+        Some((inner, after)) if is_name(&inner[..name_len(inner)]) => {
+            let (name, modifier) = inner.split_at(name_len(inner));
+            let part = WordPart::Parameter {
+                name: name.to_owned(),
+                modifier: (!modifier.is_empty()).then(|| modifier.to_owned()),
+                braced: true,
+            };
+            Some((part, after))
+        }
+        None if is_name(&rest[..name_len(rest)]) => {
+            let (name, after) = rest.split_at(name_len(rest));
+            let part = WordPart::Parameter {
+                name: name.to_owned(),
+                modifier: None,
+                braced: false,
+            };
+            Some((part, after))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a dotenv file: `KEY=value` lines with an optional `export`,
+/// `#` comments, single-quoted literals, double-quoted values that may span
+/// lines and take `\n`-style escapes, and `$VAR`/`${VAR}` interpolation
+/// outside single quotes. An unquoted value ends at a ` #` comment.
+fn parse_dotenv(source: &str) -> Script {
+    let mut statements = Vec::new();
+    let mut offset = 0;
+    let mut line = 1;
+    while offset < source.len() {
+        let mut end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+        if let Some(statement) = dotenv_statement(source, offset..end, line) {
+            // A quoted value may run past the end of its first line.
+            end = source[statement.span.end..]
+                .find('\n')
+                .map_or(source.len(), |i| statement.span.end + i);
+            statements.push(statement);
+        }
+        line += source[offset..end].matches('\n').count() + 1;
+        offset = end + 1;
+    }
+    Script { statements }
+}
+
+/// Parses the dotenv assignment starting on the line at `range`, if it
+/// holds one.
+fn dotenv_statement(source: &str, range: Range<usize>, line: usize) -> Option<Statement> {
+    let raw = &source[range.clone()];
+    let text = raw.trim_start();
+    let statement_start = range.start + raw.len() - text.len();
+    let body = text
+        .strip_prefix("export")
+        .filter(|rest| rest.starts_with([' ', '\t']))
+        .map_or(text, str::trim_start);
+    let name_start = range.end - body.len();
+    let (name, after) = body.split_once('=')?;
+    let name = name.trim_end();
+    if !is_name(name) {
+        return None;
+    }
+    let value = after.trim_start_matches([' ', '\t']);
+    let value_start = range.end - value.len();
+    let closing = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            closing_quote(&source[value_start + 1..], quote).map(|i| (quote, value_start + 1 + i))
+        }
+        _ => None,
+    };
+    let (parts, value_end) = match closing {
+        Some(('"', end)) => (
+            vec![WordPart::DoubleQuoted(dotenv_parts(
+                &source[value_start + 1..end],
+                true,
+            ))],
+            end + 1,
+        ),
+        Some((_, end)) => (
+            vec![WordPart::SingleQuoted(
+                source[value_start + 1..end].to_owned(),
+            )],
+            end + 1,
+        ),
+        None => {
+            let value = [" #", "\t#"]
+                .iter()
+                .filter_map(|comment| value.find(comment))
+                .min()
+                .map_or(value, |comment| &value[..comment])
+                .trim_end();
+            (dotenv_parts(value, false), value_start + value.len())
+        }
+    };
+    Some(Statement {
+        kind: StatementKind::Assignment {
+            command: AssignmentCommand::Bare,
+            exported: true,
+            unexported: false,
+            assignments: vec![Assignment {
+                name: name.to_owned(),
+                value: Some(Value::Scalar(Word {
+                    parts,
+                    span: value_start..value_end,
+                })),
+                append: false,
+                span: name_start..value_end,
+                value_span: value_start..value_end,
+                line,
+            }],
+        },
+        span: statement_start..value_end,
+        line,
+    })
+}
+
+/// The offset of the `quote` that closes a quoted dotenv value, skipping
+/// backslash-escaped ones inside double quotes.
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote == '"' => escaped = true,
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a dotenv value into literal text and interpolated variables,
+/// decoding backslash escapes when it was double-quoted.
+fn dotenv_parts(text: &str, escapes: bool) -> Vec<WordPart> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\\' if escapes => {
+                let decoded = match rest.chars().next() {
+                    Some('n') => Some('\n'),
+                    Some('t') => Some('\t'),
+                    Some('r') => Some('\r'),
+                    Some(c @ ('"' | '\\' | '$')) => Some(c),
+                    _ => None,
+                };
+                match decoded {
+                    Some(decoded) => {
+                        literal.push(decoded);
+                        rest = &rest[1..];
+                    }
+                    None => literal.push('\\'),
+                }
+            }
+            '$' => match parameter(rest) {
+                Some((part, after)) => {
+                    rest = after;
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                }
+                None => literal.push('$'),
+            },
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(literal));
+    }
+    parts
+}
+
+/// Writes a value for a dotenv file, double-quoting and escaping it when
+/// it isn't a single plain word. References interpolate only with
+/// `expand`; otherwise a value with a `$` is single-quoted, or has its `$`
+/// escaped when it can't be.
+fn dotenv_value(value: &str, expand: bool) -> String {
+    let literal_dollar = !expand && value.contains('$');
+    if !literal_dollar
+        && !value.is_empty()
+        && !value.contains(|c: char| c.is_whitespace() || "\"'#\\".contains(c))
+    {
+        return value.to_owned();
+    }
+    if literal_dollar && !value.contains(|c: char| c == '\'' || c.is_control()) {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '$' if !expand => quoted.push_str("\\$"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes a value for `/etc/environment`, quoting it only when needed. There
+/// are no escapes, so a value containing double quotes is single-quoted.
+/// It must not hold control characters, which would end the line or hide in
+/// it; `ConfigEdit::check` refuses edits that would write them.
+fn environment_value(value: &str) -> String {
+    debug_assert!(!value.contains(char::is_control));
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || "\"'#\\".contains(c)) {
+        value.to_owned()
+    } else if value.contains('"') {
+        format!("'{}'", value)
+    } else {
+        format!("\"{}\"", value)
+    }
+}
+
+fn parse_csh(source: &str) -> Result<Script, ParseError> {
+    let mut statements = Vec::new();
+    for mut command in simple_commands(source)? {
+        for word in &mut command.words {
+            csh_newlines(word);
+        }
+        let mut words = command.words.as_slice();
+        // `if ( cond ) setenv ...` runs the trailing command.
+        if words.first().and_then(literal).as_deref() == Some("if") {
+            let mut depth = 0;
+            let mut end = 1;
+            for (i, word) in words.iter().enumerate().skip(1) {
+                match literal(word).as_deref() {
+                    Some("(") => depth += 1,
+                    Some(")") => depth -= 1,
+                    _ => {}
+                }
+                end = i + 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            words = &words[end..];
+        }
+        while let Some(keyword) = words.first().and_then(literal) {
+            if matches!(keyword.as_str(), "then" | "else" | "endif" | "end") {
+                words = &words[1..];
+            } else {
+                break;
+            }
+        }
+        let Some(name) = words.first().and_then(literal) else {
+            if !words.is_empty() {
+                statements.push(other_statement(&command, words));
+            }
+            continue;
+        };
+        match name.as_str() {
+            "setenv" if words.len() > 1 => {
+                let name = words[1].to_literal();
+                let values = &words[2..words.len().min(3)];
+                let value = values.first().cloned().unwrap_or_default();
+                let span = words[1].span.start..words[words.len().min(3) - 1].span.end;
+                let assignment = assignment(name, span, values, Value::Scalar(value), command.line);
+                statements.push(statement(
+                    &command,
+                    AssignmentCommand::Setenv,
+                    true,
+                    vec![assignment],
+                ));
+            }
+            "set" => {
+                for assignment in csh_set(&words[1..], command.line) {
+                    let exported = tied_name(&assignment.name).is_some();
+                    let mut assignment = assignment;
+                    if let Some(scalar) = tied_name(&assignment.name) {
+                        assignment.name = scalar.to_owned();
+                    }
+                    statements.push(statement(
+                        &command,
+                        AssignmentCommand::Set,
+                        exported,
+                        vec![assignment],
+                    ));
+                }
+            }
+            "unsetenv" | "unset" => statements.push(Statement {
+                kind: StatementKind::Unset(words[1..].iter().map(Word::to_literal).collect()),
+                span: command.span.clone(),
+                line: command.line,
+            }),
+            _ => statements.push(other_statement(&command, words)),
+        }
+    }
+    Ok(Script { statements })
+}
+
+/// In csh a newline inside single quotes is written after a backslash.
+fn csh_newlines(word: &mut Word) {
+    for part in &mut word.parts {
+        if let WordPart::SingleQuoted(text) = part {
+            *text = text.replace("\\\n", "\n");
+        }
+    }
+}
+
+/// Parses the arguments of csh's `set`, which accepts `name`, `name=value`,
+/// `name = value` and `name = ( list )`.
+fn csh_set(words: &[Word], line: usize) -> Vec<Assignment> {
+    let mut assignments = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        let word = &words[i];
+        let text = word.to_literal();
+        if text.starts_with('-') {
+            i += 1;
+            continue;
+        }
+        let start = word.span.start;
+        let (name, inline_value) = match split_assignment_word(word) {
+            Some((name, _, value)) => (name, Some(value)),
+            None if is_name(&text) => (text, None),
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        i += 1;
+        let inline_value = inline_value.filter(|value| !value.parts.is_empty());
+        let has_equals = match &inline_value {
+            Some(_) => true,
+            None if text_at(words, i) == Some("=") => {
+                i += 1;
+                true
+            }
+            None => split_assignment_word(word).is_some(),
+        };
+
+        let (value, value_words) = if let Some(value) = inline_value {
+            (Value::Scalar(value.clone()), vec![value])
+        } else if has_equals && text_at(words, i) == Some("(") {
+            let open = i;
+            while i < words.len() && text_at(words, i) != Some(")") {
+                i += 1;
+            }
+            let elements = words[open + 1..i].to_vec();
+            let all = words[open..(i + 1).min(words.len())].to_vec();
+            i += 1;
+            let value = if tied_name(&name).is_some() {
+                Value::List(tie_parameters(elements))
+            } else {
+                Value::Array(elements)
+            };
+            (value, all)
+        } else if has_equals && i < words.len() {
+            i += 1;
+            (
+                Value::Scalar(words[i - 1].clone()),
+                vec![words[i - 1].clone()],
+            )
+        } else {
+            (Value::Scalar(Word::default()), Vec::new())
+        };
+
+        let end = value_words
+            .last()
+            .map_or(words[i.min(words.len()) - 1].span.end, |word| word.span.end);
+        assignments.push(assignment(name, start..end, &value_words, value, line));
+    }
+    assignments
+}
+
+fn text_at(words: &[Word], i: usize) -> Option<&str> {
+    match words.get(i)?.parts.as_slice() {
+        [WordPart::Literal(text)] => Some(text.as_str()),
+        _ => None,
+    }
+}
+
+fn parse_fish(source: &str) -> Result<Script, ParseError> {
+    let mut statements = Vec::new();
+    for command in simple_commands(source)? {
+        let mut words = command.words.as_slice();
+        while let Some(keyword) = words.first().and_then(literal) {
+            if matches!(
+                keyword.as_str(),
+                "and" | "or" | "not" | "begin" | "end" | "else" | "if" | "command" | "builtin"
+            ) {
+                words = &words[1..];
+            } else {
+                break;
+            }
+        }
+        match words.first().and_then(literal).as_deref() {
+            Some("set") => statements.extend(fish_set(&command, words)),
+            Some("fish_add_path") => statements.extend(fish_add_path(&command, &words[1..])),
+            Some(_) => statements.push(other_statement(&command, words)),
+            None if !words.is_empty() => statements.push(other_statement(&command, words)),
+            None => {}
+        }
+    }
+    Ok(Script { statements })
+}
+
+/// `set` in `words`. Queries and listings such as `set -q NAME` assign
+/// nothing and are kept as plain commands.
+fn fish_set(command: &SimpleCommand, words: &[Word]) -> Option<Statement> {
+    let mut exported = false;
+    let mut local = false;
+    let mut erase = false;
+    let mut append = false;
+    let mut prepend = false;
+    let mut assigns = true;
+    let mut rest = &words[1..];
+    while let Some(flag) = rest.first().and_then(literal) {
+        if let Some(long) = flag.strip_prefix("--") {
+            match long {
+                "export" => exported = true,
+                "local" => local = true,
+                "erase" => erase = true,
+                "append" => append = true,
+                "prepend" => prepend = true,
+                "query" | "names" | "show" | "long" | "help" => assigns = false,
+                _ => {}
+            }
+        } else if let Some(short) = flag.strip_prefix('-') {
+            exported |= short.contains('x');
+            local |= short.contains('l');
+            erase |= short.contains('e');
+            append |= short.contains('a');
+            prepend |= short.contains('p');
+            assigns &= !short.contains(['q', 'n', 'S', 'L', 'h']);
+        } else {
+            break;
+        }
+        rest = &rest[1..];
+    }
+    if !assigns {
+        return Some(other_statement(command, words));
+    }
+    let (name_word, values) = rest.split_first()?;
+    let name = name_word.to_literal();
+    if erase {
+        return Some(Statement {
+            kind: StatementKind::Unset(rest.iter().map(Word::to_literal).collect()),
+            span: command.span.clone(),
+            line: command.line,
+        });
+    }
+
+    let mut elements = values.to_vec();
+    if prepend {
+        elements.push(synthetic_parameter(&name));
+    }
+    let value = if name.ends_with("PATH") {
+        Value::List(elements)
+    } else if elements.len() == 1 {
+        Value::Scalar(elements.remove(0))
+    } else if elements.is_empty() {
+        Value::Scalar(Word::default())
+    } else {
+        Value::Array(elements)
+    };
+    let span = name_word.span.start..values.last().unwrap_or(name_word).span.end;
+    let mut assignment = assignment(name, span, values, value, command.line);
+    assignment.append = append;
+    let assignment_command = if local {
+        AssignmentCommand::Local
+    } else {
+        AssignmentCommand::Set
+    };
+    Some(statement(
+        command,
+        assignment_command,
+        exported,
+        vec![assignment],
+    ))
+}
+
+/// `fish_add_path` prepends (or with `-a` appends) directories to `PATH`.
+fn fish_add_path(command: &SimpleCommand, args: &[Word]) -> Option<Statement> {
+    let append = args
+        .iter()
+        .filter_map(literal)
+        .any(|flag| flag == "-a" || flag == "--append");
+    let dirs: Vec<Word> = args
+        .iter()
+        .filter(|word| !word.to_literal().starts_with('-'))
+        .cloned()
+        .collect();
+    let first = dirs.first()?;
+    let span = first.span.start..dirs.last().unwrap_or(first).span.end;
+    let mut elements = dirs.clone();
+    if !append {
+        elements.push(synthetic_parameter("PATH"));
+    }
+    let mut assignment = assignment(
+        "PATH".to_owned(),
+        span,
+        &dirs,
+        Value::List(elements),
+        command.line,
+    );
+    assignment.append = append;
+    Some(statement(
+        command,
+        AssignmentCommand::Set,
+        true,
+        vec![assignment],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported(dialect: Dialect, source: &str) -> Vec<(String, String)> {
+        dialect.parse(source).unwrap().exported_vars()
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn test_dialect_from_config_name() {
+        assert_eq!(Dialect::from_config_name(".bashrc"), Dialect::Posix);
+        assert_eq!(Dialect::from_config_name("/home/a/.zshrc"), Dialect::Zsh);
+        assert_eq!(Dialect::from_config_name(".kshrc"), Dialect::Ksh);
+        assert_eq!(Dialect::from_config_name(".cshrc"), Dialect::Csh);
+        assert_eq!(
+            Dialect::from_config_name(".config/fish/config.fish"),
+            Dialect::Fish
+        );
+        assert_eq!(Dialect::from_shell("/usr/bin/tcsh"), Some(Dialect::Csh));
+        assert_eq!(Dialect::from_shell("-zsh"), Some(Dialect::Zsh));
+        assert_eq!(Dialect::from_shell("/bin/nushell"), None);
+    }
+
+    #[test]
+    fn test_dotenv_file() {
+        let source = "\
+# comment=ignored
+export A=plain # trailing comment
+B = 'single $A'
+C=\"multi
+line \\\"quoted\\\" \\n$A\"
+D=
+";
+        let script = Dialect::Dotenv.parse(source).unwrap();
+        let lookup = |name: &str| (name == "A").then(|| "plain".to_owned());
+        let values: Vec<(String, String, usize)> = script
+            .assignments()
+            .map(|(_, assignment)| {
+                let word = assignment
+                    .value
+                    .as_ref()
+                    .unwrap()
+                    .words()
+                    .first()
+                    .map_or(String::new(), |word| word.expand(&lookup));
+                (assignment.name.clone(), word, assignment.line)
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("A".to_owned(), "plain".to_owned(), 2),
+                ("B".to_owned(), "single $A".to_owned(), 3),
+                (
+                    "C".to_owned(),
+                    "multi\nline \"quoted\" \nplain".to_owned(),
+                    4
+                ),
+                ("D".to_owned(), String::new(), 6),
+            ]
+        );
+        let (_, c) = script.assignments().nth(2).unwrap();
+        assert!(source[c.value_span.clone()].ends_with("$A\""));
+
+        assert_eq!(Dialect::Dotenv.assignment("A", "1", false), "A=1");
+        assert_eq!(
+            Dialect::Dotenv.assignment("A", "say \"hi\"\nbye", false),
+            "A=\"say \\\"hi\\\"\\nbye\""
+        );
+        assert_eq!(
+            Dialect::Dotenv.assignment("A", "pa$$word", false),
+            "A='pa$$word'"
+        );
+        assert_eq!(
+            Dialect::Dotenv.assignment("A", "it's $5", false),
+            "A=\"it's \\$5\""
+        );
+        assert_eq!(Dialect::Dotenv.assignment("A", "$B", true), "A=$B");
+        assert_eq!(Dialect::from_config_name("app/.env"), Dialect::Dotenv);
+        assert_eq!(Dialect::from_config_name(".env.example"), Dialect::Dotenv);
+        assert_eq!(Dialect::from_config_name("prod.env"), Dialect::Dotenv);
+        assert_eq!(Dialect::from_config_name(".envrc"), Dialect::Posix);
+    }
+
+    #[test]
+    fn test_environment_file() {
+        let source = "# comment\nLANG=en_US.UTF-8\n  export EDITOR=\"nvim -u NONE\"\nHOME_URL='$HOME'\nnot a var\n";
+        assert_eq!(
+            exported(Dialect::Environment, source),
+            vec![
+                pair("LANG", "en_US.UTF-8"),
+                pair("EDITOR", "nvim -u NONE"),
+                pair("HOME_URL", "$HOME"),
+            ]
+        );
+        assert_eq!(Dialect::Environment.assignment("A", "1", false), "A=1");
+        assert_eq!(
+            Dialect::Environment.assignment("A", "a b", false),
+            "A=\"a b\""
+        );
+        assert_eq!(
+            Dialect::Environment.assignment("A", "say \"hi\"", false),
+            "A='say \"hi\"'"
+        );
+        assert_eq!(
+            Dialect::from_config_name("/etc/environment"),
+            Dialect::Environment
+        );
+    }
+
+    #[test]
+    fn test_environment_d_expansion() {
+        let script = Dialect::EnvironmentD
+            .parse("A=1\nB=\"${A:-x} $HOME\"\nC=${UNSET:-fallback}${A:+set}\\$A\n")
+            .unwrap();
+        let lookup = |name: &str| match name {
+            "A" => Some("1".to_owned()),
+            "HOME" => Some("/home/me".to_owned()),
+            _ => None,
+        };
+        let values: Vec<String> = script
+            .assignments()
+            .map(|(_, assignment)| assignment.value.as_ref().unwrap().words()[0].expand(&lookup))
+            .collect();
+        assert_eq!(values, vec!["1", "1 /home/me", "fallbackset$A"]);
+
+        let literal = Dialect::EnvironmentD.assignment("K", "$HOME literal $$", false);
+        assert_eq!(literal, "K=\"$$HOME literal $$$$\"");
+        let script = Dialect::EnvironmentD.parse(&literal).unwrap();
+        let (_, assignment) = script.assignments().next().unwrap();
+        let value = &assignment.value.as_ref().unwrap().words()[0];
+        assert_eq!(value.expand(&lookup), "$HOME literal $$");
+        assert_eq!(
+            Dialect::EnvironmentD.assignment("K", "$HOME/bin", true),
+            "K=$HOME/bin"
+        );
+        assert_eq!(
+            Dialect::from_config_name("/etc/environment.d/10-foo.conf"),
+            Dialect::EnvironmentD
+        );
+        assert_eq!(Dialect::from_config_name("/etc/foo.conf"), Dialect::Posix);
+    }
+
+    #[test]
+    fn test_zsh_tied_path_array() {
+        let vars = exported(
+            Dialect::Zsh,
+            "export EDITOR=vim\npath=(/opt/bin $path)\npath+=(\"/x y\")\n",
+        );
+        assert_eq!(
+            vars,
+            vec![pair("EDITOR", "vim"), pair("PATH", "/opt/bin:$PATH:/x y")]
+        );
+    }
+
+    #[test]
+    fn test_csh_setenv_and_set_path() {
+        let source = "# comment\nsetenv EDITOR \"vim\"\nset path = ( /opt/bin $path )\nset prompt='> '\nif ( -d /x ) setenv X 1\nunsetenv GONE\n";
+        let vars = exported(Dialect::Csh, source);
+        assert_eq!(
+            vars,
+            vec![
+                pair("EDITOR", "vim"),
+                pair("PATH", "/opt/bin:$PATH"),
+                pair("X", "1")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fish_set_and_add_path() {
+        let source = "set -gx EDITOR vim\nset -g LOCAL_ONLY 1\nset -gx PATH /opt/bin $PATH\nset --export LIST a b\nif status is-interactive\n    set -x X 1\nend\nset -e X\nfish_add_path /y\n";
+        let vars = exported(Dialect::Fish, source);
+        assert_eq!(
+            vars,
+            vec![
+                pair("EDITOR", "vim"),
+                pair("PATH", "/y:$PATH"),
+                pair("LIST", "a b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fish_set_queries_and_locals_assign_nothing() {
+        let source = "set -gx EDITOR vim\nif not set -q EDITOR\n    set -gx EDITOR vi\nend\nset -gx PAGER less; set --query PAGER; or set -gx PAGER more\nset -n\nset -S PAGER\nset -qx LESS\nfunction f\n    set -lx EDITOR nano\n    set --local PAGER cat\nend\n";
+        let script = Dialect::Fish.parse(source).unwrap();
+
+        let assigned: Vec<(&str, &AssignmentCommand)> = script
+            .assignments()
+            .filter_map(|(statement, assignment)| match &statement.kind {
+                StatementKind::Assignment { command, .. } => {
+                    Some((assignment.name.as_str(), command))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            assigned,
+            vec![
+                ("EDITOR", &AssignmentCommand::Set),
+                ("EDITOR", &AssignmentCommand::Set),
+                ("PAGER", &AssignmentCommand::Set),
+                ("PAGER", &AssignmentCommand::Set),
+                ("EDITOR", &AssignmentCommand::Local),
+                ("PAGER", &AssignmentCommand::Local),
+            ]
+        );
+        let queries = script
+            .statements
+            .iter()
+            .filter(|statement| match &statement.kind {
+                StatementKind::Command(words) => words[0].to_literal() == "set",
+                _ => false,
+            })
+            .count();
+        assert_eq!(queries, 5);
+    }
+
+    #[test]
+    fn test_ksh_typeset() {
+        let vars = exported(Dialect::Ksh, "typeset -x A=1\nexport B=2\n");
+        assert_eq!(vars, vec![pair("A", "1"), pair("B", "2")]);
+    }
+
+    #[test]
+    fn test_assignment_round_trips_through_parse() {
+        for dialect in [
+            Dialect::Posix,
+            Dialect::Zsh,
+            Dialect::Ksh,
+            Dialect::Csh,
+            Dialect::Fish,
+        ] {
+            let source = format!(
+                "{}\n{}\n",
+                dialect.assignment("EDITOR", "nvim -u NONE", false),
+                dialect.assignment("PATH", "/a:/b c:$PATH", true)
+            );
+            assert_eq!(
+                exported(dialect, &source),
+                vec![
+                    pair("EDITOR", "nvim -u NONE"),
+                    pair("PATH", "/a:/b c:$PATH")
+                ],
+                "{dialect:?}: {source}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_path_append() {
+        assert_eq!(
+            Dialect::Posix.path_append("/x", "/bin"),
+            "export PATH=\"$PATH\":'/x'"
+        );
+        assert_eq!(Dialect::Zsh.path_append("/x", "/bin"), "path+=('/x')");
+        assert_eq!(
+            Dialect::Csh.path_append("/x", "/bin"),
+            "set path = ( $path '/x' )"
+        );
+        assert_eq!(
+            Dialect::Fish.path_append("/x", "/bin"),
+            "set -gx PATH $PATH '/x'"
+        );
+        assert_eq!(
+            Dialect::Environment.path_append("/x", "/usr/bin:/bin"),
+            "PATH=/usr/bin:/bin:/x"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use crate::dialect::Dialect;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// Reads a dotenv file, treating a missing one as empty so it can be
+/// created on the first save.
+pub fn read(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+/// The variables a dotenv file sets, in the order they first appear. Values
+/// are interpolated against variables set earlier in the file and then
+/// `base`, the environment the file is loaded into.
+pub fn resolve(source: &str, base: &dyn Fn(&str) -> Option<String>) -> Vec<(String, String)> {
+    let Ok(script) = Dialect::Dotenv.parse(source) else {
+        return Vec::new();
+    };
+    let mut vars: Vec<(String, String)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (_, assignment) in script.assignments() {
+        let Some(word) = assignment
+            .value
+            .as_ref()
+            .and_then(|value| value.words().first())
+        else {
+            continue;
+        };
+        let value = word.expand(&|name: &str| {
+            index
+                .get(name)
+                .map(|&i| vars[i].1.clone())
+                .or_else(|| base(name))
+        });
+        match index.get(&assignment.name) {
+            Some(&i) => vars[i].1 = value,
+            None => {
+                index.insert(assignment.name.clone(), vars.len());
+                vars.push((assignment.name.clone(), value));
+            }
+        }
+    }
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve_interpolates_in_file_order() {
+        let source = "\
+# database
+DB_HOST=localhost
+DB_URL=\"postgres://${DB_USER:-app}@$DB_HOST/${DB_NAME}\"
+DB_HOST=db.internal
+KEY='$DB_HOST literal'
+";
+        let base = |name: &str| (name == "DB_NAME").then(|| "main".to_owned());
+
+        let vars = resolve(source, &base);
+
+        assert_eq!(
+            vars,
+            vec![
+                ("DB_HOST".to_owned(), "db.internal".to_owned()),
+                (
+                    "DB_URL".to_owned(),
+                    "postgres://app@localhost/main".to_owned()
+                ),
+                ("KEY".to_owned(), "$DB_HOST literal".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_written_values_resolve_to_themselves() {
+        let base = |name: &str| Some(format!("<{}>", name));
+        for value in [
+            "pa$$word",
+            "a$b",
+            "${HOME}/bin",
+            "$",
+            "it's $5",
+            "line\n$A \"quoted\"",
+            "back\\slash $x",
+            "plain",
+            "",
+        ] {
+            let source = format!("{}\n", Dialect::Dotenv.assignment("V", value, false));
+            assert_eq!(
+                resolve(&source, &base),
+                vec![("V".to_owned(), value.to_owned())],
+                "{}",
+                source
+            );
+        }
+
+        let source = Dialect::Dotenv.assignment("V", "$HOME/bin and ${USER}", true);
+        assert_eq!(
+            resolve(&source, &base),
+            vec![("V".to_owned(), "<HOME>/bin and <USER>".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_literal_values_survive_block_rewrites() {
+        let mut config = ConfigFile::new(".env", Dialect::Dotenv, "");
+        config.set_var("PASSWORD", "pa$$word", false);
+        config.set_var("URL", "$HOST/api", true);
+        config.set_var("USER", "me", false);
+
+        let vars = resolve(&config.contents, &|name: &str| {
+            (name == "HOST").then(|| "example.com".to_owned())
+        });
+        assert_eq!(
+            vars,
+            vec![
+                ("PASSWORD".to_owned(), "pa$$word".to_owned()),
+                ("URL".to_owned(), "example.com/api".to_owned()),
+                ("USER".to_owned(), "me".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_missing_file_is_empty() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(read(&dir.path().join(".env")).unwrap(), "");
+    }
+}
@@ -0,0 +1,181 @@
+use crate::dialect::Dialect;
+use crate::parser::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// User drop-in directory, relative to `$HOME`.
+pub const USER_DIR: &str = ".config/environment.d";
+
+/// System drop-in directories, highest precedence first.
+pub const SYSTEM_DIRS: &[&str] = &[
+    "/etc/environment.d",
+    "/run/environment.d",
+    "/usr/local/lib/environment.d",
+    "/usr/lib/environment.d",
+];
+
+/// Drop-in that envelope adds new variables to.
+pub const ENVELOPE_DROP_IN: &str = "60-envelope.conf";
+
+/// Every drop-in directory for a user with the given home, highest
+/// precedence first.
+pub fn dirs(home: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![home.join(USER_DIR)];
+    dirs.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+/// The `*.conf` drop-ins in `dirs`, in the order systemd reads them: sorted
+/// by file name, with a file masking any of the same name in a directory of
+/// lower precedence.
+pub fn discover(dirs: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .filter(|path| {
+                !files
+                    .iter()
+                    .any(|file| file.file_name() == path.file_name())
+            })
+            .collect();
+        files.append(&mut found);
+    }
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(files)
+}
+
+/// The value a variable ends up with and the drop-in that set it last.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolved {
+    pub value: String,
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+/// Evaluates `files` in order, expanding references against variables set
+/// by earlier assignments and then `base`, the environment the session
+/// starts with.
+pub fn resolve(
+    files: &[PathBuf],
+    base: &dyn Fn(&str) -> Option<String>,
+) -> io::Result<HashMap<String, Resolved>> {
+    let mut resolved: HashMap<String, Resolved> = HashMap::new();
+    for path in files {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+        let Ok(script) = Dialect::EnvironmentD.parse(&contents) else {
+            continue;
+        };
+        for (_, assignment) in script.assignments() {
+            let Some(Value::Scalar(word)) = &assignment.value else {
+                continue;
+            };
+            let value = word.expand(&|name: &str| {
+                resolved
+                    .get(name)
+                    .map(|resolved| resolved.value.clone())
+                    .or_else(|| base(name))
+            });
+            resolved.insert(
+                assignment.name.clone(),
+                Resolved {
+                    value,
+                    path: path.clone(),
+                    line: assignment.line,
+                },
+            );
+        }
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_discover_orders_and_masks_drop_ins() {
+        let user = TempDir::new().unwrap();
+        let system = TempDir::new().unwrap();
+        fs::write(user.path().join("50-editor.conf"), "").unwrap();
+        fs::write(system.path().join("50-editor.conf"), "").unwrap();
+        fs::write(system.path().join("10-path.conf"), "").unwrap();
+        fs::write(system.path().join("README"), "").unwrap();
+        let dirs = [
+            user.path().to_path_buf(),
+            system.path().to_path_buf(),
+            user.path().join("missing"),
+        ];
+
+        assert_eq!(
+            discover(&dirs).unwrap(),
+            vec![
+                system.path().join("10-path.conf"),
+                user.path().join("50-editor.conf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_reports_effective_value_and_winner() {
+        let dir = TempDir::new().unwrap();
+        let first = dir.path().join("10-a.conf");
+        let second = dir.path().join("20-b.conf");
+        fs::write(&first, "EDITOR=vi\nPATH=$HOME/bin:$PATH\n").unwrap();
+        fs::write(&second, "# override\nEDITOR=${VISUAL:-nano}\n").unwrap();
+        let base = |name: &str| match name {
+            "HOME" => Some("/home/me".to_owned()),
+            "PATH" => Some("/usr/bin".to_owned()),
+            _ => None,
+        };
+
+        let resolved = resolve(&[first.clone(), second.clone()], &base).unwrap();
+
+        assert_eq!(
+            resolved["EDITOR"],
+            Resolved {
+                value: "nano".to_owned(),
+                path: second,
+                line: 2,
+            }
+        );
+        assert_eq!(resolved["PATH"].value, "/home/me/bin:/usr/bin");
+        assert_eq!(resolved["PATH"].path, first);
+    }
+
+    #[test]
+    fn test_resolve_skips_missing_files_and_keeps_escaped_dollars() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("10-a.conf");
+        fs::write(&file, "PRICE=\"$$5 for $UNSET\"\nBAD LINE\n").unwrap();
+
+        let resolved = resolve(&[dir.path().join("missing.conf"), file], &|_| None).unwrap();
+
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved["PRICE"].value, "$5 for ");
+    }
+
+    #[test]
+    fn test_unreadable_drop_ins_are_errors() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("10-a.conf");
+        fs::write(&file, b"A=\xff\n").unwrap();
+        assert!(resolve(std::slice::from_ref(&file), &|_| None).is_err());
+        // A file where a directory should be.
+        assert!(discover(&[file]).is_err());
+    }
+}
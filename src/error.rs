@@ -0,0 +1,150 @@
+use crate::dialect::Dialect;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::RecvError;
+
+/// Why an operation failed.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file or the terminal failed.
+    Io(io::Error),
+    /// The command line couldn't be understood.
+    Usage(String),
+    /// A shell whose startup files envelope can't edit was asked for.
+    UnsupportedShell(PathBuf),
+    /// A file envelope must not write to was about to be edited.
+    ReadOnly(PathBuf),
+    /// The value of the named variable isn't valid UTF-8, so it can't be
+    /// written to a config file.
+    NotUtf8(String),
+    /// The value of the named variable has control characters, such as a
+    /// newline, that an environment file can't hold.
+    ControlCharacters(String),
+    /// The thread reading terminal events stopped.
+    Events(RecvError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::UnsupportedShell(program) => {
+                write!(
+                    f,
+                    "{} isn't a shell envelope can configure",
+                    program.display()
+                )
+            }
+            Error::ReadOnly(path) => write!(f, "{} is read-only", path.display()),
+            Error::NotUtf8(name) => write!(
+                f,
+                "{} isn't valid UTF-8, so it can't be written to a config file",
+                name
+            ),
+            Error::ControlCharacters(name) => write!(
+                f,
+                "{} has control characters, which environment files can't hold",
+                name
+            ),
+            Error::Events(error) => write!(f, "terminal events stopped: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Events(error) => Some(error),
+            Error::Usage(_)
+            | Error::UnsupportedShell(_)
+            | Error::ReadOnly(_)
+            | Error::NotUtf8(_)
+            | Error::ControlCharacters(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(error: RecvError) -> Error {
+        Error::Events(error)
+    }
+}
+
+/// A problem envelope works around rather than stopping for, shown in the
+/// status area.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// `$HOME` isn't set, so the user's startup files can't be found and
+    /// the user scope is read-only.
+    NoHome,
+    /// Neither `$SHELL` nor the passwd entry names a shell envelope
+    /// understands, so the dialect was guessed.
+    ShellGuessed(Dialect),
+    /// The shell's config file doesn't exist yet; applying creates it.
+    ConfigMissing(PathBuf),
+    /// The shell's config file exists but couldn't be read, so it's
+    /// read-only.
+    ConfigUnreadable { path: PathBuf, error: String },
+    /// The shell's config file couldn't be parsed; new definitions still
+    /// go to the managed block.
+    ConfigUnparsable { path: PathBuf, error: String },
+    /// `PATH` isn't set in the environment.
+    NoPath,
+    /// Variables whose names differ only in case have different values.
+    /// Shells treat them as separate variables, so one usually shadows the
+    /// other for the programs that read it.
+    CaseCollision(Vec<String>),
+    /// An operation failed.
+    Failed(String),
+}
+
+impl Diagnostic {
+    /// Whether the user scope is read-only because of it.
+    pub fn read_only(&self) -> bool {
+        matches!(
+            self,
+            Diagnostic::NoHome | Diagnostic::ConfigUnreadable { .. }
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::NoHome => write!(f, "HOME isn't set; user files are read-only"),
+            Diagnostic::ShellGuessed(dialect) => write!(
+                f,
+                "Couldn't detect your shell; editing {}",
+                dialect.config_file()
+            ),
+            Diagnostic::ConfigMissing(path) => {
+                write!(f, "{} doesn't exist; applying creates it", path.display())
+            }
+            Diagnostic::ConfigUnreadable { path, error } => write!(
+                f,
+                "Can't read {} ({}); it's read-only",
+                path.display(),
+                error
+            ),
+            Diagnostic::ConfigUnparsable { path, error } => {
+                write!(f, "Can't parse {}: {}", path.display(), error)
+            }
+            Diagnostic::NoPath => write!(f, "PATH isn't set in the current environment"),
+            Diagnostic::CaseCollision(names) => write!(
+                f,
+                "{} differ only in case but have different values",
+                names.join(" and ")
+            ),
+            Diagnostic::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
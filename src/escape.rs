@@ -0,0 +1,104 @@
+use std::ffi::{OsStr, OsString};
+
+/// Shown after a value that isn't valid UTF-8.
+pub const NOT_UTF8_MARKER: &str = "[not UTF-8]";
+
+/// `value` as text. Valid UTF-8 is returned as it is; otherwise backslashes
+/// are doubled and every byte that isn't part of a UTF-8 character is
+/// written as `\xHH`, so that `unescape` gives back the same bytes.
+pub fn escape(value: &OsStr) -> String {
+    if let Some(text) = value.to_str() {
+        return text.to_owned();
+    }
+    let mut escaped = String::new();
+    for chunk in value.as_encoded_bytes().utf8_chunks() {
+        escaped.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// `value` for display, escaped and marked if it isn't valid UTF-8.
+pub fn display(value: &OsStr) -> String {
+    match value.to_str() {
+        Some(text) => text.to_owned(),
+        None => format!("{} {}", escape(value), NOT_UTF8_MARKER),
+    }
+}
+
+/// Reverses `escape` for a value that wasn't valid UTF-8. A backslash that
+/// doesn't start `\\` or `\xHH` is kept.
+pub fn unescape(text: &str) -> OsString {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        rest = match (byte, tail.first(), hex) {
+            (b'\\', Some(b'\\'), _) => {
+                bytes.push(b'\\');
+                &tail[1..]
+            }
+            (b'\\', _, Some(value)) => {
+                bytes.push(value);
+                &tail[3..]
+            }
+            _ => {
+                bytes.push(byte);
+                tail
+            }
+        };
+    }
+    from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+/// Other platforms can't hold arbitrary bytes in an `OsString`.
+#[cfg(not(unix))]
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn test_escape_round_trip() {
+        let value = OsStr::from_bytes(b"caf\xe9 C:\\dir \xff\xfe \xc3\xa9");
+
+        let escaped = escape(value);
+
+        assert_eq!(escaped, "caf\\xE9 C:\\\\dir \\xFF\\xFE é");
+        assert_eq!(unescape(&escaped), value);
+        assert_eq!(escape(OsStr::new("C:\\dir")), "C:\\dir");
+        assert_eq!(unescape("\\xzz \\"), OsStr::new("\\xzz \\"));
+    }
+
+    #[test]
+    fn test_escape_edge_cases() {
+        let value = OsStr::from_bytes(b"\\x41\xff");
+        assert_eq!(escape(value), "\\\\x41\\xFF");
+        assert_eq!(display(value), "\\\\x41\\xFF [not UTF-8]");
+        assert_eq!(unescape(&escape(value)), value);
+        assert_eq!(display(OsStr::new("\\x41")), "\\x41");
+
+        // A character cut short is escaped byte by byte.
+        let truncated = OsStr::from_bytes(b"\xe2\x82");
+        assert_eq!(escape(truncated), "\\xE2\\x82");
+        assert_eq!(unescape("\\xe2\\x82"), truncated);
+        assert_eq!(unescape("a\\x4"), OsStr::new("a\\x4"));
+        assert_eq!(unescape(""), OsStr::new(""));
+    }
+}
@@ -6,8 +6,8 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-/// Terminal events.
-#[derive(Clone, Copy, Debug)]
+/// Terminal events, and work finished off the UI thread.
+#[derive(Clone, Debug)]
 pub enum Event {
     /// Key press.
     Key(KeyEvent),
@@ -15,6 +15,9 @@ pub enum Event {
     Mouse(MouseEvent),
     /// Terminal resize.
     Resize(u16, u16),
+    /// A runtime capture finished, with the environment the shell ended up
+    /// with or why it couldn't be run.
+    Captured(Result<Vec<(String, String)>, String>),
 }
 
 /// Terminal event handler.
@@ -35,6 +38,11 @@ impl EventHandler {
         pub fn next(&self) -> AppResult<Event> {
         Ok(self.receiver.recv()?)
     }
+
+    /// A sender for work done on other threads to report back through.
+    pub fn sender(&self) -> mpsc::Sender<Event> {
+        self.sender.clone()
+    }
 }
 impl Default for EventHandler {
     /// Constructs a new instance of [`EventHandler`].
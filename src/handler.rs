@@ -1,92 +1,215 @@
-use crate::app::{ActiveList, App, AppResult};
-use globalenv::set_var;
+use crate::app::{ActiveList, App, AppResult, CurrentlyEditing};
+use crate::config::ConfigEdit;
+use crate::error::Error;
+use crate::escape::escape;
+use crate::history::Operation;
+use crate::search::Search;
 use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::{env::join_paths, path::PathBuf};
+use std::path::PathBuf;
 
 pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
-    let config_path = app.config_path.clone();
-    let mut shell_config = OpenOptions::new().append(true).open(&config_path).unwrap();
+    if matches!(key_event.code, KeyCode::Char('c') | KeyCode::Char('C'))
+        && key_event.modifiers == KeyModifiers::CONTROL
+    {
+        app.quit();
+        return Ok(());
+    }
+    if !app.confirm_install.is_empty() {
+        match key_event.code {
+            KeyCode::Char('y') => app.confirm_staged_install(true)?,
+            KeyCode::Char('n') | KeyCode::Esc => app.confirm_staged_install(false)?,
+            _ => {}
+        }
+        return Ok(());
+    }
+    if app.confirm_unset.is_some() {
+        match key_event.code {
+            KeyCode::Char('y') => app.confirm_delete(true),
+            KeyCode::Char('n') => app.confirm_delete(false),
+            KeyCode::Esc => app.confirm_unset = None,
+            _ => {}
+        }
+        return Ok(());
+    }
+    if app.confirm_quit {
+        match key_event.code {
+            KeyCode::Char('y') => app.quit_with_pending(true)?,
+            KeyCode::Char('n') => app.quit_with_pending(false)?,
+            KeyCode::Esc => app.confirm_quit = false,
+            _ => {}
+        }
+        return Ok(());
+    }
+    if app.picker.is_some() {
+        match key_event.code {
+            KeyCode::Up => app.move_picker(-1),
+            KeyCode::Down => app.move_picker(1),
+            KeyCode::Char(' ') | KeyCode::Enter => app.toggle_picked_target(),
+            KeyCode::Char('f') | KeyCode::Esc => app.toggle_picker(),
+            _ => {}
+        }
+        return Ok(());
+    }
+    if app.searching {
+        return handle_search_keys(key_event, app);
+    }
+    if app.editing {
+        return handle_editing_keys(key_event, app);
+    }
+    let env_selected = app.env_list_state.selected().is_some();
+    let path_selected = app.path_list_state.selected().is_some();
     match key_event.code {
+        KeyCode::Esc if app.search.is_some() => app.clear_search(),
         KeyCode::Char('q') | KeyCode::Esc => {
-            app.quit();
+            app.request_quit();
         }
-        KeyCode::Char('c') | KeyCode::Char('C') => {
-            if key_event.modifiers == KeyModifiers::CONTROL {
-                app.quit();
+        KeyCode::Char('/') => app.start_search(),
+        KeyCode::Char('n') => app.jump_to_hit(1),
+        KeyCode::Char('N') => app.jump_to_hit(-1),
+        KeyCode::Char('e') => match app.activated_list {
+            ActiveList::EnvList if env_selected && app.selected_env_var < app.env_vars.len() => {
+                app.editing = true;
+                app.editing_bytes = app.selected_value().to_str().is_none();
+                app.env_var_value = escape(app.selected_value());
             }
-        }
-        KeyCode::Char('e') => {
-            if !app.editing {
+            ActiveList::PathList
+                if path_selected && app.selected_path_dir < app.path_var_dirs.len() =>
+            {
+                let dir = app.path_var_dirs[app.selected_path_dir].as_os_str();
                 app.editing = true;
-                app.env_var_value = app.selected_value().to_string();
+                app.editing_bytes = dir.to_str().is_none();
+                app.path_var_edit = escape(dir);
             }
+            _ => {}
+        },
+        KeyCode::Char('s') => {
+            write_to_config(app)?;
         }
-        KeyCode::Char(c) => {
-            if app.editing {
-                match app.activated_list {
-                    ActiveList::EnvList => app.env_var_value.push(c),
-                    ActiveList::PathList => {
-                        app.path_var_value.push(c);
-                        let path_edit = app.path_var_value.clone();
-                        app.path_var_edit = path_edit;
-                        app.path_var_edit.push(c);
-                    }
-                }
-            }
+        KeyCode::Char('x') => {
+            app.discard_pending();
         }
-        KeyCode::Backspace => {
-            if app.editing {
-                match app.activated_list {
-                    ActiveList::EnvList => {
-                        app.env_var_value.pop();
-                    }
-                    ActiveList::PathList => {
-                        app.path_var_edit.pop();
-                    }
-                }
+        KeyCode::Char('u') => {
+            app.undo()?;
+        }
+        KeyCode::Char('r') => {
+            app.redo()?;
+        }
+        KeyCode::Char('h') => {
+            app.show_history = !app.show_history;
+        }
+        KeyCode::Char('i') if app.activated_list == ActiveList::PathList => {
+            app.start_path_insert(false);
+        }
+        KeyCode::Char('a') if app.activated_list == ActiveList::PathList => {
+            app.start_path_insert(true);
+        }
+        KeyCode::Char('a') => {
+            app.start_new_var();
+        }
+        KeyCode::Char('g') => {
+            app.toggle_scope();
+        }
+        KeyCode::Char('m') => {
+            app.add_missing_keys();
+        }
+        KeyCode::Char('o') => {
+            app.cycle_origin_filter();
+        }
+        KeyCode::Char('c') => {
+            app.show_chain = !app.show_chain;
+        }
+        KeyCode::Char('l') => {
+            app.cycle_shell_mode();
+        }
+        KeyCode::Char('p') => {
+            app.toggle_path_steps();
+        }
+        KeyCode::Char('f') => {
+            app.toggle_picker();
+        }
+        KeyCode::Char('v') => {
+            if app.show_capture {
+                app.show_capture = false;
+            } else {
+                app.capture_runtime();
             }
         }
+        KeyCode::Left => app.move_path_step(-1),
+        KeyCode::Right => app.move_path_step(1),
+        KeyCode::Char('d') if app.activated_list == ActiveList::EnvList && env_selected => {
+            app.delete_selected_var()?;
+        }
+        KeyCode::Char('d') if app.activated_list == ActiveList::PathList && path_selected => {
+            app.remove_path_dir();
+        }
+        KeyCode::Char('K') if app.activated_list == ActiveList::PathList && path_selected => {
+            app.move_path_dir(-1);
+        }
+        KeyCode::Char('J') if app.activated_list == ActiveList::PathList && path_selected => {
+            app.move_path_dir(1);
+        }
+        KeyCode::Char('D') if app.activated_list == ActiveList::PathList => {
+            app.dedupe_path();
+        }
+        KeyCode::Char('R') if app.activated_list == ActiveList::EnvList && env_selected => {
+            app.start_rename()?;
+        }
         KeyCode::Tab => {
             app.toggle_active();
         }
         KeyCode::Down => match app.activated_list {
-            ActiveList::EnvList => {
-                if !app.editing && app.selected_env_var < app.env_vars.len() - 1 {
-                    app.selected_env_var += 1;
-                    app.env_list_state.select(Some(app.selected_env_var))
-                }
-            }
-            ActiveList::PathList => {
-                if app.selected_path_dir < app.path_var_dirs.len() - 1 {
-                    app.selected_path_dir += 1;
-                    app.path_var_value = app.path_var_dirs[app.selected_path_dir]
-                        .clone()
-                        .to_str()
-                        .unwrap()
-                        .to_owned();
-                    app.path_list_state.select(Some(app.selected_path_dir))
-                }
-            }
+            ActiveList::EnvList => app.move_env_selection(1),
+            ActiveList::PathList => app.move_path_selection(1),
         },
         KeyCode::Up => match app.activated_list {
+            ActiveList::EnvList => app.move_env_selection(-1),
+            ActiveList::PathList => app.move_path_selection(-1),
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handles keys while a search query is typed. Both lists narrow as it
+/// changes; Enter keeps the search and Esc drops it.
+fn handle_search_keys(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    match key_event.code {
+        KeyCode::Esc => app.clear_search(),
+        KeyCode::Enter => app.searching = false,
+        KeyCode::Tab => app.update_search(Search::toggle_regex),
+        KeyCode::BackTab => app.update_search(Search::toggle_values),
+        KeyCode::Char(c) => app.update_search(|search| search.push(c)),
+        KeyCode::Backspace => app.update_search(Search::pop),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handles keys while a value is being edited. Enter stages the edit as a
+/// pending change; nothing is written until it is applied.
+fn handle_editing_keys(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    if app.currently_editing.is_some() {
+        return handle_new_var_keys(key_event, app);
+    }
+    if app.rename_from.is_some() {
+        return handle_rename_keys(key_event, app);
+    }
+    match key_event.code {
+        KeyCode::Esc => {
+            app.editing = false;
+            app.path_insert = None;
+        }
+        KeyCode::Tab if app.activated_list == ActiveList::EnvList => app.toggle_expansion(),
+        KeyCode::Char(c) => match app.activated_list {
+            ActiveList::EnvList => app.env_var_value.push(c),
+            ActiveList::PathList => app.path_var_edit.push(c),
+        },
+        KeyCode::Backspace => match app.activated_list {
             ActiveList::EnvList => {
-                if !app.editing && app.selected_env_var > 0 {
-                    app.selected_env_var -= 1;
-                    app.env_list_state.select(Some(app.selected_env_var))
-                }
+                app.env_var_value.pop();
             }
             ActiveList::PathList => {
-                if !app.editing && app.selected_path_dir > 0 {
-                    app.selected_path_dir -= 1;
-                    app.path_var_value = app.path_var_dirs[app.selected_path_dir]
-                        .clone()
-                        .to_str()
-                        .unwrap()
-                        .to_owned();
-                    app.path_list_state.select(Some(app.selected_path_dir))
-                }
+                app.path_var_edit.pop();
             }
         },
         KeyCode::Enter => match app.activated_list {
@@ -95,27 +218,43 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                 // we can check for values that would be overwritten
                 // or duplicated, display a pop up to the user,
                 // and make decisions based on the interaction
-                app.env_vars[app.selected_env_var].1 = app.env_var_value.clone();
-                let key = app.env_vars[app.selected_env_var].0.clone();
-                let env_var_key = key[..].to_ascii_uppercase().trim_matches('\"').to_owned();
+                let before = app.snapshot();
+                let new = app.edited_value(&app.env_var_value);
+                let old = std::mem::replace(&mut app.env_vars[app.selected_env_var].1, new.clone());
+                let env_var_key = escape(&app.env_vars[app.selected_env_var].0);
                 if app.shell_env_vars.contains_key(&env_var_key) {
                     app.overwrite = true;
                 }
-                let env_var = format!(
-                    "export {}=\"{}\"\n",
-                    env_var_key,
-                    &app.env_var_value.clone()[..]
+                match new.to_str() {
+                    Some(value) => app.stage(ConfigEdit::SetVar {
+                        name: env_var_key.clone(),
+                        value: value.to_owned(),
+                        expand: app.expanding.contains(&env_var_key),
+                    }),
+                    None => app.report(Error::NotUtf8(env_var_key.clone())),
+                }
+                app.record(
+                    Operation::SetValue {
+                        name: env_var_key,
+                        old: escape(&old),
+                        new: escape(&new),
+                    },
+                    before,
+                    Vec::new(),
                 );
-                write_to_config(&app, &env_var, &mut shell_config);
-                app.editing = !app.editing;
+                app.editing = false;
             }
+            ActiveList::PathList if app.path_insert.is_some() => app.insert_path_dir(),
             ActiveList::PathList => {
-                app.path_var_dirs[app.selected_path_dir] = PathBuf::from(app.path_var_edit.clone());
-                let path_var = app.path_var_edit.clone();
-                let export_var = format!("export PATH=$PATH:{}\n", path_var,);
-
-                write_to_config(&app, &export_var, &mut shell_config);
-                app.editing = !app.editing;
+                let before = app.snapshot();
+                let new = PathBuf::from(app.edited_value(&app.path_var_edit));
+                let old = std::mem::replace(&mut app.path_var_dirs[app.selected_path_dir], new);
+                let operation = Operation::EditPath {
+                    old: escape(old.as_os_str()),
+                    new: app.path_var_edit.clone(),
+                };
+                app.stage_path_dirs(operation, before);
+                app.editing = false;
             }
         },
         _ => {}
@@ -123,112 +262,937 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
     Ok(())
 }
 
-pub fn write_to_config(app: &App, config_var: &str, config_file: &mut File) {
-    if app.activated_list == ActiveList::PathList {
-        config_file
-            .write_all(b"\n")
-            .expect("Unable to write new line to config file");
-        config_file
-            .write_all(config_var.as_bytes())
-            .expect(&format!("Unable to write {:?} to config file", config_var));
-    } else if app.activated_list == ActiveList::EnvList {
-        config_file
-            .write_all(b"\n")
-            .expect("Unable to write new line to config file");
-        config_file.write_all(config_var.as_bytes())
-            .expect(&format!("Unable to write {:?} to config file", config_var));
+/// Handles keys in the new variable flow. Tab switches between the name and
+/// value fields; Enter validates the name and stages the definition.
+fn handle_new_var_keys(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    let field = match app.currently_editing {
+        Some(CurrentlyEditing::EnvVarName) => &mut app.env_var_key,
+        _ => &mut app.env_var_value,
+    };
+    match key_event.code {
+        KeyCode::Esc => app.cancel_new_var(),
+        KeyCode::Tab => {
+            app.currently_editing = match app.currently_editing {
+                Some(CurrentlyEditing::EnvVarName) => Some(CurrentlyEditing::EnvVarValue),
+                _ => Some(CurrentlyEditing::EnvVarName),
+            }
+        }
+        KeyCode::Char(c) => {
+            field.push(c);
+            app.name_error = None;
+        }
+        KeyCode::Backspace => {
+            field.pop();
+            app.name_error = None;
+        }
+        KeyCode::Enter => app.create_new_var(),
+        _ => {}
     }
+    Ok(())
+}
+
+/// Handles keys while a new name is entered for a variable. Enter stages
+/// the rename in every startup file that mentions the variable.
+fn handle_rename_keys(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    match key_event.code {
+        KeyCode::Esc => app.cancel_rename(),
+        KeyCode::Char(c) => {
+            app.env_var_key.push(c);
+            app.name_error = None;
+        }
+        KeyCode::Backspace => {
+            app.env_var_key.pop();
+            app.name_error = None;
+        }
+        KeyCode::Enter => app.rename_selected_var()?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Writes the staged edits to the shell config file. New variables land in
+/// the envelope-managed block; existing definitions are rewritten in place.
+pub fn write_to_config(app: &mut App) -> AppResult<()> {
+    app.apply_pending()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::{File, remove_file};
-    use std::io::{Read, Seek, SeekFrom, Write};
-    use std::path::PathBuf;
+    use crate::app::Scope;
+    use crate::config::{BLOCK_END, BLOCK_START};
+    use crate::dialect::Dialect;
+    use crate::error::Diagnostic;
+    use std::fs::{read_to_string, write};
+    use tempfile::TempDir;
 
-    fn create_test_app(activated_list: ActiveList) -> App {
-        let mut app = App::default();
-        app.activated_list = activated_list;
-        app
+    fn create_test_app(contents: &str) -> (App, TempDir) {
+        let dir = TempDir::new().unwrap();
+        write(dir.path().join(".bashrc"), contents).unwrap();
+        let app = App::in_home(dir.path());
+        (app, dir)
     }
 
-    fn create_temp_file() -> (PathBuf, File) {
-        let path = format!(".bashrc");
-        let file = File::create(&path).unwrap();
-        (path.into(), file)
+    fn set_var(name: &str, value: &str) -> ConfigEdit {
+        ConfigEdit::SetVar {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            expand: false,
+        }
     }
 
     #[test]
     fn test_write_to_config_path_list() {
-        let app = create_test_app(ActiveList::PathList);
-        let (path, mut file) = create_temp_file();
-        let config_var = "export PATH=$PATH:/new/path";
+        let (mut app, _dir) = create_test_app("");
 
-        write_to_config(&app, config_var, &mut file);
+        app.stage(ConfigEdit::AppendPath("/new/path".to_owned()));
+        write_to_config(&mut app).unwrap();
 
-        let mut contents = String::new();
-        file.seek(SeekFrom::Start(0)).unwrap();
-        file.read_to_string(&mut contents).unwrap();
+        let contents = read_to_string(&app.config_path).unwrap();
+        assert!(contents.starts_with(BLOCK_START));
+        assert!(contents.contains("\nexport PATH=\"$PATH:/new/path\"\n"));
+        assert!(contents.ends_with(&format!("{}\n", BLOCK_END)));
+    }
 
-        assert_eq!(contents, format!("\n{}", config_var));
+    #[test]
+    fn test_write_to_config_env_list() {
+        let (mut app, _dir) = create_test_app("# existing\n");
+
+        app.stage(set_var("NEW_VAR", "value"));
+        write_to_config(&mut app).unwrap();
 
-        remove_file(path).unwrap();
+        let contents = read_to_string(&app.config_path).unwrap();
+        assert!(contents.starts_with(&format!("# existing\n\n{}\n", BLOCK_START)));
+        assert!(contents.contains("\nexport NEW_VAR='value'\n"));
+        assert_eq!(app.shell_env_vars["NEW_VAR"], "value");
     }
 
     #[test]
-    fn test_write_to_config_env_list() {
-        let app = create_test_app(ActiveList::EnvList);
-        let (path, mut file) = create_temp_file();
-        let config_var = "export NEW_VAR=value";
+    fn test_write_to_config_multiple_writes() {
+        let (mut app, _dir) = create_test_app("");
+
+        app.stage(set_var("VAR1", "value1"));
+        write_to_config(&mut app).unwrap();
+        app.stage(set_var("VAR2", "value2"));
+        write_to_config(&mut app).unwrap();
+
+        let contents = read_to_string(&app.config_path).unwrap();
+        assert_eq!(contents.matches(BLOCK_START).count(), 1);
+        assert!(contents.contains("\nexport VAR1='value1'\nexport VAR2='value2'\n"));
+    }
 
-        write_to_config(&app, config_var, &mut file);
+    #[test]
+    fn test_write_to_config_overwrites_in_place() {
+        let (mut app, _dir) = create_test_app("export VAR1=old # note\nexport VAR2=keep\n");
+
+        app.stage(set_var("VAR1", "new"));
+        write_to_config(&mut app).unwrap();
+        app.stage(set_var("VAR1", "newer"));
+        write_to_config(&mut app).unwrap();
 
-        let mut contents = String::new();
-        file.seek(SeekFrom::Start(0)).unwrap();
-        file.read_to_string(&mut contents).unwrap();
+        let contents = read_to_string(&app.config_path).unwrap();
+        assert_eq!(contents, "export VAR1='newer' # note\nexport VAR2=keep\n");
+    }
 
-        assert_eq!(contents, format!("\n{}", config_var));
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
 
-        remove_file(path).unwrap();
+    fn select_env_var(app: &mut App, name: &str, value: &str) {
+        app.env_vars.push((name.into(), value.into()));
+        app.selected_env_var = app.env_vars.len() - 1;
+        app.activated_list = ActiveList::EnvList;
     }
 
     #[test]
-    fn test_write_to_config_multiple_writes() {
-        let app = create_test_app(ActiveList::EnvList);
-        let (path, mut file) = create_temp_file();
-        let config_var1 = "export VAR1=value1";
-        let config_var2 = "export VAR2=value2";
+    fn test_enter_stages_without_writing() {
+        let (mut app, _dir) = create_test_app("export ENVELOPE_TEST=old\n");
+        select_env_var(&mut app, "ENVELOPE_TEST", "old");
 
-        write_to_config(&app, config_var1, &mut file);
-        write_to_config(&app, config_var2, &mut file);
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        for _ in 0..3 {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        for c in "new".chars() {
+            handle_key_events(key(KeyCode::Char(c)), &mut app).unwrap();
+        }
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
 
-        let mut contents = String::new();
-        file.seek(SeekFrom::Start(0)).unwrap();
-        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(app.pending.len(), 1);
+        assert!(app
+            .pending_diff
+            .contains("-export ENVELOPE_TEST=old\n+export ENVELOPE_TEST='new'\n"));
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "export ENVELOPE_TEST=old\n"
+        );
 
-        assert_eq!(contents, format!("\n{}\n{}", config_var1, config_var2));
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
 
-        remove_file(path).unwrap();
+        assert!(app.pending.is_empty());
+        assert!(app.pending_diff.is_empty());
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "export ENVELOPE_TEST='new'\n"
+        );
     }
 
     #[test]
-    #[should_panic(expected = "Unable to write new line to config file")]
-    fn test_write_to_config_file_write_error() {
-        use std::fs::OpenOptions;
+    fn test_discard_restores_values() {
+        let (mut app, _dir) = create_test_app("");
+        select_env_var(&mut app, "ENVELOPE_TEST", "old");
+        app.saved_env_vars = app.env_vars.clone();
+
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('!')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert_eq!(app.selected_value(), "old!");
+
+        handle_key_events(key(KeyCode::Char('x')), &mut app).unwrap();
+
+        assert!(app.pending.is_empty());
+        assert_eq!(app.selected_value(), "old");
+        assert_eq!(read_to_string(&app.config_path).unwrap(), "");
+    }
 
-        let app = create_test_app(ActiveList::EnvList);
-        let (path, _) = create_temp_file();
-        let config_var = "export ERROR_VAR=value";
+    #[test]
+    fn test_undo_redo_edit_and_apply() {
+        let (mut app, _dir) = create_test_app("export ENVELOPE_TEST=old\n");
+        select_env_var(&mut app, "ENVELOPE_TEST", "old");
+        app.saved_env_vars = app.env_vars.clone();
+
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('!')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "export ENVELOPE_TEST='old!'\n"
+        );
+        assert_eq!(app.history.undo.len(), 2);
+
+        // Undoing the apply restores the file and puts the edit back on stage.
+        handle_key_events(key(KeyCode::Char('u')), &mut app).unwrap();
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "export ENVELOPE_TEST=old\n"
+        );
+        assert_eq!(app.pending.len(), 1);
+        assert_eq!(app.selected_value(), "old!");
+
+        handle_key_events(key(KeyCode::Char('u')), &mut app).unwrap();
+        assert!(app.pending.is_empty());
+        assert_eq!(app.selected_value(), "old");
+
+        handle_key_events(key(KeyCode::Char('r')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('r')), &mut app).unwrap();
+        assert!(app.pending.is_empty());
+        assert_eq!(app.selected_value(), "old!");
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "export ENVELOPE_TEST='old!'\n"
+        );
+        assert!(app.history.redo.is_empty());
+    }
+
+    #[test]
+    fn test_edit_preserves_name_case_and_warns_on_collision() {
+        let (mut app, _dir) = create_test_app("export http_proxy=http://old\n");
+        app.shell_env_vars
+            .insert("http_proxy".to_owned(), "http://old".to_owned());
+        select_env_var(&mut app, "HTTP_PROXY", "http://proxy");
+        select_env_var(&mut app, "http_proxy", "http://old");
+
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        for _ in 0.."old".len() {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        type_text(&mut app, "new");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+
+        assert!(app.overwrite);
+        assert!(app
+            .pending_diff
+            .contains("-export http_proxy=http://old\n+export http_proxy='http://new'\n"));
+        assert_eq!(
+            app.diagnostics,
+            vec![Diagnostic::CaseCollision(vec![
+                "HTTP_PROXY".to_owned(),
+                "http_proxy".to_owned()
+            ])]
+        );
+
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        for _ in 0.."new".len() {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        type_text(&mut app, "proxy");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(app.diagnostics.is_empty());
+
+        handle_key_events(key(KeyCode::Char('u')), &mut app).unwrap();
+        assert_eq!(app.diagnostics.len(), 1);
+    }
 
-        // Open the file in read-only mode to simulate a write error
-        let mut read_only_file = OpenOptions::new()
-            .read(true)
-            .open(&path)
+    #[test]
+    fn test_values_are_literal_unless_expansion_is_allowed() {
+        let (mut app, _dir) = create_test_app("export GOPATH=\"$HOME/go\"\n");
+        select_env_var(&mut app, "GOPATH", "/home/me/go");
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        app.env_var_value = "$HOME/go2".to_owned();
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(app.pending_diff.contains("+export GOPATH=\"$HOME/go2\"\n"));
+
+        select_env_var(&mut app, "ENVELOPE_CMD", "");
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        app.env_var_value = "$(id) $HOME".to_owned();
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(app
+            .pending_diff
+            .contains("+export ENVELOPE_CMD='$(id) $HOME'\n"));
+
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Tab), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(app
+            .pending_diff
+            .contains("+export ENVELOPE_CMD=\"\\$(id) $HOME\"\n"));
+    }
+
+    #[test]
+    fn test_search_narrows_lists_and_jumps_between_hits() {
+        let (mut app, _dir) = create_test_app("");
+        app.env_vars = ["CARGO_HOME", "EDITOR", "GOPATH", "MANPATH"]
+            .map(|name| (name.into(), format!("/opt/{}", name.to_lowercase()).into()))
+            .into();
+        app.path_var_dirs = vec!["/usr/bin".into(), "/opt/go/bin".into()];
+        app.selected_env_var = 0;
+        app.activated_list = ActiveList::EnvList;
+
+        handle_key_events(key(KeyCode::Char('/')), &mut app).unwrap();
+        type_text(&mut app, "path");
+        assert_eq!(app.visible_env_vars(), vec![2, 3]);
+        assert_eq!(app.selected_env_var, 2);
+        assert_eq!(app.env_list_state.selected(), Some(0));
+        assert!(app.visible_path_dirs().is_empty());
+
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(!app.searching);
+        handle_key_events(key(KeyCode::Char('n')), &mut app).unwrap();
+        assert_eq!(app.selected_env_var, 3);
+        handle_key_events(key(KeyCode::Char('n')), &mut app).unwrap();
+        assert_eq!(app.selected_env_var, 2);
+        handle_key_events(key(KeyCode::Char('N')), &mut app).unwrap();
+        assert_eq!(app.selected_env_var, 3);
+
+        handle_key_events(key(KeyCode::Char('/')), &mut app).unwrap();
+        for _ in 0.."path".len() {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        type_text(&mut app, "go");
+        handle_key_events(key(KeyCode::BackTab), &mut app).unwrap();
+        assert_eq!(app.visible_env_vars(), vec![0, 2]);
+        assert_eq!(app.visible_path_dirs(), vec![1]);
+        assert_eq!(app.path_var_value, "/opt/go/bin");
+
+        handle_key_events(key(KeyCode::Esc), &mut app).unwrap();
+        assert!(app.search.is_none());
+        assert_eq!(app.visible_env_vars().len(), 4);
+        assert_eq!(app.visible_path_dirs().len(), 2);
+    }
+
+    #[test]
+    fn test_path_operations_stage_one_canonical_assignment() {
+        let (mut app, _dir) = create_test_app("");
+        app.path_var_dirs = vec!["/usr/bin".into(), "/bin".into()];
+        app.inherited_path_dirs = app.path_var_dirs.clone();
+        app.selected_path_dir = 0;
+        app.activated_list = ActiveList::PathList;
+        let block_path = |app: &App| {
+            let (_, after) = app.pending.preview().unwrap().pop().unwrap();
+            after.managed_vars().remove("PATH")
+        };
+
+        handle_key_events(key(KeyCode::Char('a')), &mut app).unwrap();
+        type_text(&mut app, "/opt/bin");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert_eq!(
+            app.path_var_dirs,
+            ["/usr/bin", "/opt/bin", "/bin"].map(PathBuf::from)
+        );
+        assert_eq!(
+            block_path(&app),
+            Some(("/usr/bin:/opt/bin:/bin".to_owned(), false))
+        );
+
+        handle_key_events(key(KeyCode::Char('K')), &mut app).unwrap();
+        assert_eq!(app.selected_path_dir, 0);
+        assert_eq!(block_path(&app), Some(("/opt/bin:$PATH".to_owned(), true)));
+
+        handle_key_events(key(KeyCode::Char('i')), &mut app).unwrap();
+        type_text(&mut app, "/opt/bin");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('D')), &mut app).unwrap();
+        assert_eq!(
+            app.path_var_dirs,
+            ["/opt/bin", "/usr/bin", "/bin"].map(PathBuf::from)
+        );
+        assert_eq!(block_path(&app), Some(("/opt/bin:$PATH".to_owned(), true)));
+        assert_eq!(
+            app.history.undo.last().unwrap().operation,
+            Operation::DedupePath { removed: 1 }
+        );
+
+        // Editing an entry replaces it rather than appending another.
+        handle_key_events(key(KeyCode::Down), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        type_text(&mut app, "/local");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert_eq!(
+            block_path(&app),
+            Some(("/opt/bin:/usr/bin/local:/bin".to_owned(), false))
+        );
+
+        handle_key_events(key(KeyCode::Char('d')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('d')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('d')), &mut app).unwrap();
+        assert!(app.path_var_dirs.is_empty());
+        assert_eq!(app.path_list_state.selected(), None);
+        handle_key_events(key(KeyCode::Char('u')), &mut app).unwrap();
+        assert_eq!(app.path_var_dirs, [PathBuf::from("/opt/bin")]);
+
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+        let written = read_to_string(&app.config_path).unwrap();
+        assert!(written.starts_with(BLOCK_START));
+        assert!(written.contains("\nexport PATH='/opt/bin'\n"));
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            handle_key_events(key(KeyCode::Char(c)), app).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_new_var_flow_validates_and_stages() {
+        let (mut app, _dir) = create_test_app("export ENVELOPE_EXISTING=1\n");
+        app.shell_env_vars
+            .insert("ENVELOPE_EXISTING".to_owned(), "1".to_owned());
+        let count = app.env_vars.len();
+
+        handle_key_events(key(KeyCode::Char('a')), &mut app).unwrap();
+        type_text(&mut app, "1BAD");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(app
+            .name_error
+            .as_deref()
+            .unwrap()
+            .contains("not a valid name"));
+        assert!(app.editing);
+
+        for _ in 0..4 {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        type_text(&mut app, "ENVELOPE_EXISTING");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        assert!(app
+            .name_error
+            .as_deref()
+            .unwrap()
+            .contains("already defined"));
+
+        for _ in 0.."EXISTING".len() {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        type_text(&mut app, "NEW");
+        handle_key_events(key(KeyCode::Tab), &mut app).unwrap();
+        type_text(&mut app, "hello world");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+
+        assert!(!app.editing);
+        assert_eq!(app.name_error, None);
+        assert_eq!(app.env_vars.len(), count + 1);
+        assert_eq!(app.selected_value(), "hello world");
+        assert!(app
+            .pending_diff
+            .contains("+export ENVELOPE_NEW='hello world'\n"));
+
+        handle_key_events(key(KeyCode::Char('u')), &mut app).unwrap();
+        assert_eq!(app.env_vars.len(), count);
+        assert!(app.pending.is_empty());
+    }
+
+    #[test]
+    fn test_delete_removes_definitions_from_rc_files() {
+        let (mut app, dir) = create_test_app("export ENVELOPE_TEST=1\n");
+        let profile = dir.path().join(".profile");
+        write(&profile, "ENVELOPE_TEST=0\nexport ENVELOPE_TEST\n").unwrap();
+        select_env_var(&mut app, "ENVELOPE_TEST", "1");
+        let count = app.env_vars.len();
+
+        handle_key_events(key(KeyCode::Char('d')), &mut app).unwrap();
+
+        assert_eq!(app.confirm_unset, None);
+        assert_eq!(app.env_vars.len(), count - 1);
+        assert_eq!(app.pending.len(), 2);
+        write_to_config(&mut app).unwrap();
+        assert_eq!(
+            read_to_string(&profile).unwrap(),
+            "# ENVELOPE_TEST=0\n# export ENVELOPE_TEST\n"
+        );
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "# export ENVELOPE_TEST=1\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_inherited_var_asks_before_unsetting() {
+        let (mut app, _dir) = create_test_app("");
+        select_env_var(&mut app, "ENVELOPE_TEST", "1");
+        let count = app.env_vars.len();
+
+        handle_key_events(key(KeyCode::Char('d')), &mut app).unwrap();
+        assert_eq!(app.confirm_unset.as_deref(), Some("ENVELOPE_TEST"));
+        handle_key_events(key(KeyCode::Esc), &mut app).unwrap();
+        assert_eq!(app.env_vars.len(), count);
+
+        handle_key_events(key(KeyCode::Char('d')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('y')), &mut app).unwrap();
+        assert_eq!(app.env_vars.len(), count - 1);
+        assert!(app.pending_diff.contains("+unset ENVELOPE_TEST\n"));
+    }
+
+    #[test]
+    fn test_rename_previews_and_stages_references() {
+        let (mut app, dir) = create_test_app("export ENVELOPE_URL=x\necho $ENVELOPE_URL\n");
+        let profile = dir.path().join(".profile");
+        write(&profile, "[ -n \"${ENVELOPE_URL}\" ] && echo ok\n").unwrap();
+        select_env_var(&mut app, "ENVELOPE_URL", "x");
+
+        handle_key_events(key(KeyCode::Char('R')), &mut app).unwrap();
+        assert_eq!(app.rename_preview.len(), 3);
+        assert!(
+            app.rename_preview[0].ends_with(".profile:1: [ -n \"${ENVELOPE_URL}\" ] && echo ok")
+        );
+        for _ in 0.."URL".len() {
+            handle_key_events(key(KeyCode::Backspace), &mut app).unwrap();
+        }
+        type_text(&mut app, "ENDPOINT");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+
+        assert_eq!(app.rename_from, None);
+        assert_eq!(app.env_vars.last().unwrap().0, "ENVELOPE_ENDPOINT");
+        write_to_config(&mut app).unwrap();
+        assert_eq!(
+            read_to_string(&app.config_path).unwrap(),
+            "export ENVELOPE_ENDPOINT=x\necho $ENVELOPE_ENDPOINT\n"
+        );
+        assert_eq!(
+            read_to_string(&profile).unwrap(),
+            "[ -n \"${ENVELOPE_ENDPOINT}\" ] && echo ok\n"
+        );
+    }
+
+    #[test]
+    fn test_system_scope_edits_environment_file() {
+        let (mut app, dir) = create_test_app("");
+        app.system_env_path = dir.path().join("environment");
+        write(&app.system_env_path, "LANG=C\nENVELOPE_TEST=old\n").unwrap();
+        select_env_var(&mut app, "ENVELOPE_TEST", "old");
+
+        handle_key_events(key(KeyCode::Char('g')), &mut app).unwrap();
+        assert_eq!(app.scope, Scope::System);
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        type_text(&mut app, " and new");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+
+        assert_eq!(
+            read_to_string(&app.system_env_path).unwrap(),
+            "LANG=C\nENVELOPE_TEST=\"old and new\"\n"
+        );
+        assert_eq!(read_to_string(&app.config_path).unwrap(), "");
+        assert_eq!(app.system_env_vars["ENVELOPE_TEST"], "old and new");
+    }
+
+    #[test]
+    fn test_session_scope_edits_winning_drop_in() {
+        let (mut app, _dir) = create_test_app("");
+        let [user_dir, system_dir] = [app.session_dirs[0].clone(), app.session_dirs[1].clone()];
+        std::fs::create_dir_all(&user_dir).unwrap();
+        std::fs::create_dir_all(&system_dir).unwrap();
+        write(system_dir.join("10-editor.conf"), "EDITOR=vi\nPAGER=less\n").unwrap();
+        write(user_dir.join("50-editor.conf"), "EDITOR=${PAGER}-nano\n").unwrap();
+        app.refresh_session_env();
+        assert_eq!(app.session_env["EDITOR"].value, "less-nano");
+        assert_eq!(
+            app.session_env["EDITOR"].path,
+            user_dir.join("50-editor.conf")
+        );
+        select_env_var(&mut app, "EDITOR", "less-nano");
+
+        for _ in 0..2 {
+            handle_key_events(key(KeyCode::Char('g')), &mut app).unwrap();
+        }
+        assert_eq!(app.scope, Scope::SessionUser);
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        type_text(&mut app, " -w");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        app.stage(set_var("ENVELOPE_TEST", "1"));
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+
+        assert_eq!(
+            read_to_string(user_dir.join("50-editor.conf")).unwrap(),
+            "EDITOR=\"less-nano -w\"\n"
+        );
+        assert!(read_to_string(user_dir.join("60-envelope.conf"))
+            .unwrap()
+            .contains("ENVELOPE_TEST=1\n"));
+        assert_eq!(read_to_string(&app.config_path).unwrap(), "");
+        assert_eq!(app.session_env["EDITOR"].value, "less-nano -w");
+    }
+
+    #[test]
+    fn test_dotenv_mode_edits_file_in_place() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".env");
+        write(
+            &path,
+            "# app\nHOST=localhost # dev only\nURL=\"http://$HOST\nsecond line\"\n\n# keys\nKEY='s3cret'\n",
+        )
+        .unwrap();
+        let mut app = App::in_home(dir.path());
+        app.open_dotenv(path.clone()).unwrap();
+        assert_eq!(
+            app.env_vars,
+            vec![
+                ("HOST".into(), "localhost".into()),
+                ("URL".into(), "http://localhost\nsecond line".into()),
+                ("KEY".into(), "s3cret".into()),
+            ]
+        );
+
+        app.selected_env_var = 2;
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        type_text(&mut app, " two");
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('g')), &mut app).unwrap();
+        assert_eq!(app.scope, Scope::Dotenv);
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            "# app\nHOST=localhost # dev only\nURL=\"http://$HOST\nsecond line\"\n\n# keys\nKEY=\"s3cret two\"\n"
+        );
+        assert!(!app.config_path.exists());
+    }
+
+    #[test]
+    fn test_compare_adds_missing_keys_to_dotenv() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".env");
+        let example = dir.path().join(".env.example");
+        write(&path, "# local\nAPI_KEY=changeme\n").unwrap();
+        write(
+            &example,
+            "API_KEY=changeme\nDB_URL=postgres://localhost\nTOKEN=\n",
+        )
+        .unwrap();
+        let mut app = App::in_home(dir.path());
+        app.open_dotenv(path.clone()).unwrap();
+        app.compare_with(example).unwrap();
+        let comparison = app.comparison().unwrap();
+        assert_eq!(comparison.missing.len(), 2);
+        assert_eq!(comparison.placeholders, vec!["API_KEY".to_owned()]);
+
+        handle_key_events(key(KeyCode::Char('m')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+
+        let contents = read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# local\nAPI_KEY=changeme\n"));
+        assert!(contents.contains("\nDB_URL=postgres://localhost\n"));
+        assert!(contents.contains("\nTOKEN=\"\"\n"));
+        let comparison = app.comparison().unwrap();
+        assert!(comparison.missing.is_empty());
+        assert_eq!(comparison.empty, vec!["TOKEN".to_owned()]);
+    }
+
+    #[test]
+    fn test_provenance_and_origin_filter() {
+        let (mut app, dir) = create_test_app("# mine\nexport ENVELOPE_A=1\n");
+        app.system_env_path = dir.path().join("environment");
+        write(&app.system_env_path, "ENVELOPE_B=2\n").unwrap();
+        app.env_vars = vec![
+            ("ENVELOPE_A".into(), "1".into()),
+            ("ENVELOPE_B".into(), "2".into()),
+            ("ENVELOPE_C".into(), "3".into()),
+        ];
+        app.refresh_provenance();
+        assert_eq!(
+            app.provenance["ENVELOPE_A"].to_string(),
+            format!("{}:2: export ENVELOPE_A=1", app.config_path.display())
+        );
+        assert!(!app.provenance.contains_key("ENVELOPE_C"));
+
+        let mut shown = Vec::new();
+        for _ in 0..4 {
+            handle_key_events(key(KeyCode::Char('o')), &mut app).unwrap();
+            shown.push((app.visible_env_vars(), app.selected_env_var));
+        }
+        assert_eq!(
+            shown,
+            vec![(vec![1], 1), (vec![0], 0), (vec![2], 2), (vec![0, 1, 2], 2)]
+        );
+        assert_eq!(app.origin_filter, None);
+        handle_key_events(key(KeyCode::Up), &mut app).unwrap();
+        assert_eq!(app.selected_env_var, 1);
+        assert_eq!(app.env_list_state.selected(), Some(1));
+    }
+
+    #[test]
+    fn test_shell_mode_changes_startup_chain_and_provenance() {
+        let (mut app, dir) = create_test_app("export ENVELOPE_A=1\n");
+        write(
+            dir.path().join(".profile"),
+            "export ENVELOPE_B=2\n. \"$HOME/.bashrc\"\n",
+        )
+        .unwrap();
+        app.refresh_provenance();
+        let chain = |app: &App| -> Vec<(PathBuf, usize)> {
+            app.startup_chain
+                .iter()
+                .map(|entry| (entry.path.clone(), entry.depth))
+                .collect()
+        };
+        assert_eq!(chain(&app), vec![(app.config_path.clone(), 0)]);
+        assert!(!app.provenance.contains_key("ENVELOPE_B"));
+
+        handle_key_events(key(KeyCode::Char('l')), &mut app).unwrap();
+
+        assert!(app.shell_mode.login && app.shell_mode.interactive);
+        assert_eq!(
+            chain(&app),
+            vec![
+                (dir.path().join(".profile"), 0),
+                (app.config_path.clone(), 1),
+            ]
+        );
+        assert_eq!(app.provenance["ENVELOPE_B"].line, 1);
+        assert_eq!(app.provenance["ENVELOPE_A"].path, app.config_path);
+    }
+
+    #[test]
+    fn test_step_through_path_evolution() {
+        let (mut app, dir) = create_test_app("export PATH=\"$HOME/bin:$PATH\"\n");
+        app.system_env_path = dir.path().join("environment");
+        write(&app.system_env_path, "PATH=/usr/bin\n").unwrap();
+        app.refresh_provenance();
+
+        handle_key_events(key(KeyCode::Char('p')), &mut app).unwrap();
+        assert_eq!(app.path_step, Some(1));
+        let step = &app.path_steps[1];
+        assert_eq!(
+            step.dirs,
+            vec![
+                dir.path().join("bin").display().to_string(),
+                "/usr/bin".to_owned()
+            ]
+        );
+        assert_eq!((step.added.clone(), step.line), (vec![0], 1));
+
+        handle_key_events(key(KeyCode::Left), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Left), &mut app).unwrap();
+        assert_eq!(app.path_step, Some(0));
+        assert_eq!(app.path_steps[0].path, app.system_env_path);
+        handle_key_events(key(KeyCode::Char('p')), &mut app).unwrap();
+        assert_eq!(app.path_step, None);
+    }
+
+    #[test]
+    #[ignore = "starts bash"]
+    fn test_capture_flags_runtime_only_vars() {
+        let (mut app, _dir) = create_test_app(
+            "export ENVELOPE_DECLARED=1\neval \"export ENVELOPE_COMPUTED=$(echo yes)\"\n",
+        );
+        app.shell_program = Some(PathBuf::from("bash"));
+
+        handle_key_events(key(KeyCode::Char('v')), &mut app).unwrap();
+
+        assert!(app.show_capture);
+        assert_eq!(app.capture_error, None);
+        let computed = app
+            .runtime_diff
+            .iter()
+            .find(|diff| diff.name == "ENVELOPE_COMPUTED")
             .unwrap();
+        assert!(computed.runtime_only());
+        assert_eq!(computed.runtime.as_deref(), Some("yes"));
+        let declared = app
+            .runtime_diff
+            .iter()
+            .find(|diff| diff.name == "ENVELOPE_DECLARED")
+            .unwrap();
+        assert!(!declared.runtime_only());
+        handle_key_events(key(KeyCode::Char('v')), &mut app).unwrap();
+        assert!(!app.show_capture);
+    }
 
-        write_to_config(&app, config_var, &mut read_only_file);
+    #[test]
+    fn test_edits_go_to_picked_shells() {
+        let (mut app, dir) = create_test_app("");
+        let zshrc = dir.path().join(".zshrc");
+        write(&zshrc, "export EDITOR=vi\n").unwrap();
+        app.shells = vec![Dialect::Posix, Dialect::Zsh];
+
+        handle_key_events(key(KeyCode::Char('f')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Down), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char(' ')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('f')), &mut app).unwrap();
+        assert_eq!(
+            (app.picker, app.extra_targets.clone()),
+            (None, vec![Dialect::Zsh])
+        );
+        assert_eq!(app.dialect_for(&zshrc), Dialect::Zsh);
+        assert!(app.rc_files().contains(&zshrc));
+
+        app.stage(set_var("ENVELOPE_TEST", "1"));
+        handle_key_events(key(KeyCode::Char('s')), &mut app).unwrap();
+        assert!(read_to_string(&app.config_path)
+            .unwrap()
+            .contains("export ENVELOPE_TEST='1'"));
+        assert!(read_to_string(&zshrc)
+            .unwrap()
+            .contains("export ENVELOPE_TEST='1'"));
+
+        app.use_shell(PathBuf::from("/usr/bin/zsh")).unwrap();
+        assert_eq!((app.dialect, &app.config_path), (Dialect::Zsh, &zshrc));
+        assert_eq!(app.shells, vec![Dialect::Zsh, Dialect::Posix]);
+        assert!(app.extra_targets.is_empty());
+        assert!(app.shell_env_vars.contains_key("EDITOR"));
+        assert!(app.use_shell(PathBuf::from("/bin/nu")).is_err());
+    }
+
+    #[test]
+    fn test_unreadable_config_is_read_only() {
+        let (mut app, _dir) = create_test_app("export EDITOR=vi\n");
+        app.diagnostics = vec![Diagnostic::ConfigUnreadable {
+            path: app.config_path.clone(),
+            error: "Permission denied".to_owned(),
+        }];
+
+        app.stage(set_var("ENVELOPE_TEST", "1"));
+
+        assert!(app.pending.is_empty());
+        assert!(matches!(
+            app.diagnostics.last(),
+            Some(Diagnostic::Failed(message)) if message.ends_with("is read-only")
+        ));
+        app.scope = Scope::SessionUser;
+        app.stage(set_var("ENVELOPE_TEST", "1"));
+        assert_eq!(app.pending.len(), 1);
+
+        // Without $HOME there's no config file to edit at all.
+        app.scope = Scope::User;
+        app.config_path = PathBuf::new();
+        app.diagnostics = vec![Diagnostic::NoHome];
+        app.stage(set_var("ENVELOPE_TEST", "2"));
+        assert_eq!(app.pending.len(), 1);
+        assert_eq!(app.diagnostics.len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_value_that_is_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (mut app, _dir) = create_test_app("");
+        select_env_var(&mut app, "ENVELOPE_BYTES", "");
+        app.env_vars.last_mut().unwrap().1 = OsStr::from_bytes(b"caf\xe9").to_owned();
+
+        handle_key_events(key(KeyCode::Char('e')), &mut app).unwrap();
+        assert_eq!(app.env_var_value, "caf\\xE9");
+        handle_key_events(key(KeyCode::Char('!')), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Enter), &mut app).unwrap();
+
+        assert_eq!(
+            app.env_vars.last().unwrap().1,
+            OsStr::from_bytes(b"caf\xe9!")
+        );
+        // The bytes are kept in the list but can't go into the text config.
+        assert!(app.pending.is_empty());
+        assert!(matches!(
+            app.diagnostics.last(),
+            Some(Diagnostic::Failed(message)) if message.starts_with("ENVELOPE_BYTES")
+        ));
+
+        app.path_var_dirs = vec![
+            PathBuf::from("/bin"),
+            PathBuf::from(OsStr::from_bytes(b"/opt/\xff")),
+        ];
+        app.selected_path_dir = 0;
+        app.activated_list = ActiveList::PathList;
+        handle_key_events(key(KeyCode::Down), &mut app).unwrap();
+        assert_eq!(app.path_var_value, "/opt/\\xFF [not UTF-8]");
+    }
+
+    #[test]
+    fn test_confirm_staged_install_writes_copy_and_command() {
+        let (mut app, dir) = create_test_app("");
+        app.system_env_path = dir.path().join("environment");
+        write(&app.system_env_path, "LANG=C\n").unwrap();
+        app.scope = Scope::System;
+        app.stage(set_var("ENVELOPE_TEST", "1"));
+        app.stage_in(app.config_path.clone(), set_var("ENVELOPE_USER", "2"));
+        // As if the system file had turned out to need root.
+        app.confirm_install = vec![app.system_env_path.clone()];
+
+        handle_key_events(key(KeyCode::Char('y')), &mut app).unwrap();
+
+        assert!(app.pending.is_empty());
+        assert_eq!(read_to_string(&app.system_env_path).unwrap(), "LANG=C\n");
+        assert!(read_to_string(&app.config_path)
+            .unwrap()
+            .contains("export ENVELOPE_USER='2'"));
+        assert_eq!(app.install_commands.len(), 1);
+        let (staged, command) = &app.install_commands[0];
+        assert!(command.contains(&*staged.to_string_lossy()));
+        assert!(read_to_string(staged)
+            .unwrap()
+            .contains("\nENVELOPE_TEST=1\n"));
+        std::fs::remove_file(staged).unwrap();
+    }
+
+    #[test]
+    fn test_quit_keys_confirm_pending_changes() {
+        let dir = TempDir::new().unwrap();
+        let mut app = App::in_home(dir.path());
+        app.stage_in(
+            app.config_path.clone(),
+            ConfigEdit::UnsetVar("PAGER".to_owned()),
+        );
+
+        handle_key_events(key(KeyCode::Char('q')), &mut app).unwrap();
+        assert!(app.running && app.confirm_quit);
+        handle_key_events(key(KeyCode::Esc), &mut app).unwrap();
+        assert!(app.running && !app.confirm_quit);
+        assert!(!app.pending.is_empty());
+
+        handle_key_events(key(KeyCode::Esc), &mut app).unwrap();
+        handle_key_events(key(KeyCode::Char('n')), &mut app).unwrap();
+        assert!(!app.running);
+        assert!(app.pending.is_empty());
+        assert!(!app.config_path.exists());
+    }
+
+    #[test]
+    fn test_write_to_config_file_write_error() {
+        let (mut app, dir) = create_test_app("");
+        // A directory can be read as a path but never written as a file.
+        app.config_path = dir.path().to_path_buf();
 
-        remove_file(path).unwrap();
+        app.stage(set_var("ERROR_VAR", "value"));
+        assert!(write_to_config(&mut app).is_err());
     }
 }
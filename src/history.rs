@@ -0,0 +1,352 @@
+use crate::pending::PendingChanges;
+use crate::writer::{write_atomic, DEFAULT_BACKUPS};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+/// A user-visible operation that can be undone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    SetValue {
+        name: String,
+        old: String,
+        new: String,
+    },
+    EditPath {
+        old: String,
+        new: String,
+    },
+    InsertPath {
+        dir: String,
+        position: usize,
+    },
+    RemovePath {
+        dir: String,
+    },
+    MovePath {
+        dir: String,
+        position: usize,
+    },
+    DedupePath {
+        removed: usize,
+    },
+    CreateVar {
+        name: String,
+        value: String,
+    },
+    DeleteVar {
+        name: String,
+    },
+    RenameVar {
+        from: String,
+        to: String,
+    },
+    Apply {
+        files: usize,
+    },
+    Discard {
+        edits: usize,
+    },
+    StageInstall {
+        files: usize,
+    },
+    AddMissing {
+        count: usize,
+        from: String,
+    },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::SetValue { name, old, new } => {
+                write!(f, "set {}: {:?} -> {:?}", name, old, new)
+            }
+            Operation::EditPath { old, new } => write!(f, "edit PATH entry {} -> {}", old, new),
+            Operation::InsertPath { dir, position } => {
+                write!(f, "insert PATH entry {} at {}", dir, position)
+            }
+            Operation::RemovePath { dir } => write!(f, "remove PATH entry {}", dir),
+            Operation::MovePath { dir, position } => {
+                write!(f, "move PATH entry {} to {}", dir, position)
+            }
+            Operation::DedupePath { removed } => {
+                write!(f, "remove {} duplicate PATH dir(s)", removed)
+            }
+            Operation::CreateVar { name, value } => write!(f, "create {}={:?}", name, value),
+            Operation::DeleteVar { name } => write!(f, "delete {}", name),
+            Operation::RenameVar { from, to } => write!(f, "rename {} -> {}", from, to),
+            Operation::Apply { files } => write!(f, "apply changes to {} file(s)", files),
+            Operation::Discard { edits } => write!(f, "discard {} pending edit(s)", edits),
+            Operation::StageInstall { files } => {
+                write!(f, "stage {} file(s) for privileged install", files)
+            }
+            Operation::AddMissing { count, from } => {
+                write!(f, "add {} missing key(s) from {}", count, from)
+            }
+        }
+    }
+}
+
+/// The parts of `App` an operation can change.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub env_vars: Vec<(OsString, OsString)>,
+    pub path_var_dirs: Vec<PathBuf>,
+    pub shell_env_vars: HashMap<String, String>,
+    pub system_env_vars: HashMap<String, String>,
+    pub pending: PendingChanges,
+    pub saved_env_vars: Vec<(OsString, OsString)>,
+    pub saved_path_var_dirs: Vec<PathBuf>,
+}
+
+/// A config file written by an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    /// Contents before the write, `None` if the file did not exist.
+    pub before: Option<String>,
+    pub after: String,
+}
+
+impl FileChange {
+    /// Puts back the contents from before the write.
+    pub fn revert(&self) -> io::Result<()> {
+        self.ensure_unchanged(&self.after)?;
+        match &self.before {
+            Some(before) => write_atomic(&self.path, before.as_bytes(), DEFAULT_BACKUPS),
+            None => fs::remove_file(&self.path),
+        }
+    }
+
+    /// Writes the contents again after a revert.
+    pub fn reapply(&self) -> io::Result<()> {
+        self.ensure_unchanged(self.before.as_deref().unwrap_or_default())?;
+        write_atomic(&self.path, self.after.as_bytes(), DEFAULT_BACKUPS)
+    }
+
+    /// Refuses to clobber edits made outside envelope since the write.
+    fn ensure_unchanged(&self, expected: &str) -> io::Result<()> {
+        let current = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+        if current == expected {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "{} was changed outside envelope",
+                self.path.display()
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub operation: Operation,
+    pub before: Snapshot,
+    pub after: Snapshot,
+    pub files: Vec<FileChange>,
+}
+
+/// Undo and redo stacks of operations.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    pub undo: Vec<HistoryEntry>,
+    pub redo: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Records a new operation, which invalidates anything that could be redone.
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.undo.push(entry);
+        self.redo.clear();
+    }
+
+    /// Takes the most recent operation for undoing. Any files it wrote are
+    /// reverted first, and only if none was changed since; if that fails
+    /// the entry stays on the undo stack.
+    pub fn undo(&mut self) -> io::Result<Option<Snapshot>> {
+        let Some(entry) = self.undo.last() else {
+            return Ok(None);
+        };
+        for file in &entry.files {
+            file.ensure_unchanged(&file.after)?;
+        }
+        for file in entry.files.iter().rev() {
+            file.revert()?;
+        }
+        let entry = self.undo.pop().expect("entry checked above");
+        let before = entry.before.clone();
+        self.redo.push(entry);
+        Ok(Some(before))
+    }
+
+    /// Takes the most recently undone operation for redoing, checking every
+    /// file it writes the way `undo` does.
+    pub fn redo(&mut self) -> io::Result<Option<Snapshot>> {
+        let Some(entry) = self.redo.last() else {
+            return Ok(None);
+        };
+        for file in &entry.files {
+            file.ensure_unchanged(file.before.as_deref().unwrap_or_default())?;
+        }
+        for file in &entry.files {
+            file.reapply()?;
+        }
+        let entry = self.redo.pop().expect("entry checked above");
+        let after = entry.after.clone();
+        self.undo.push(entry);
+        Ok(Some(after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(value: &str, files: Vec<FileChange>) -> HistoryEntry {
+        let snapshot = |value: &str| Snapshot {
+            env_vars: vec![("A".into(), value.into())],
+            ..Snapshot::default()
+        };
+        HistoryEntry {
+            operation: Operation::Apply { files: files.len() },
+            before: snapshot(""),
+            after: snapshot(value),
+            files,
+        }
+    }
+
+    #[test]
+    fn test_history_undo_redo_order() {
+        let mut history = History::default();
+        history.push(entry("1", Vec::new()));
+        history.push(entry("2", Vec::new()));
+
+        assert_eq!(history.undo().unwrap().unwrap().env_vars[0].1, "");
+        assert_eq!(history.redo.len(), 1);
+        assert_eq!(history.redo().unwrap().unwrap().env_vars[0].1, "2");
+        assert!(history.redo().unwrap().is_none());
+
+        history.undo().unwrap();
+        history.push(entry("3", Vec::new()));
+        assert!(history.redo.is_empty());
+        assert_eq!(history.undo.len(), 2);
+    }
+
+    #[test]
+    fn test_history_reverts_written_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "after\n").unwrap();
+        let mut history = History::default();
+        history.push(entry(
+            "x",
+            vec![FileChange {
+                path: path.clone(),
+                before: Some("before\n".to_owned()),
+                after: "after\n".to_owned(),
+            }],
+        ));
+
+        history.undo().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "before\n");
+        history.redo().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+    }
+
+    #[test]
+    fn test_history_refuses_to_clobber_outside_edits() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "edited by hand\n").unwrap();
+        let mut history = History::default();
+        history.push(entry(
+            "x",
+            vec![FileChange {
+                path: path.clone(),
+                before: None,
+                after: "after\n".to_owned(),
+            }],
+        ));
+
+        assert!(history.undo().is_err());
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "edited by hand\n");
+    }
+
+    #[test]
+    fn test_history_removes_created_files_and_redo_checks_too() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".zshrc");
+        fs::write(&path, "after\n").unwrap();
+        let mut history = History::default();
+        history.push(entry(
+            "x",
+            vec![FileChange {
+                path: path.clone(),
+                before: None,
+                after: "after\n".to_owned(),
+            }],
+        ));
+
+        history.undo().unwrap();
+        assert!(!path.exists());
+
+        // Recreated by hand, the file isn't overwritten by redoing.
+        fs::write(&path, "by hand\n").unwrap();
+        assert!(history.redo().is_err());
+        assert_eq!(history.redo.len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "by hand\n");
+
+        fs::remove_file(&path).unwrap();
+        history.redo().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+    }
+
+    #[test]
+    fn test_history_with_nothing_to_undo_or_redo() {
+        let mut history = History::default();
+        assert!(history.undo().unwrap().is_none());
+        assert!(history.redo().unwrap().is_none());
+
+        history.push(entry("1", Vec::new()));
+        assert!(history.redo().unwrap().is_none());
+        assert_eq!(history.undo.len(), 1);
+    }
+
+    #[test]
+    fn test_history_checks_every_file_before_writing_any() {
+        let dir = TempDir::new().unwrap();
+        let bashrc = dir.path().join(".bashrc");
+        let environment = dir.path().join("environment");
+        fs::write(&bashrc, "edited by hand\n").unwrap();
+        fs::write(&environment, "after\n").unwrap();
+        let change = |path: &PathBuf| FileChange {
+            path: path.clone(),
+            before: Some("before\n".to_owned()),
+            after: "after\n".to_owned(),
+        };
+        let mut history = History::default();
+        history.push(entry("x", vec![change(&bashrc), change(&environment)]));
+
+        // Undo reverts the last file first, so it's the one checked late.
+        assert!(history.undo().is_err());
+        assert_eq!(fs::read_to_string(&environment).unwrap(), "after\n");
+        assert_eq!(history.undo.len(), 1);
+
+        fs::write(&bashrc, "after\n").unwrap();
+        history.undo().unwrap();
+        fs::write(&environment, "edited by hand\n").unwrap();
+        assert!(history.redo().is_err());
+        assert_eq!(fs::read_to_string(&bashrc).unwrap(), "before\n");
+        assert_eq!(history.redo.len(), 1);
+    }
+}
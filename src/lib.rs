@@ -12,3 +12,60 @@ pub mod tui;
 
 /// Event handler.
 pub mod handler;
+
+/// Shell configuration parser.
+pub mod parser;
+
+/// Shell dialects.
+pub mod dialect;
+
+/// Shell config file editing.
+pub mod config;
+
+/// Atomic config file writes.
+pub mod writer;
+
+/// Line diffs.
+pub mod diff;
+
+/// Staged config changes.
+pub mod pending;
+
+/// Undo and redo history.
+pub mod history;
+
+/// systemd environment.d drop-ins.
+pub mod environment_d;
+
+/// Project .env files.
+pub mod dotenv;
+
+/// Comparing variables against a reference such as .env.example.
+pub mod compare;
+
+/// Where each variable is defined.
+pub mod provenance;
+
+/// Shell startup file chains.
+pub mod startup;
+
+/// Replaying PATH assignments through the startup chain.
+pub mod path_evolution;
+
+/// Capturing the environment a spawned shell ends up with.
+pub mod capture;
+
+/// Detecting the user's shells.
+pub mod shell;
+
+/// Errors and the problems envelope works around.
+pub mod error;
+
+/// Showing and editing values that aren't valid UTF-8.
+pub mod escape;
+
+/// Quoting values for shell config files.
+pub mod quote;
+
+/// Searching variable names, values and PATH directories.
+pub mod search;
@@ -1,27 +1,81 @@
-use envelope::app::{App, AppResult, get_shell_vars};
+use envelope::app::{App, AppResult};
+use envelope::error::Error;
 use envelope::event::{Event, EventHandler};
 use envelope::handler::handle_key_events;
 use envelope::tui::Tui;
 use ratatui::{backend::CrosstermBackend, Terminal};
+use std::env;
 use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-fn main() -> AppResult<()> {
-    let mut app = App::new();
+const USAGE: &str = "usage: envelope [--dotenv <path>] [--compare <path>] [--shell <program>]";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("envelope: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> AppResult<()> {
+    let mut dotenv = None;
+    let mut reference = None;
+    let mut shell = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (target, kind) = match arg.as_str() {
+            "--dotenv" => (&mut dotenv, "path"),
+            "--compare" => (&mut reference, "path"),
+            "--shell" => (&mut shell, "program"),
+            _ => return Err(Error::Usage(format!("unknown argument {}; {}", arg, USAGE))),
+        };
+        match args.next() {
+            Some(value) => *target = Some(PathBuf::from(value)),
+            None => return Err(Error::Usage(format!("{} needs a {}; {}", arg, kind, USAGE))),
+        }
+    }
+    let mut app = match dotenv {
+        Some(path) => App::with_dotenv(path)?,
+        None => App::new(),
+    };
+    if let Some(program) = shell {
+        app.use_shell(program)?;
+    }
+    if let Some(path) = reference {
+        app.compare_with(path)?;
+    }
     let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
     let events = EventHandler::new();
+    app.events = Some(events.sender());
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
+    let result = run_loop(&mut tui, &mut app);
+    tui.exit()?;
+    result
+}
+
+/// Draws and handles events until the app quits. A key that fails is
+/// reported in the status area rather than ending the session.
+fn run_loop(tui: &mut Tui<CrosstermBackend<io::Stdout>>, app: &mut App) -> AppResult<()> {
     while app.running {
-        tui.draw(&mut app)?;
+        tui.draw(app)?;
 
         match tui.events.next()? {
-            Event::Key(key_event) => handle_key_events(key_event, &mut app)?,
+            Event::Key(key_event) => {
+                if let Err(error) = handle_key_events(key_event, app) {
+                    app.report(error);
+                }
+            }
             Event::Mouse(_) => {}
             Event::Resize(_, _) => {}
+            Event::Captured(result) => app.finish_capture(result),
         }
     }
-    tui.exit()?;
     Ok(())
 }
@@ -0,0 +1,1374 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+/// A parsed shell configuration file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Script {
+    /// Statements in source order.
+    pub statements: Vec<Statement>,
+}
+
+/// A single simple command found in a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    /// What the statement does.
+    pub kind: StatementKind,
+    /// Byte range of the statement within the source.
+    pub span: Range<usize>,
+    /// Line (1-based) on which the statement starts.
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    /// One or more variable assignments, e.g. `export A=1 B=2` or `declare -x A`.
+    Assignment {
+        command: AssignmentCommand,
+        exported: bool,
+        /// Whether the names lose the export attribute, as with `export -n`
+        /// or `declare +x`.
+        unexported: bool,
+        assignments: Vec<Assignment>,
+    },
+    /// `unset NAME...`
+    Unset(Vec<String>),
+    /// `source FILE` or `. FILE`
+    Source(Word),
+    /// Any other command, kept so callers can inspect it.
+    Command(Vec<Word>),
+}
+
+/// The builtin, if any, that introduced an assignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignmentCommand {
+    Bare,
+    Export,
+    Declare,
+    Typeset,
+    Local,
+    Readonly,
+    /// csh `set` and fish `set`.
+    Set,
+    /// csh `setenv`.
+    Setenv,
+}
+
+/// A single `NAME=value` (or bare `NAME`) within an assignment statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    /// `None` for `export NAME` without a value.
+    pub value: Option<Value>,
+    /// Whether the assignment used `+=`.
+    pub append: bool,
+    /// Byte range of the whole assignment word(s).
+    pub span: Range<usize>,
+    /// Byte range of the value, empty when there is none.
+    pub value_span: Range<usize>,
+    /// Line (1-based) on which the assignment starts.
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(Word),
+    Array(Vec<Word>),
+    /// A colon-separated list written as separate words, such as zsh's
+    /// `path=(...)` or fish's `set -gx PATH a b`.
+    List(Vec<Word>),
+}
+
+/// A shell word made of literal, quoted and expansion parts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text, with escapes already removed.
+    Literal(String),
+    /// `'...'`
+    SingleQuoted(String),
+    /// `"..."`
+    DoubleQuoted(Vec<WordPart>),
+    /// `$'...'`, stored decoded.
+    AnsiC(String),
+    /// `$NAME`, `${NAME}` or `${NAME<modifier>}`.
+    Parameter {
+        name: String,
+        modifier: Option<String>,
+        braced: bool,
+    },
+    /// `$(...)`, `$((...))` or `` `...` ``.
+    CommandSubstitution { command: String, backtick: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Value {
+    /// The unquoted value with expansions left in their source form.
+    pub fn to_literal(&self) -> String {
+        match self {
+            Value::Scalar(word) => word.to_literal(),
+            Value::Array(words) => words
+                .iter()
+                .map(Word::to_literal)
+                .collect::<Vec<_>>()
+                .join(" "),
+            Value::List(words) => words
+                .iter()
+                .map(Word::to_literal)
+                .collect::<Vec<_>>()
+                .join(":"),
+        }
+    }
+
+    /// Expands parameters in every word using `lookup`, joining list and
+    /// array elements the way `to_literal` does.
+    pub fn expand(&self, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+        let separator = match self {
+            Value::Scalar(word) => return word.expand(lookup),
+            Value::Array(_) => " ",
+            Value::List(_) => ":",
+        };
+        self.words()
+            .iter()
+            .map(|word| word.expand(lookup))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    /// The words the value is made of.
+    pub fn words(&self) -> &[Word] {
+        match self {
+            Value::Scalar(word) => std::slice::from_ref(word),
+            Value::Array(words) | Value::List(words) => words,
+        }
+    }
+
+    /// Names of all parameters referenced by the value.
+    pub fn parameters(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(word) => word.parameters(),
+            Value::Array(words) | Value::List(words) => {
+                words.iter().flat_map(Word::parameters).collect()
+            }
+        }
+    }
+}
+
+impl Word {
+    /// The unquoted text of the word with expansions left in their source form.
+    pub fn to_literal(&self) -> String {
+        let mut out = String::new();
+        literal_parts(&self.parts, &mut out);
+        out
+    }
+
+    /// Expands parameters using `lookup`. Command substitutions cannot be
+    /// evaluated statically and are left in their source form.
+    pub fn expand(&self, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+        let mut out = String::new();
+        expand_parts(&self.parts, lookup, &mut out);
+        out
+    }
+
+    /// Whether the word contains no expansions at all.
+    pub fn is_static(&self) -> bool {
+        fn check(parts: &[WordPart]) -> bool {
+            parts.iter().all(|part| match part {
+                WordPart::Parameter { .. } | WordPart::CommandSubstitution { .. } => false,
+                WordPart::DoubleQuoted(inner) => check(inner),
+                _ => true,
+            })
+        }
+        check(&self.parts)
+    }
+
+    /// Names of all parameters referenced by the word.
+    pub fn parameters(&self) -> Vec<String> {
+        fn collect(parts: &[WordPart], out: &mut Vec<String>) {
+            for part in parts {
+                match part {
+                    WordPart::Parameter { name, .. } => out.push(name.clone()),
+                    WordPart::DoubleQuoted(inner) => collect(inner, out),
+                    _ => {}
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.parts, &mut out);
+        out
+    }
+}
+
+fn literal_parts(parts: &[WordPart], out: &mut String) {
+    for part in parts {
+        match part {
+            WordPart::Literal(text) | WordPart::SingleQuoted(text) | WordPart::AnsiC(text) => {
+                out.push_str(text)
+            }
+            WordPart::DoubleQuoted(inner) => literal_parts(inner, out),
+            WordPart::Parameter {
+                name,
+                modifier,
+                braced,
+            } => match (modifier, braced) {
+                (None, false) => {
+                    out.push('$');
+                    out.push_str(name);
+                }
+                _ => {
+                    out.push_str("${");
+                    out.push_str(name);
+                    out.push_str(modifier.as_deref().unwrap_or_default());
+                    out.push('}');
+                }
+            },
+            WordPart::CommandSubstitution { command, backtick } => {
+                if *backtick {
+                    out.push('`');
+                    out.push_str(command);
+                    out.push('`');
+                } else {
+                    out.push_str("$(");
+                    out.push_str(command);
+                    out.push(')');
+                }
+            }
+        }
+    }
+}
+
+fn expand_parts(parts: &[WordPart], lookup: &dyn Fn(&str) -> Option<String>, out: &mut String) {
+    for part in parts {
+        match part {
+            WordPart::DoubleQuoted(inner) => expand_parts(inner, lookup, out),
+            WordPart::Parameter { name, modifier, .. } => {
+                out.push_str(&expand_parameter(name, modifier.as_deref(), lookup))
+            }
+            other => literal_parts(std::slice::from_ref(other), out),
+        }
+    }
+}
+
+fn expand_parameter(
+    name: &str,
+    modifier: Option<&str>,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> String {
+    let value = lookup(name);
+    let Some(modifier) = modifier else {
+        return value.unwrap_or_default();
+    };
+    let (colon, rest) = match modifier.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, modifier),
+    };
+    let mut chars = rest.chars();
+    let op = chars.next();
+    let operand = || {
+        parse_word(chars.as_str())
+            .map(|word| word.expand(lookup))
+            .unwrap_or_else(|_| chars.as_str().to_owned())
+    };
+    let unset = match &value {
+        None => true,
+        Some(value) => colon && value.is_empty(),
+    };
+    match op {
+        Some('-') | Some('=') if unset => operand(),
+        Some('+') if !unset => operand(),
+        Some('+') => String::new(),
+        _ => value.unwrap_or_default(),
+    }
+}
+
+/// Parses `source` as a POSIX sh/bash script.
+pub fn parse(source: &str) -> Result<Script, ParseError> {
+    let tokens = Lexer::new(source).tokenize()?;
+    let lines = LineIndex::new(source);
+    let mut statements = Vec::new();
+    let mut command: Vec<Token> = Vec::new();
+    let mut skip_next_word = false;
+
+    for token in tokens {
+        match token {
+            Token::Op(Op::Redirect, _) => skip_next_word = true,
+            Token::Op(..) => {
+                statements.extend(build_statement(std::mem::take(&mut command), &lines));
+            }
+            _ if skip_next_word => skip_next_word = false,
+            word => command.push(word),
+        }
+    }
+    statements.extend(build_statement(command, &lines));
+    Ok(Script { statements })
+}
+
+/// A simple command split into words, used by the dialects whose syntax is
+/// too far from POSIX sh to share its statement grammar.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub span: Range<usize>,
+    pub line: usize,
+}
+
+/// Splits `source` into simple commands. Parentheses are kept as words of
+/// their own so csh lists and fish command substitutions survive.
+pub(crate) fn simple_commands(source: &str) -> Result<Vec<SimpleCommand>, ParseError> {
+    let mut lexer = Lexer::new(source);
+    lexer.parens_as_words = true;
+    let tokens = lexer.tokenize()?;
+    let lines = LineIndex::new(source);
+    let mut commands = Vec::new();
+    let mut words: Vec<Word> = Vec::new();
+    let mut skip_next_word = false;
+
+    let mut finish = |words: Vec<Word>| {
+        if let (Some(first), Some(last)) = (words.first(), words.last()) {
+            let span = first.span.start..last.span.end;
+            commands.push(SimpleCommand {
+                line: lines.line(span.start),
+                span,
+                words,
+            });
+        }
+    };
+    for token in tokens {
+        match token {
+            Token::Op(Op::Redirect, _) => skip_next_word = true,
+            Token::Op(..) => finish(std::mem::take(&mut words)),
+            _ if skip_next_word => skip_next_word = false,
+            token => words.push(token.to_word()),
+        }
+    }
+    finish(words);
+    Ok(commands)
+}
+
+/// Parses a single word such as an assignment value.
+pub fn parse_word(source: &str) -> Result<Word, ParseError> {
+    let mut lexer = Lexer::new(source);
+    let word = lexer.word()?;
+    Ok(word)
+}
+
+impl Script {
+    /// Iterates over every assignment alongside the statement that contains it.
+    pub fn assignments(&self) -> impl Iterator<Item = (&Statement, &Assignment)> {
+        self.statements.iter().flat_map(|statement| {
+            let assignments = match &statement.kind {
+                StatementKind::Assignment { assignments, .. } => assignments.as_slice(),
+                _ => &[],
+            };
+            assignments
+                .iter()
+                .map(move |assignment| (statement, assignment))
+        })
+    }
+
+    /// Exported variables with their literal values, in the order they were
+    /// first exported. Later definitions override earlier ones, `export -n`
+    /// takes a variable out of the environment and `unset` removes it
+    /// entirely.
+    pub fn exported_vars(&self) -> Vec<(String, String)> {
+        let mut values: HashMap<&str, String> = HashMap::new();
+        let mut exported: Vec<&str> = Vec::new();
+
+        for statement in &self.statements {
+            match &statement.kind {
+                StatementKind::Assignment {
+                    exported: is_exported,
+                    unexported,
+                    assignments,
+                    ..
+                } => {
+                    for assignment in assignments {
+                        let name = assignment.name.as_str();
+                        if let Some(value) = &assignment.value {
+                            let value = value.to_literal();
+                            match values.get_mut(name) {
+                                Some(existing) if assignment.append => {
+                                    match assignment.value {
+                                        Some(Value::List(_)) => existing.push(':'),
+                                        Some(Value::Array(_)) => existing.push(' '),
+                                        _ => {}
+                                    }
+                                    existing.push_str(&value)
+                                }
+                                _ => {
+                                    values.insert(name, value);
+                                }
+                            }
+                        }
+                        if *is_exported && !exported.contains(&name) {
+                            exported.push(name);
+                        } else if *unexported {
+                            exported.retain(|exported| *exported != name);
+                        }
+                    }
+                }
+                StatementKind::Unset(names) => {
+                    for name in names {
+                        values.remove(name.as_str());
+                        exported.retain(|exported| exported != name);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        exported
+            .into_iter()
+            .filter_map(|name| values.remove(name).map(|value| (name.to_owned(), value)))
+            .collect()
+    }
+}
+
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(source: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { starts }
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        match self.starts.binary_search(&offset) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        }
+    }
+}
+
+const RESERVED_WORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "{", "}", "!",
+];
+
+fn build_statement(tokens: Vec<Token>, lines: &LineIndex) -> Option<Statement> {
+    let mut tokens: &[Token] = &tokens;
+    while let Some(Token::Word(word)) = tokens.first() {
+        if word.is_static() && RESERVED_WORDS.contains(&word.to_literal().as_str()) {
+            tokens = &tokens[1..];
+        } else {
+            break;
+        }
+    }
+    let first = tokens.first()?;
+    let span = first.span().start..tokens.last()?.span().end;
+    let line = lines.line(span.start);
+
+    let leading = tokens
+        .iter()
+        .take_while(|token| as_assignment(token, lines).is_some())
+        .count();
+    if leading > 0 {
+        if leading == tokens.len() {
+            let assignments = tokens
+                .iter()
+                .filter_map(|token| as_assignment(token, lines))
+                .collect();
+            return Some(Statement {
+                kind: StatementKind::Assignment {
+                    command: AssignmentCommand::Bare,
+                    exported: false,
+                    unexported: false,
+                    assignments,
+                },
+                span,
+                line,
+            });
+        }
+        // `FOO=1 command` only sets FOO for that command.
+        return Some(Statement {
+            kind: StatementKind::Command(tokens.iter().map(Token::to_word).collect()),
+            span,
+            line,
+        });
+    }
+
+    let name = match first {
+        Token::Word(word) if word.is_static() => word.to_literal(),
+        _ => String::new(),
+    };
+    let args = &tokens[1..];
+    let command = match name.as_str() {
+        "export" => Some(AssignmentCommand::Export),
+        "declare" => Some(AssignmentCommand::Declare),
+        "typeset" => Some(AssignmentCommand::Typeset),
+        "local" => Some(AssignmentCommand::Local),
+        "readonly" => Some(AssignmentCommand::Readonly),
+        _ => None,
+    };
+
+    let kind = if let Some(command) = command {
+        let mut flags = String::new();
+        let mut assignments = Vec::new();
+        for arg in args {
+            if let Some(assignment) = as_assignment(arg, lines) {
+                assignments.push(assignment);
+                continue;
+            }
+            let word = arg.to_word();
+            let text = word.to_literal();
+            if text.starts_with('-') || text.starts_with('+') {
+                flags.push_str(&text);
+            } else if is_name(&text) {
+                assignments.push(Assignment {
+                    name: text,
+                    value: None,
+                    append: false,
+                    span: word.span.clone(),
+                    value_span: word.span.end..word.span.end,
+                    line: lines.line(word.span.start),
+                });
+            }
+        }
+        let unexported = match command {
+            AssignmentCommand::Export => flags.contains('n'),
+            _ => flags.starts_with('+') && flags.contains('x'),
+        };
+        let exported = match command {
+            AssignmentCommand::Export => !unexported,
+            _ => flags.starts_with('-') && flags.contains('x'),
+        };
+        StatementKind::Assignment {
+            command,
+            exported,
+            unexported,
+            assignments,
+        }
+    } else {
+        match name.as_str() {
+            "unset" => StatementKind::Unset(
+                args.iter()
+                    .map(|arg| arg.to_word().to_literal())
+                    .filter(|arg| !arg.starts_with('-'))
+                    .collect(),
+            ),
+            "source" | "." if !args.is_empty() => StatementKind::Source(args[0].to_word()),
+            _ => StatementKind::Command(tokens.iter().map(Token::to_word).collect()),
+        }
+    };
+
+    Some(Statement { kind, span, line })
+}
+
+fn as_assignment(token: &Token, lines: &LineIndex) -> Option<Assignment> {
+    let (word, array) = match token {
+        Token::Word(word) => (word, None),
+        Token::Array {
+            prefix, elements, ..
+        } => (prefix, Some(elements)),
+        Token::Op(..) => return None,
+    };
+    let (name, append, value) = split_assignment_word(word)?;
+    let span = token.span();
+    let value_span = value.span.start..span.end;
+    let value = match array {
+        Some(elements) => Value::Array(elements.clone()),
+        None => Value::Scalar(value),
+    };
+    Some(Assignment {
+        name,
+        value: Some(value),
+        append,
+        value_span,
+        span,
+        line: lines.line(word.span.start),
+    })
+}
+
+/// Splits a `NAME=value` word into the name and the value word, returning
+/// `None` when the word is not an assignment.
+pub(crate) fn split_assignment_word(word: &Word) -> Option<(String, bool, Word)> {
+    let Some(WordPart::Literal(head)) = word.parts.first() else {
+        return None;
+    };
+    let eq = head.find('=')?;
+    let (name, append) = match head[..eq].strip_suffix('+') {
+        Some(name) => (name, true),
+        None => (&head[..eq], false),
+    };
+    if !is_name(name) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    let rest = &head[eq + 1..];
+    if !rest.is_empty() {
+        parts.push(WordPart::Literal(rest.to_owned()));
+    }
+    parts.extend(word.parts[1..].iter().cloned());
+    let value = Word {
+        parts,
+        span: word.span.start + eq + 1..word.span.end,
+    };
+    Some((name.to_owned(), append, value))
+}
+
+/// Whether `name` is a valid POSIX shell variable name.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Separator,
+    Redirect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(Word),
+    /// `NAME=(a b c)`
+    Array {
+        prefix: Word,
+        elements: Vec<Word>,
+        span: Range<usize>,
+    },
+    Op(Op, Range<usize>),
+}
+
+impl Token {
+    fn span(&self) -> Range<usize> {
+        match self {
+            Token::Word(word) => word.span.clone(),
+            Token::Array { span, .. } | Token::Op(_, span) => span.clone(),
+        }
+    }
+
+    fn to_word(&self) -> Word {
+        match self {
+            Token::Word(word) => word.clone(),
+            Token::Array {
+                prefix,
+                elements,
+                span,
+            } => {
+                let mut parts = prefix.parts.clone();
+                parts.push(WordPart::Literal("(".to_owned()));
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        parts.push(WordPart::Literal(" ".to_owned()));
+                    }
+                    parts.extend(element.parts.iter().cloned());
+                }
+                parts.push(WordPart::Literal(")".to_owned()));
+                Word {
+                    parts,
+                    span: span.clone(),
+                }
+            }
+            Token::Op(_, span) => Word {
+                parts: Vec::new(),
+                span: span.clone(),
+            },
+        }
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+    heredocs: Vec<(String, bool)>,
+    parens_as_words: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Lexer {
+            source,
+            pos: 0,
+            heredocs: Vec::new(),
+            parens_as_words: false,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.source[self.pos..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, offset: usize, message: &str) -> ParseError {
+        ParseError {
+            message: message.to_owned(),
+            line: self.source[..offset].matches('\n').count() + 1,
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            match self.peek() {
+                None => break,
+                Some(' ' | '\t' | '\r') => {
+                    self.bump();
+                }
+                Some('\\') if self.peek_at(1) == Some('\n') => {
+                    self.pos += 2;
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                Some('\n') => {
+                    let start = self.pos;
+                    self.bump();
+                    self.skip_heredocs();
+                    tokens.push(Token::Op(Op::Separator, start..self.pos));
+                }
+                Some(c @ ('(' | ')')) if self.parens_as_words => {
+                    let start = self.pos;
+                    self.bump();
+                    tokens.push(Token::Word(Word {
+                        parts: vec![WordPart::Literal(c.to_string())],
+                        span: start..self.pos,
+                    }));
+                }
+                Some(';' | '&' | '|' | '(' | ')') => {
+                    let start = self.pos;
+                    let c = self.bump();
+                    if c == Some('&') && self.peek() == Some('>') {
+                        self.bump();
+                        self.eat('>');
+                        tokens.push(Token::Op(Op::Redirect, start..self.pos));
+                        continue;
+                    }
+                    if matches!(c, Some(';' | '&' | '|')) && self.peek() == c {
+                        self.bump();
+                    }
+                    tokens.push(Token::Op(Op::Separator, start..self.pos));
+                }
+                Some('<' | '>') => {
+                    let start = self.pos;
+                    if self.source[self.pos..].starts_with("<<<") {
+                        self.pos += 3;
+                    } else if self.source[self.pos..].starts_with("<<") {
+                        self.pos += 2;
+                        let strip_tabs = self.eat('-');
+                        while matches!(self.peek(), Some(' ' | '\t')) {
+                            self.bump();
+                        }
+                        let delimiter = self.word()?;
+                        self.heredocs.push((delimiter.to_literal(), strip_tabs));
+                        tokens.push(Token::Op(Op::Redirect, start..self.pos));
+                        tokens.push(Token::Word(delimiter));
+                        continue;
+                    } else {
+                        self.bump();
+                        if matches!(self.peek(), Some('>' | '&' | '|')) {
+                            self.bump();
+                        }
+                    }
+                    tokens.push(Token::Op(Op::Redirect, start..self.pos));
+                }
+                Some(_) => {
+                    let start = self.pos;
+                    let word = self.word()?;
+                    if matches!(self.peek(), Some('<' | '>'))
+                        && matches!(word.parts.as_slice(), [WordPart::Literal(text)] if text.chars().all(|c| c.is_ascii_digit()))
+                    {
+                        // File descriptor number of a redirection such as `2>`.
+                        continue;
+                    }
+                    if !self.parens_as_words && self.peek() == Some('(') && is_array_prefix(&word) {
+                        self.bump();
+                        let elements = self.array_elements()?;
+                        tokens.push(Token::Array {
+                            prefix: word,
+                            elements,
+                            span: start..self.pos,
+                        });
+                    } else {
+                        tokens.push(Token::Word(word));
+                    }
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn skip_heredocs(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.source.len() {
+                let rest = &self.source[self.pos..];
+                let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+                let line = rest[..end].trim_end_matches('\n');
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line
+                };
+                self.pos += end;
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn array_elements(&mut self) -> Result<Vec<Word>, ParseError> {
+        let mut elements = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated array")),
+                Some(')') => {
+                    self.bump();
+                    return Ok(elements);
+                }
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.bump();
+                }
+                Some('\\') if self.peek_at(1) == Some('\n') => {
+                    self.pos += 2;
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                Some(c @ (';' | '&' | '|' | '<' | '>' | '(')) => {
+                    return Err(self.error(&format!("unexpected `{}` in array", c)))
+                }
+                Some(_) => elements.push(self.array_element()?),
+            }
+        }
+    }
+
+    /// A word inside an array, where zsh glob qualifiers such as the `(N-/)`
+    /// of `~/bin(N-/)` are part of the word.
+    fn array_element(&mut self) -> Result<Word, ParseError> {
+        let mut word = self.word()?;
+        while self.peek() == Some('(') {
+            let start = self.pos;
+            let mut depth = 0;
+            loop {
+                match self.bump() {
+                    None => return Err(self.error_at(start, "unterminated glob qualifier")),
+                    Some('(') => depth += 1,
+                    Some(')') if depth == 1 => break,
+                    Some(')') => depth -= 1,
+                    Some(_) => {}
+                }
+            }
+            let qualifier = &self.source[start..self.pos];
+            match word.parts.last_mut() {
+                Some(WordPart::Literal(text)) => text.push_str(qualifier),
+                _ => word.parts.push(WordPart::Literal(qualifier.to_owned())),
+            }
+            if !matches!(
+                self.peek(),
+                None | Some(' ' | '\t' | '\r' | '\n' | ')' | '(')
+            ) {
+                let rest = self.word()?;
+                word.parts.extend(rest.parts);
+            }
+            word.span.end = self.pos;
+        }
+        Ok(word)
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        let start = self.pos;
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>' => break,
+                '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        Some('\n') => {}
+                        Some(escaped) => {
+                            flush(&mut literal, &mut parts);
+                            parts.push(WordPart::Literal(escaped.to_string()));
+                        }
+                        None => literal.push('\\'),
+                    }
+                }
+                '\'' => {
+                    flush(&mut literal, &mut parts);
+                    let quote = self.pos;
+                    self.bump();
+                    let end = self.source[self.pos..]
+                        .find('\'')
+                        .ok_or_else(|| self.error_at(quote, "unterminated single quote"))?;
+                    parts.push(WordPart::SingleQuoted(
+                        self.source[self.pos..self.pos + end].to_owned(),
+                    ));
+                    self.pos += end + 1;
+                }
+                '"' => {
+                    flush(&mut literal, &mut parts);
+                    self.bump();
+                    let inner = self.double_quoted()?;
+                    parts.push(WordPart::DoubleQuoted(inner));
+                }
+                '$' => {
+                    flush(&mut literal, &mut parts);
+                    if self.peek_at(1) == Some('\'') {
+                        self.pos += 2;
+                        parts.push(WordPart::AnsiC(self.ansi_c()?));
+                    } else if self.peek_at(1) == Some('"') {
+                        self.pos += 2;
+                        parts.push(WordPart::DoubleQuoted(self.double_quoted()?));
+                    } else {
+                        parts.push(self.dollar()?);
+                    }
+                }
+                '`' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backtick()?);
+                }
+                _ => {
+                    self.bump();
+                    literal.push(c);
+                }
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok(Word {
+            parts,
+            span: start..self.pos,
+        })
+    }
+
+    fn double_quoted(&mut self) -> Result<Vec<WordPart>, ParseError> {
+        let quote = self.pos - 1;
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error_at(quote, "unterminated double quote")),
+                Some('"') => {
+                    self.bump();
+                    flush(&mut literal, &mut parts);
+                    return Ok(parts);
+                }
+                Some('\\') => {
+                    self.bump();
+                    match self.bump() {
+                        Some('\n') => {}
+                        Some(c @ ('$' | '`' | '"' | '\\')) => literal.push(c),
+                        Some(c) => {
+                            literal.push('\\');
+                            literal.push(c);
+                        }
+                        None => return Err(self.error_at(quote, "unterminated double quote")),
+                    }
+                }
+                Some('$') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.dollar()?);
+                }
+                Some('`') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backtick()?);
+                }
+                Some(c) => {
+                    self.bump();
+                    literal.push(c);
+                }
+            }
+        }
+    }
+
+    fn dollar(&mut self) -> Result<WordPart, ParseError> {
+        self.bump();
+        match self.peek() {
+            Some('(') => {
+                self.bump();
+                let command = self.balanced('(', ')')?;
+                Ok(WordPart::CommandSubstitution {
+                    command,
+                    backtick: false,
+                })
+            }
+            Some('{') => {
+                self.bump();
+                let inner = self.balanced('{', '}')?;
+                let name_len = inner
+                    .char_indices()
+                    .find(|&(i, c)| {
+                        !(c.is_ascii_alphanumeric()
+                            || c == '_'
+                            || (i == 0 && matches!(c, '#' | '!' | '?' | '@' | '*' | '$' | '-')))
+                    })
+                    .map_or(inner.len(), |(i, _)| i);
+                let (name, modifier) = inner.split_at(name_len);
+                Ok(WordPart::Parameter {
+                    name: name.to_owned(),
+                    modifier: (!modifier.is_empty()).then(|| modifier.to_owned()),
+                    braced: true,
+                })
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
+                    self.bump();
+                }
+                Ok(WordPart::Parameter {
+                    name: self.source[start..self.pos].to_owned(),
+                    modifier: None,
+                    braced: false,
+                })
+            }
+            Some(c @ ('0'..='9' | '?' | '$' | '#' | '@' | '*' | '!' | '-')) => {
+                self.bump();
+                Ok(WordPart::Parameter {
+                    name: c.to_string(),
+                    modifier: None,
+                    braced: false,
+                })
+            }
+            _ => Ok(WordPart::Literal("$".to_owned())),
+        }
+    }
+
+    /// Reads up to the `close` matching an already consumed `open`, honouring
+    /// nesting and quotes, and returns the text in between.
+    fn balanced(&mut self, open: char, close: char) -> Result<String, ParseError> {
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(c) = self.bump() {
+            match c {
+                '\\' => {
+                    self.bump();
+                }
+                '\'' if open == '(' => {
+                    let end = self.source[self.pos..]
+                        .find('\'')
+                        .ok_or_else(|| self.error("unterminated single quote"))?;
+                    self.pos += end + 1;
+                }
+                '"' => {
+                    self.double_quoted()?;
+                }
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(self.source[start..self.pos - 1].to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(self.error(&format!("expected `{close}`")))
+    }
+
+    fn backtick(&mut self) -> Result<WordPart, ParseError> {
+        self.bump();
+        let start = self.pos;
+        while let Some(c) = self.bump() {
+            match c {
+                '\\' => {
+                    self.bump();
+                }
+                '`' => {
+                    return Ok(WordPart::CommandSubstitution {
+                        command: self.source[start..self.pos - 1].to_owned(),
+                        backtick: true,
+                    })
+                }
+                _ => {}
+            }
+        }
+        Err(self.error("unterminated backquote"))
+    }
+
+    fn ansi_c(&mut self) -> Result<String, ParseError> {
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated $'...' string")),
+                Some('\'') => return Ok(out),
+                Some('\\') => match self.bump() {
+                    None => return Err(self.error("unterminated $'...' string")),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('a') => out.push('\x07'),
+                    Some('b') => out.push('\x08'),
+                    Some('e' | 'E') => out.push('\x1b'),
+                    Some('f') => out.push('\x0c'),
+                    Some('v') => out.push('\x0b'),
+                    Some('x') => out.push(self.numeric_escape(16, 2).unwrap_or('x')),
+                    Some('u') => out.push(self.numeric_escape(16, 4).unwrap_or('u')),
+                    Some('U') => out.push(self.numeric_escape(16, 8).unwrap_or('U')),
+                    Some(c @ '0'..='7') => {
+                        self.pos -= 1;
+                        out.push(self.numeric_escape(8, 3).unwrap_or(c));
+                    }
+                    Some(c @ ('\\' | '\'' | '"' | '?')) => out.push(c),
+                    Some(c) => {
+                        out.push('\\');
+                        out.push(c);
+                    }
+                },
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn numeric_escape(&mut self, radix: u32, max: usize) -> Option<char> {
+        let digits: String = self.source[self.pos..]
+            .chars()
+            .take(max)
+            .take_while(|c| c.is_digit(radix))
+            .collect();
+        if digits.is_empty() {
+            return None;
+        }
+        self.pos += digits.len();
+        u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+    }
+}
+
+fn flush(literal: &mut String, parts: &mut Vec<WordPart>) {
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(literal)));
+    }
+}
+
+fn is_array_prefix(word: &Word) -> bool {
+    match word.parts.as_slice() {
+        [WordPart::Literal(text)] => text
+            .strip_suffix('=')
+            .map(|name| name.strip_suffix('+').unwrap_or(name))
+            .is_some_and(is_name),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported(source: &str) -> Vec<(String, String)> {
+        parse(source).unwrap().exported_vars()
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn test_parse_strips_quotes() {
+        let vars = exported("export A=\"double\"\nexport B='single'\nexport C=plain\n");
+        assert_eq!(
+            vars,
+            vec![pair("A", "double"), pair("B", "single"), pair("C", "plain")]
+        );
+    }
+
+    #[test]
+    fn test_parse_multiple_assignments_per_statement() {
+        let vars = exported("export A=1 B=2; export C=3 && export D=4");
+        assert_eq!(
+            vars,
+            vec![
+                pair("A", "1"),
+                pair("B", "2"),
+                pair("C", "3"),
+                pair("D", "4")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_export_without_value() {
+        assert!(exported("export FOO").is_empty());
+        assert_eq!(exported("FOO=bar\nexport FOO"), vec![pair("FOO", "bar")]);
+    }
+
+    #[test]
+    fn test_parse_declare_and_typeset() {
+        let vars = exported("declare -x A=1\ntypeset -x B=2\ndeclare -r C=3\n");
+        assert_eq!(vars, vec![pair("A", "1"), pair("B", "2")]);
+    }
+
+    #[test]
+    fn test_parse_unexport() {
+        assert!(exported("export A=1; export -n A").is_empty());
+        assert!(exported("declare -x A=1\ndeclare +x A").is_empty());
+        assert!(exported("export -n A=1").is_empty());
+        assert_eq!(
+            exported("export A=1\nexport -n A\nexport A"),
+            vec![pair("A", "1")]
+        );
+        assert_eq!(
+            exported("export A=1 B=2\ndeclare -r A"),
+            vec![pair("A", "1"), pair("B", "2")]
+        );
+    }
+
+    #[test]
+    fn test_parse_escapes_and_ansi_c() {
+        let vars = exported(r#"export A="a \"q\" \$x" B=$'tab\there\x21' C=a\ b"#);
+        assert_eq!(
+            vars,
+            vec![
+                pair("A", "a \"q\" $x"),
+                pair("B", "tab\there!"),
+                pair("C", "a b")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_comments_and_continuations() {
+        let source =
+            "# export HIDDEN=1\nexport A=1 # trailing comment\nexport B=\\\n2\nexport C=x#y\n";
+        assert_eq!(
+            exported(source),
+            vec![pair("A", "1"), pair("B", "2"), pair("C", "x#y")]
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_heredoc_bodies() {
+        let source = "cat <<EOF > out\nexport NOT_A_VAR=1\nEOF\nexport A=1\n";
+        assert_eq!(exported(source), vec![pair("A", "1")]);
+    }
+
+    #[test]
+    fn test_parse_keeps_expansions_literal() {
+        let vars = exported("export PATH=\"$HOME/bin:${PATH}\" D=$(date) E=${X:-y}");
+        assert_eq!(
+            vars,
+            vec![
+                pair("PATH", "$HOME/bin:${PATH}"),
+                pair("D", "$(date)"),
+                pair("E", "${X:-y}")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_conditionals_and_unset() {
+        let source = "if [ -d x ]; then export A=1; fi\nexport B=2\nunset B\n";
+        assert_eq!(exported(source), vec![pair("A", "1")]);
+    }
+
+    #[test]
+    fn test_parse_command_prefix_is_not_assignment() {
+        let script = parse("FOO=1 make\n").unwrap();
+        assert!(matches!(
+            script.statements[0].kind,
+            StatementKind::Command(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_array_assignment() {
+        let script = parse("path=(/a \"/b c\")\n").unwrap();
+        let (_, assignment) = script.assignments().next().unwrap();
+        assert_eq!(assignment.name, "path");
+        match assignment.value.as_ref().unwrap() {
+            Value::Array(words) => {
+                let words: Vec<_> = words.iter().map(Word::to_literal).collect();
+                assert_eq!(words, vec!["/a", "/b c"]);
+            }
+            other => panic!("expected array, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_array_glob_qualifiers_and_separators() {
+        let script = parse("path=(~/bin(N-/) /opt/*(#q/)x $path)\n").unwrap();
+        let (_, assignment) = script.assignments().next().unwrap();
+        match assignment.value.as_ref().unwrap() {
+            Value::Array(words) => {
+                let words: Vec<_> = words.iter().map(Word::to_literal).collect();
+                assert_eq!(words, vec!["~/bin(N-/)", "/opt/*(#q/)x", "$path"]);
+            }
+            other => panic!("expected array, got {other:?}"),
+        }
+
+        for source in [
+            "a=(x;y)\n",
+            "a=(x &)\n",
+            "a=(x |y)\n",
+            "a=(<in)\n",
+            "a=((x))\n",
+        ] {
+            assert!(parse(source).is_err(), "{}", source);
+        }
+        assert_eq!(parse("a=(x(N\n").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn test_parse_spans_and_lines() {
+        let source = "# header\nexport A=1 B=\"two\"\n";
+        let script = parse(source).unwrap();
+        let assignments: Vec<_> = script.assignments().map(|(_, a)| a.clone()).collect();
+        assert_eq!(&source[assignments[0].span.clone()], "A=1");
+        assert_eq!(&source[assignments[1].span.clone()], "B=\"two\"");
+        assert_eq!(&source[assignments[1].value_span.clone()], "\"two\"");
+        assert_eq!(assignments[1].line, 2);
+        assert_eq!(script.statements[0].line, 2);
+    }
+
+    #[test]
+    fn test_parse_expand() {
+        let word = parse_word("\"${A:-fallback}/$B\"").unwrap();
+        let lookup = |name: &str| (name == "B").then(|| "bee".to_owned());
+        assert_eq!(word.expand(&lookup), "fallback/bee");
+    }
+
+    #[test]
+    fn test_parse_unterminated_quote_is_error() {
+        let error = parse("export A=1\nexport B=\"oops\n").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::dialect::Dialect;
+use crate::parser::{StatementKind, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `PATH` after one assignment in the startup chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStep {
+    pub path: PathBuf,
+    pub line: usize,
+    /// First line of the assigning statement.
+    pub statement: String,
+    /// The directories in `PATH` after the assignment.
+    pub dirs: Vec<String>,
+    /// Indices into `dirs` of directories the assignment added.
+    pub added: Vec<usize>,
+    /// Indices into `dirs` of directories the assignment moved relative to
+    /// the others.
+    pub moved: Vec<usize>,
+    /// Directories the assignment removed.
+    pub removed: Vec<String>,
+}
+
+/// Replays every assignment to `PATH` in `files`, in order, starting from
+/// an empty `PATH`. Other parameters are expanded with `lookup`.
+pub fn replay(
+    files: &[(PathBuf, Dialect)],
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Vec<PathStep> {
+    let mut steps = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+    for (path, dialect) in files {
+        let Ok(contents) = fs::read_to_string(path) else {
+            continue;
+        };
+        let Ok(script) = dialect.parse(&contents) else {
+            continue;
+        };
+        for statement in &script.statements {
+            let dirs = match &statement.kind {
+                StatementKind::Assignment { assignments, .. } => {
+                    let mut dirs = None;
+                    for assignment in assignments.iter().filter(|a| a.name == "PATH") {
+                        let Some(value) = &assignment.value else {
+                            continue;
+                        };
+                        let before = dirs.as_ref().unwrap_or(&current).join(":");
+                        let expanded = value.expand(&|name: &str| match name {
+                            "PATH" => Some(before.clone()),
+                            _ => lookup(name),
+                        });
+                        let value = match (assignment.append, value) {
+                            (false, _) => expanded,
+                            (true, Value::List(_)) => format!("{}:{}", before, expanded),
+                            (true, _) => format!("{}{}", before, expanded),
+                        };
+                        dirs = Some(split(&value));
+                    }
+                    dirs
+                }
+                StatementKind::Unset(names) if names.iter().any(|name| name == "PATH") => {
+                    Some(Vec::new())
+                }
+                _ => None,
+            };
+            let Some(dirs) = dirs else {
+                continue;
+            };
+            let text = contents[statement.span.clone()]
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim();
+            steps.push(step(&current, &dirs, path, statement.line, text));
+            current = dirs;
+        }
+    }
+    steps
+}
+
+fn split(value: &str) -> Vec<String> {
+    value
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Compares `PATH` before and after an assignment. Directories kept by it
+/// count as moved unless they're part of the longest run of kept
+/// directories whose order didn't change.
+fn step(
+    before: &[String],
+    after: &[String],
+    path: &Path,
+    line: usize,
+    statement: &str,
+) -> PathStep {
+    let kept_before: Vec<&String> = before.iter().filter(|dir| after.contains(dir)).collect();
+    let kept_after: Vec<(usize, &String)> = after
+        .iter()
+        .enumerate()
+        .filter(|(_, dir)| before.contains(dir))
+        .collect();
+    // Longest common subsequence of the kept directories in both orders.
+    let (n, m) = (kept_before.len(), kept_after.len());
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if kept_before[i] == kept_after[j].1 {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut in_order = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if kept_before[i] == kept_after[j].1 {
+            in_order.push(kept_after[j].0);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    PathStep {
+        path: path.to_path_buf(),
+        line,
+        statement: statement.to_owned(),
+        dirs: after.to_vec(),
+        added: (0..after.len())
+            .filter(|&i| !before.contains(&after[i]))
+            .collect(),
+        moved: kept_after
+            .iter()
+            .map(|(i, _)| *i)
+            .filter(|i| !in_order.contains(i))
+            .collect(),
+        removed: before
+            .iter()
+            .filter(|dir| !after.contains(dir))
+            .cloned()
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_replay_tracks_added_moved_and_removed() {
+        let dir = TempDir::new().unwrap();
+        let environment = dir.path().join("environment");
+        let profile = dir.path().join(".profile");
+        let zshrc = dir.path().join(".zshrc");
+        fs::write(&environment, "PATH=/usr/bin:/bin:/usr/local/bin\n").unwrap();
+        fs::write(
+            &profile,
+            "# python first\nexport PATH=\"$HOME/.pyenv/bin:$PATH\"\nPATH=/usr/local/bin:/usr/bin:$HOME/.pyenv/bin\n",
+        )
+        .unwrap();
+        fs::write(&zshrc, "path+=(/opt/bin)\n").unwrap();
+        let files = [
+            (environment.clone(), Dialect::Environment),
+            (profile.clone(), Dialect::Posix),
+            (zshrc, Dialect::Zsh),
+        ];
+        let lookup = |name: &str| (name == "HOME").then(|| "/home/me".to_owned());
+
+        let steps = replay(&files, &lookup);
+
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0].path, environment);
+        assert_eq!(steps[0].added, vec![0, 1, 2]);
+        assert_eq!(
+            steps[1],
+            PathStep {
+                path: profile.clone(),
+                line: 2,
+                statement: "export PATH=\"$HOME/.pyenv/bin:$PATH\"".to_owned(),
+                dirs: vec![
+                    "/home/me/.pyenv/bin".to_owned(),
+                    "/usr/bin".to_owned(),
+                    "/bin".to_owned(),
+                    "/usr/local/bin".to_owned(),
+                ],
+                added: vec![0],
+                moved: Vec::new(),
+                removed: Vec::new(),
+            }
+        );
+        assert_eq!(
+            steps[2].dirs,
+            vec!["/usr/local/bin", "/usr/bin", "/home/me/.pyenv/bin"]
+        );
+        assert_eq!(steps[2].moved.len(), 2);
+        assert_eq!(steps[2].removed, vec!["/bin".to_owned()]);
+        assert_eq!(steps[3].dirs.last().unwrap(), "/opt/bin");
+        assert_eq!(steps[3].added, vec![3]);
+    }
+
+    #[test]
+    fn test_replay_edge_cases() {
+        let dir = TempDir::new().unwrap();
+        let broken = dir.path().join("broken");
+        let bashrc = dir.path().join(".bashrc");
+        fs::write(&broken, "PATH=/broken\nexport A=\"\n").unwrap();
+        fs::write(
+            &bashrc,
+            concat!(
+                "export PATH\n",
+                "EDITOR=vi\n",
+                "PATH=/a::/b: PATH=$PATH:/c\n",
+                "PATH+=:/d\n",
+                "unset PATH\n",
+                "PATH=$UNSET\n",
+            ),
+        )
+        .unwrap();
+        let files = [
+            (dir.path().join("missing"), Dialect::Posix),
+            (broken, Dialect::Posix),
+            (bashrc, Dialect::Posix),
+        ];
+
+        let steps = replay(&files, &|_: &str| None);
+
+        let lines: Vec<usize> = steps.iter().map(|step| step.line).collect();
+        assert_eq!(lines, vec![3, 4, 5, 6]);
+        // Assignments sharing a statement are one step, empty entries dropped.
+        assert_eq!(steps[0].dirs, vec!["/a", "/b", "/c"]);
+        assert_eq!(steps[0].added, vec![0, 1, 2]);
+        assert_eq!(steps[1].dirs, vec!["/a", "/b", "/c", "/d"]);
+        assert!(steps[2].dirs.is_empty());
+        assert_eq!(steps[2].removed, vec!["/a", "/b", "/c", "/d"]);
+        assert!(steps[3].dirs.is_empty() && steps[3].removed.is_empty());
+    }
+}
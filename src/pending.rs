@@ -0,0 +1,268 @@
+use crate::config::{ConfigEdit, ConfigFile};
+use crate::dialect::Dialect;
+use crate::diff::unified_diff;
+use crate::history::FileChange;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An edit waiting to be written to a particular config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEdit {
+    pub path: PathBuf,
+    pub dialect: Dialect,
+    pub edit: ConfigEdit,
+}
+
+/// Edits staged in the TUI that have not been written to disk yet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PendingChanges {
+    pub edits: Vec<PendingEdit>,
+}
+
+impl PendingChanges {
+    pub fn stage(&mut self, path: PathBuf, dialect: Dialect, edit: ConfigEdit) {
+        self.edits.push(PendingEdit {
+            path,
+            dialect,
+            edit,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn discard(&mut self) {
+        self.edits.clear();
+    }
+
+    /// Every file with staged edits, in the order they were first touched.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for pending in &self.edits {
+            if !paths.contains(&pending.path) {
+                paths.push(pending.path.clone());
+            }
+        }
+        paths
+    }
+
+    /// Moves the edits staged for `path` out into their own set.
+    pub fn split_off_path(&mut self, path: &Path) -> PendingChanges {
+        let (edits, rest) = self
+            .edits
+            .drain(..)
+            .partition(|pending| pending.path == path);
+        self.edits = rest;
+        PendingChanges { edits }
+    }
+
+    /// Every affected file as it is on disk and as it will be once the staged
+    /// edits are applied, in the order the files were first touched.
+    pub fn preview(&self) -> io::Result<Vec<(ConfigFile, ConfigFile)>> {
+        let mut files: Vec<(ConfigFile, ConfigFile)> = Vec::new();
+        for pending in &self.edits {
+            let index = match files.iter().position(|(_, file)| file.path == pending.path) {
+                Some(index) => index,
+                None => {
+                    let before = ConfigFile::load(&pending.path, pending.dialect)?;
+                    files.push((before.clone(), before));
+                    files.len() - 1
+                }
+            };
+            files[index].1.apply(&pending.edit);
+        }
+        Ok(files)
+    }
+
+    /// A unified diff of everything that applying would change.
+    pub fn diff(&self) -> io::Result<String> {
+        Ok(self
+            .preview()?
+            .iter()
+            .map(|(before, after)| {
+                let name = before.path.display().to_string();
+                unified_diff(&before.contents, &after.contents, &name, &name)
+            })
+            .collect())
+    }
+
+    /// Writes every affected file and clears the staged edits, returning what
+    /// changed in each file that was written. Every file is loaded before
+    /// any is written. If writing one fails, the edits to the files written
+    /// before it are cleared and the rest stay staged.
+    pub fn apply(&mut self) -> Result<Vec<FileChange>, PartialApply> {
+        let files = self.preview().map_err(|error| PartialApply {
+            written: Vec::new(),
+            error,
+        })?;
+        let mut written: Vec<FileChange> = Vec::new();
+        for (before, after) in files {
+            if before.contents == after.contents {
+                continue;
+            }
+            let existed = before.path.exists();
+            if let Err(error) = after.save() {
+                self.edits
+                    .retain(|pending| !written.iter().any(|change| change.path == pending.path));
+                return Err(PartialApply { written, error });
+            }
+            written.push(FileChange {
+                path: after.path,
+                before: existed.then_some(before.contents),
+                after: after.contents,
+            });
+        }
+        self.discard();
+        Ok(written)
+    }
+}
+
+/// Applying stopped at a file that couldn't be written.
+#[derive(Debug)]
+pub struct PartialApply {
+    /// What changed in the files written before it.
+    pub written: Vec<FileChange>,
+    pub error: io::Error,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn set_var(name: &str, value: &str) -> ConfigEdit {
+        ConfigEdit::SetVar {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            expand: false,
+        }
+    }
+
+    #[test]
+    fn test_pending_changes_preview_without_writing() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "export EDITOR=vi\n").unwrap();
+        let mut pending = PendingChanges::default();
+
+        pending.stage(path.clone(), Dialect::Posix, set_var("EDITOR", "nano"));
+        pending.stage(path.clone(), Dialect::Posix, set_var("EDITOR", "nvim"));
+
+        let diff = pending.diff().unwrap();
+        assert!(diff.contains("-export EDITOR=vi\n+export EDITOR='nvim'\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "export EDITOR=vi\n");
+        assert_eq!(pending.len(), 2);
+    }
+
+    #[test]
+    fn test_pending_changes_apply_and_discard() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "export EDITOR=vi\n").unwrap();
+        let mut pending = PendingChanges::default();
+
+        pending.stage(path.clone(), Dialect::Posix, set_var("EDITOR", "nvim"));
+        pending.discard();
+        assert!(pending.is_empty());
+        assert_eq!(pending.diff().unwrap(), "");
+
+        pending.stage(path.clone(), Dialect::Posix, set_var("EDITOR", "nvim"));
+        let changes = pending.apply().unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].before.as_deref(), Some("export EDITOR=vi\n"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "export EDITOR='nvim'\n");
+    }
+
+    #[test]
+    fn test_pending_changes_group_edits_by_file() {
+        let dir = TempDir::new().unwrap();
+        let bashrc = dir.path().join(".bashrc");
+        let zshrc = dir.path().join(".zshrc");
+        fs::write(&bashrc, "export EDITOR=vi\n").unwrap();
+        fs::write(&zshrc, "export EDITOR=vi\n").unwrap();
+        let mut pending = PendingChanges::default();
+
+        pending.stage(zshrc.clone(), Dialect::Zsh, set_var("EDITOR", "nano"));
+        pending.stage(bashrc.clone(), Dialect::Posix, set_var("EDITOR", "nano"));
+        pending.stage(zshrc.clone(), Dialect::Zsh, set_var("PAGER", "less"));
+        assert_eq!(pending.paths(), vec![zshrc.clone(), bashrc.clone()]);
+        let preview = pending.preview().unwrap();
+        assert_eq!(preview.len(), 2);
+        assert!(preview[0].1.contents.contains("PAGER"));
+
+        let zsh = pending.split_off_path(&zshrc);
+        assert_eq!(zsh.len(), 2);
+        assert_eq!(pending.paths(), vec![bashrc.clone()]);
+        assert!(pending.split_off_path(&zshrc).is_empty());
+    }
+
+    #[test]
+    fn test_pending_changes_apply_writes_only_changed_files() {
+        let dir = TempDir::new().unwrap();
+        let unchanged = dir.path().join(".bashrc");
+        let created = dir.path().join(".zshrc");
+        fs::write(&unchanged, "export EDITOR=vi\n").unwrap();
+        let mut pending = PendingChanges::default();
+
+        // Removing a variable the file doesn't define leaves it as it is.
+        pending.stage(
+            unchanged.clone(),
+            Dialect::Posix,
+            ConfigEdit::RemoveVar("PAGER".to_owned()),
+        );
+        pending.stage(created.clone(), Dialect::Zsh, set_var("EDITOR", "vi"));
+        let changes = pending.apply().unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!((&changes[0].path, &changes[0].before), (&created, &None));
+        assert!(fs::read_to_string(&created)
+            .unwrap()
+            .contains("export EDITOR='vi'"));
+        assert_eq!(
+            fs::read_to_string(&unchanged).unwrap(),
+            "export EDITOR=vi\n"
+        );
+    }
+
+    #[test]
+    fn test_pending_changes_stay_staged_when_apply_fails() {
+        let dir = TempDir::new().unwrap();
+        let mut pending = PendingChanges::default();
+        // A directory can't be loaded as a config file.
+        pending.stage(
+            dir.path().to_owned(),
+            Dialect::Posix,
+            set_var("EDITOR", "vi"),
+        );
+
+        assert!(pending.diff().is_err());
+        assert!(pending.apply().unwrap_err().written.is_empty());
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[test]
+    fn test_pending_changes_keep_unwritten_edits_when_a_write_fails() {
+        let dir = TempDir::new().unwrap();
+        let written = dir.path().join(".bashrc");
+        // Loads as a new file, but its directory doesn't exist to save it.
+        let unwritable = dir.path().join("missing/.zshrc");
+        let mut pending = PendingChanges::default();
+        pending.stage(written.clone(), Dialect::Posix, set_var("EDITOR", "vi"));
+        pending.stage(unwritable.clone(), Dialect::Zsh, set_var("EDITOR", "vi"));
+        pending.stage(written.clone(), Dialect::Posix, set_var("PAGER", "less"));
+
+        let error = pending.apply().unwrap_err();
+
+        assert_eq!(error.written.len(), 1);
+        assert_eq!(error.written[0].path, written);
+        assert!(fs::read_to_string(&written).unwrap().contains("PAGER"));
+        assert_eq!(pending.paths(), vec![unwritable]);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::dialect::Dialect;
+use crate::parser::{AssignmentCommand, StatementKind};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where a variable is defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub path: PathBuf,
+    pub line: usize,
+    /// First line of the defining statement.
+    pub statement: String,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.path.display(),
+            self.line,
+            self.statement
+        )
+    }
+}
+
+/// Finds the statement that last gives each exported variable its value,
+/// reading `files` in the order they are evaluated. A later `unset` clears
+/// a variable's origin. Files that are missing or don't parse are skipped.
+pub fn trace(files: &[(PathBuf, Dialect)]) -> HashMap<String, Provenance> {
+    let mut origins: HashMap<String, Provenance> = HashMap::new();
+    let mut exported: HashSet<String> = HashSet::new();
+    for (path, dialect) in files {
+        let Ok(contents) = fs::read_to_string(path) else {
+            continue;
+        };
+        let Ok(script) = dialect.parse(&contents) else {
+            continue;
+        };
+        exported.extend(script.exported_vars().into_iter().map(|(name, _)| name));
+        for statement in &script.statements {
+            match &statement.kind {
+                StatementKind::Assignment {
+                    command: AssignmentCommand::Local,
+                    ..
+                } => {}
+                StatementKind::Assignment { assignments, .. } => {
+                    let text = contents[statement.span.clone()]
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .trim();
+                    for assignment in assignments {
+                        // `export NAME` alone only marks an earlier definition.
+                        if assignment.value.is_none() && origins.contains_key(&assignment.name) {
+                            continue;
+                        }
+                        origins.insert(
+                            assignment.name.clone(),
+                            Provenance {
+                                path: path.clone(),
+                                line: assignment.line,
+                                statement: text.to_owned(),
+                            },
+                        );
+                    }
+                }
+                StatementKind::Unset(names) => {
+                    for name in names {
+                        origins.remove(name);
+                        exported.remove(name);
+                    }
+                }
+                StatementKind::Source(_) | StatementKind::Command(_) => {}
+            }
+        }
+        // Plain shell variables never reach the environment.
+        origins.retain(|name, _| exported.contains(name));
+    }
+    origins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trace_reports_last_definition() {
+        let dir = TempDir::new().unwrap();
+        let environment = dir.path().join("environment");
+        let profile = dir.path().join(".profile");
+        let bashrc = dir.path().join(".bashrc");
+        fs::write(&environment, "LANG=C\nEDITOR=nano\n").unwrap();
+        fs::write(
+            &profile,
+            "EDITOR=vi\nexport EDITOR\nlocal_only=1\nexport PAGER=less\n",
+        )
+        .unwrap();
+        fs::write(&bashrc, "unset PAGER\nexport EDITOR\nLANG=en_US.UTF-8\n").unwrap();
+        let files = [
+            (environment, Dialect::Environment),
+            (profile.clone(), Dialect::Posix),
+            (bashrc.clone(), Dialect::Posix),
+            (dir.path().join("missing"), Dialect::Posix),
+        ];
+
+        let origins = trace(&files);
+
+        assert_eq!(
+            origins["EDITOR"],
+            Provenance {
+                path: profile,
+                line: 1,
+                statement: "EDITOR=vi".to_owned(),
+            }
+        );
+        assert_eq!((&origins["LANG"].path, origins["LANG"].line), (&bashrc, 3));
+        assert!(!origins.contains_key("PAGER"));
+        assert!(!origins.contains_key("local_only"));
+    }
+
+    #[test]
+    fn test_trace_skips_files_that_do_not_parse() {
+        let dir = TempDir::new().unwrap();
+        let profile = dir.path().join(".profile");
+        let bashrc = dir.path().join(".bashrc");
+        fs::write(&profile, "export EDITOR=vi\n").unwrap();
+        fs::write(&bashrc, "export EDITOR=\"nano\nexport PAGER=less\n").unwrap();
+
+        let origins = trace(&[(profile.clone(), Dialect::Posix), (bashrc, Dialect::Posix)]);
+
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins["EDITOR"].path, profile);
+    }
+
+    #[test]
+    fn test_trace_edge_cases() {
+        let dir = TempDir::new().unwrap();
+        let profile = dir.path().join(".profile");
+        fs::write(
+            &profile,
+            concat!(
+                "export PAGER\n",
+                "export LANG=C\n",
+                "unset LANG\n",
+                "LANG=en_US.UTF-8\n",
+                "export MULTI='a\n",
+                "b'\n",
+            ),
+        )
+        .unwrap();
+
+        let origins = trace(&[(profile, Dialect::Posix)]);
+
+        // A bare `export` sets nothing.
+        assert!(!origins.contains_key("PAGER"));
+        // `unset` drops the export, so the redefinition stays a shell variable.
+        assert!(!origins.contains_key("LANG"));
+        assert_eq!(origins["MULTI"].statement, "export MULTI='a");
+    }
+}
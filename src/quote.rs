@@ -0,0 +1,350 @@
+use crate::dialect::Dialect;
+
+/// A piece of a value as it's written to a shell config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Text the shell takes as it is.
+    Text(String),
+    /// `$NAME` or `${NAME<modifier>}`, which the shell expands.
+    Reference {
+        name: String,
+        modifier: Option<String>,
+    },
+}
+
+/// Splits `value` into text and references. Without `expand` the whole
+/// value is text. With it, `$NAME` and `${NAME}` are references, as is
+/// `${NAME<modifier>}` in the shells that have modifiers when the modifier
+/// holds nothing but plain text and references. Any other `$`, such as the
+/// start of a command substitution, is text.
+pub fn segments(dialect: Dialect, value: &str, expand: bool) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        if let Some((reference, len)) = rest
+            .strip_prefix('$')
+            .filter(|_| expand)
+            .and_then(|after| reference(dialect, after))
+        {
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(reference);
+            rest = &rest[1 + len..];
+            continue;
+        }
+        text.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
+
+/// The reference that follows a `$` at the start of `rest`, with the
+/// number of bytes it takes.
+fn reference(dialect: Dialect, rest: &str) -> Option<(Segment, usize)> {
+    let Some(braced) = rest.strip_prefix('{') else {
+        let len = name_len(rest);
+        let name = rest[..len].to_owned();
+        return (len > 0).then_some((
+            Segment::Reference {
+                name,
+                modifier: None,
+            },
+            len,
+        ));
+    };
+    let len = name_len(braced);
+    let close = braced.find('}')?;
+    let modifier = &braced[len..close];
+    let plain = modifier.is_empty() || (has_modifiers(dialect) && plain_modifier(modifier));
+    (len > 0 && plain).then(|| {
+        (
+            Segment::Reference {
+                name: braced[..len].to_owned(),
+                modifier: (!modifier.is_empty()).then(|| modifier.to_owned()),
+            },
+            close + 2,
+        )
+    })
+}
+
+/// Length of the variable name `text` starts with.
+fn name_len(text: &str) -> usize {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(text.len())
+}
+
+fn has_modifiers(dialect: Dialect) -> bool {
+    matches!(dialect, Dialect::Posix | Dialect::Ksh | Dialect::Zsh)
+}
+
+/// Whether `modifier` can't quote, escape or run anything, and every `$`
+/// in it starts a name.
+fn plain_modifier(modifier: &str) -> bool {
+    !modifier.contains(['"', '\'', '`', '\\', '{', '(', '\n'])
+        && modifier
+            .match_indices('$')
+            .all(|(i, _)| name_len(&modifier[i + 1..]) > 0)
+}
+
+/// `value` written as a single word that `dialect`, which must be a shell,
+/// reads back unchanged, expanding references only with `expand`. Text is
+/// single-quoted. In sh, ksh and zsh a value with references is instead
+/// double-quoted with everything else escaped; csh and fish can't escape
+/// `$` inside double quotes, so there only the references are.
+pub fn shell_word(dialect: Dialect, value: &str, expand: bool) -> String {
+    let segments = segments(dialect, value, expand);
+    let references = segments
+        .iter()
+        .any(|segment| matches!(segment, Segment::Reference { .. }));
+    match dialect {
+        Dialect::Posix | Dialect::Ksh | Dialect::Zsh if references => double_quoted(&segments),
+        Dialect::Posix | Dialect::Ksh | Dialect::Zsh => single_quoted(dialect, value),
+        _ if segments.is_empty() => "''".to_owned(),
+        _ => segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => single_quoted(dialect, text),
+                Segment::Reference { name, .. } if dialect == Dialect::Csh => {
+                    format!("\"${{{}}}\"", name)
+                }
+                Segment::Reference { name, .. } => format!("\"${}\"", name),
+            })
+            .collect(),
+    }
+}
+
+/// `text` in single quotes. A quote closes them for an escaped one, and so
+/// does a backslash in fish, which would otherwise escape what follows. csh
+/// needs a backslash before a newline for it to stay in the quotes.
+fn single_quoted(dialect: Dialect, text: &str) -> String {
+    let mut quoted = String::from('\'');
+    for c in text.chars() {
+        match (dialect, c) {
+            (_, '\'') => quoted.push_str("'\\''"),
+            (Dialect::Fish, '\\') => quoted.push_str("'\\\\'"),
+            (Dialect::Csh, '\n') => quoted.push_str("\\\n"),
+            (_, c) => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// `segments` in double quotes, escaping every character that's special
+/// there so that only the references expand.
+fn double_quoted(segments: &[Segment]) -> String {
+    let mut quoted = String::from('"');
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Text(text) => {
+                for c in text.chars() {
+                    if matches!(c, '"' | '\\' | '$' | '`') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                }
+            }
+            Segment::Reference { name, modifier } => {
+                // A following name character or subscript would otherwise
+                // run into the name.
+                let joined = matches!(
+                    segments.get(i + 1),
+                    Some(Segment::Text(next))
+                        if next.starts_with(|c: char| c.is_ascii_alphanumeric() || "_[{".contains(c))
+                );
+                match modifier {
+                    None if !joined => quoted.push_str(&format!("${}", name)),
+                    _ => quoted.push_str(&format!(
+                        "${{{}{}}}",
+                        name,
+                        modifier.as_deref().unwrap_or_default()
+                    )),
+                }
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Value, Word, WordPart};
+    use proptest::prelude::*;
+    use std::process::Command;
+
+    const SHELLS: [Dialect; 5] = [
+        Dialect::Posix,
+        Dialect::Ksh,
+        Dialect::Zsh,
+        Dialect::Csh,
+        Dialect::Fish,
+    ];
+
+    /// The segments a parsed word is made of. A command substitution means
+    /// the value was written unsafely.
+    fn word_segments(word: &Word) -> Vec<Segment> {
+        fn collect(parts: &[WordPart], segments: &mut Vec<Segment>) {
+            for part in parts {
+                let text = match part {
+                    WordPart::Literal(text)
+                    | WordPart::SingleQuoted(text)
+                    | WordPart::AnsiC(text) => text,
+                    WordPart::DoubleQuoted(inner) => {
+                        collect(inner, segments);
+                        continue;
+                    }
+                    WordPart::Parameter { name, modifier, .. } => {
+                        segments.push(Segment::Reference {
+                            name: name.clone(),
+                            modifier: modifier.clone(),
+                        });
+                        continue;
+                    }
+                    WordPart::CommandSubstitution { command, .. } => {
+                        panic!("{} would run", command)
+                    }
+                };
+                match segments.last_mut() {
+                    Some(Segment::Text(last)) => last.push_str(text),
+                    _ if text.is_empty() => {}
+                    _ => segments.push(Segment::Text(text.clone())),
+                }
+            }
+        }
+        let mut segments = Vec::new();
+        collect(&word.parts, &mut segments);
+        segments
+    }
+
+    /// Writes `value` with `dialect` and parses it back.
+    fn round_trip(dialect: Dialect, value: &str, expand: bool) -> Vec<Segment> {
+        let source = format!("{}\n", dialect.assignment("VALUE", value, expand));
+        let script = dialect
+            .parse(&source)
+            .unwrap_or_else(|error| panic!("{:?}: {}: {}", dialect, error, source));
+        let (_, assignment) = script.assignments().next().unwrap();
+        match &assignment.value {
+            Some(Value::Scalar(word)) => word_segments(word),
+            other => panic!("{:?}: {:?} from {}", dialect, other, source),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_literal_values_round_trip(value in "[ -~\\t\\né]{0,24}") {
+            for dialect in SHELLS {
+                let expected: Vec<Segment> = segments(dialect, &value, false);
+                prop_assert_eq!(round_trip(dialect, &value, false), expected, "{:?}", dialect);
+            }
+        }
+
+        #[test]
+        fn prop_expanded_values_round_trip(
+            value in "([a-z/:'\"\\\\`$(){}\\n -]|\\$[A-Z_]{1,3}|\\$\\{[A-Z]{1,2}(:-[a-z$]{0,2})?\\}){0,8}"
+        ) {
+            for dialect in SHELLS {
+                prop_assert_eq!(
+                    round_trip(dialect, &value, true),
+                    segments(dialect, &value, true),
+                    "{:?}",
+                    dialect
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_shell_word_quoting() {
+        assert_eq!(shell_word(Dialect::Posix, "it's", false), "'it'\\''s'");
+        assert_eq!(
+            shell_word(Dialect::Posix, "$(rm -rf ~)", true),
+            "'$(rm -rf ~)'"
+        );
+        assert_eq!(
+            shell_word(Dialect::Zsh, "$HOME/bin:${GOPATH}bin `id`", true),
+            "\"$HOME/bin:${GOPATH}bin \\`id\\`\""
+        );
+        assert_eq!(
+            shell_word(Dialect::Csh, "$HOME/a'b", true),
+            "\"${HOME}\"'/a'\\''b'"
+        );
+        assert_eq!(shell_word(Dialect::Fish, "C:\\", false), "'C:'\\\\''");
+        assert_eq!(shell_word(Dialect::Fish, "", true), "''");
+    }
+
+    #[test]
+    fn test_segments_edge_cases() {
+        let reference = |name: &str, modifier: Option<&str>| Segment::Reference {
+            name: name.to_owned(),
+            modifier: modifier.map(str::to_owned),
+        };
+        let text = |text: &str| Segment::Text(text.to_owned());
+
+        assert_eq!(
+            segments(Dialect::Posix, "$HOME/bin", false),
+            vec![text("$HOME/bin")]
+        );
+        assert!(segments(Dialect::Posix, "", true).is_empty());
+        // Positional and special parameters, a lone `$` and unclosed braces
+        // stay text.
+        assert_eq!(
+            segments(Dialect::Posix, "$1 $$ $ ${A", true),
+            vec![text("$1 $$ $ ${A")]
+        );
+        assert_eq!(
+            segments(Dialect::Posix, "${A:-$B}x$C_1", true),
+            vec![
+                reference("A", Some(":-$B")),
+                text("x"),
+                reference("C_1", None),
+            ]
+        );
+        assert_eq!(
+            segments(Dialect::Fish, "${A:-b}", true),
+            vec![text("${A:-b}")]
+        );
+        for modifier in ["${A:-$(id)}", "${A:-'b'}", "${A:-$1}", "${A:-\\}"] {
+            assert_eq!(
+                segments(Dialect::Zsh, modifier, true),
+                vec![text(modifier)],
+                "{}",
+                modifier
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "starts sh"]
+    fn test_sh_reads_back_written_values() {
+        for (value, expand, expected) in [
+            ("$(touch pwned) `id` \"q\" 'a' \\", false, None),
+            ("a\nb $HOME", false, None),
+            ("$HOME/$(id)", true, Some("/home/me/$(id)")),
+        ] {
+            let source = format!(
+                "{}\nprintf %s \"$VALUE\"",
+                Dialect::Posix.assignment("VALUE", value, expand)
+            );
+            let output = Command::new("sh")
+                .args(["-c", &source])
+                .env("HOME", "/home/me")
+                .current_dir(std::env::temp_dir())
+                .output()
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                expected.unwrap_or(value)
+            );
+        }
+    }
+}
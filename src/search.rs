@@ -0,0 +1,190 @@
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// A search over the listed variables and `PATH` directories. Matching is
+/// case-insensitive unless the query has an uppercase letter.
+#[derive(Debug, Clone, Default)]
+pub struct Search {
+    query: String,
+    /// Whether the query is a regular expression rather than fuzzy.
+    regex: bool,
+    /// Whether variable values are searched as well as names.
+    values: bool,
+    compiled: Option<Regex>,
+    error: Option<String>,
+}
+
+impl Search {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn is_regex(&self) -> bool {
+        self.regex
+    }
+
+    pub fn searches_values(&self) -> bool {
+        self.values
+    }
+
+    /// Why the query isn't a valid regular expression, if it isn't.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.compile();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.compile();
+    }
+
+    pub fn toggle_regex(&mut self) {
+        self.regex = !self.regex;
+        self.compile();
+    }
+
+    pub fn toggle_values(&mut self) {
+        self.values = !self.values;
+    }
+
+    fn compile(&mut self) {
+        self.compiled = None;
+        self.error = None;
+        if !self.regex {
+            return;
+        }
+        match RegexBuilder::new(&self.query)
+            .case_insensitive(self.ignores_case())
+            .build()
+        {
+            Ok(regex) => self.compiled = Some(regex),
+            Err(error) => self.error = Some(error.to_string()),
+        }
+    }
+
+    fn ignores_case(&self) -> bool {
+        !self.query.chars().any(char::is_uppercase)
+    }
+
+    /// Byte ranges of the parts of `text` the query matches, or `None` if it
+    /// doesn't match. An empty query, or a regular expression that doesn't
+    /// compile, matches everything without highlighting anything.
+    pub fn find(&self, text: &str) -> Option<Vec<Range<usize>>> {
+        if self.query.is_empty() || self.error.is_some() {
+            return Some(Vec::new());
+        }
+        match &self.compiled {
+            Some(regex) => {
+                let ranges: Vec<Range<usize>> = regex
+                    .find_iter(text)
+                    .map(|found| found.range())
+                    .filter(|range| !range.is_empty())
+                    .collect();
+                (!ranges.is_empty() || regex.is_match(text)).then_some(ranges)
+            }
+            None => fuzzy(&self.query, text, self.ignores_case()),
+        }
+    }
+}
+
+/// Matches `query` against `text` as a substring if it can, so that the
+/// match reads as one piece, and otherwise as a subsequence, each query
+/// character matching the first one left after the previous.
+fn fuzzy(query: &str, text: &str, ignore_case: bool) -> Option<Vec<Range<usize>>> {
+    let fold = |c: char| {
+        if ignore_case {
+            c.to_lowercase().next().unwrap_or(c)
+        } else {
+            c
+        }
+    };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let end = |i: usize| chars[i].0 + chars[i].1.len_utf8();
+    let substring = (0..(chars.len() + 1).saturating_sub(query.len())).find(|&start| {
+        query
+            .iter()
+            .enumerate()
+            .all(|(k, &c)| fold(chars[start + k].1) == c)
+    });
+    if let Some(start) = substring {
+        let range = chars[start].0..end(start + query.len() - 1);
+        return Some(vec![range]);
+    }
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut wanted = query.iter().peekable();
+    for (i, &(offset, c)) in chars.iter().enumerate() {
+        if wanted.peek().is_some_and(|&&want| fold(c) == want) {
+            wanted.next();
+            match ranges.last_mut() {
+                Some(last) if last.end == offset => last.end = end(i),
+                _ => ranges.push(offset..end(i)),
+            }
+        }
+    }
+    wanted.peek().is_none().then_some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(query: &str, regex: bool) -> Search {
+        let mut search = Search::default();
+        if regex {
+            search.toggle_regex();
+        }
+        query.chars().for_each(|c| search.push(c));
+        search
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_fuzzy_and_regex_find() {
+        let fuzzy = search("path", false);
+        assert_eq!(fuzzy.find("MANPATH"), Some(vec![3..7]));
+        assert_eq!(fuzzy.find("PYTHONPATH"), Some(vec![6..10]));
+        assert_eq!(fuzzy.find("XDG_DATA_HOME"), None);
+        assert_eq!(
+            search("xdh", false).find("XDG_DATA_HOME"),
+            Some(vec![0..2, 9..10])
+        );
+        assert_eq!(search("Path", false).find("MANPATH"), None);
+        assert_eq!(search("", false).find("anything"), Some(Vec::new()));
+
+        let regex = search("^(go|cargo)_", true);
+        assert_eq!(regex.find("CARGO_HOME"), Some(vec![0..6]));
+        assert_eq!(regex.find("GOPATH"), None);
+
+        let invalid = search("go(", true);
+        assert!(invalid.error().is_some());
+        assert_eq!(invalid.find("GOPATH"), Some(Vec::new()));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_find_edge_cases() {
+        assert_eq!(search("é", false).find("CAFÉ"), Some(vec![3..5]));
+        assert_eq!(search("fc", false).find("CAFÉ"), None);
+        assert_eq!(search("longer", false).find("long"), None);
+
+        // Matches that are empty highlight nothing but still count.
+        assert_eq!(search("x*", true).find("PATH"), Some(Vec::new()));
+        assert_eq!(search("^$", true).find("PATH"), None);
+        assert_eq!(search("PATH$", true).find("manpath"), None);
+
+        let mut invalid = search("go(", true);
+        invalid.toggle_regex();
+        assert_eq!(invalid.error(), None);
+        assert_eq!(invalid.find("GOPATH"), None);
+        invalid.pop();
+        invalid.pop();
+        invalid.pop();
+        assert_eq!(invalid.query(), "");
+        assert_eq!(invalid.find("anything"), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::dialect::Dialect;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Shells whose startup files envelope edits, in the order they're listed
+/// when several are configured.
+pub const SHELLS: [Dialect; 5] = [
+    Dialect::Posix,
+    Dialect::Zsh,
+    Dialect::Ksh,
+    Dialect::Csh,
+    Dialect::Fish,
+];
+
+/// The login shell `passwd` lists for `user`.
+pub fn login_shell(passwd: &str, user: &str) -> Option<PathBuf> {
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() == 7 && fields[0] == user)
+        .map(|fields| PathBuf::from(fields[6]))
+        .filter(|shell| !shell.as_os_str().is_empty())
+}
+
+/// Where the user's shell may be found, most authoritative first: `$SHELL`,
+/// then the user's entry in `/etc/passwd`.
+pub fn candidates() -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = env::var_os("SHELL")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    let user = env::var("USER").or_else(|_| env::var("LOGNAME"));
+    if let (Ok(user), Ok(passwd)) = (user, fs::read_to_string("/etc/passwd")) {
+        candidates.extend(login_shell(&passwd, &user));
+    }
+    candidates
+}
+
+/// The first candidate that's a shell envelope understands, with its
+/// dialect.
+pub fn detect(candidates: &[PathBuf]) -> Option<(PathBuf, Dialect)> {
+    candidates.iter().find_map(|shell| {
+        Dialect::from_shell(&shell.to_string_lossy()).map(|dialect| (shell.clone(), dialect))
+    })
+}
+
+/// The shells that have a startup file in `home`.
+pub fn configured(home: &Path) -> Vec<Dialect> {
+    SHELLS
+        .into_iter()
+        .filter(|dialect| {
+            dialect
+                .startup_files()
+                .iter()
+                .any(|file| home.join(file).is_file())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_shell() {
+        let passwd = "\
+root:x:0:0:root:/root:/bin/bash
+# comment
+me:x:1000:1000:Me,,,:/home/me:/usr/bin/zsh
+nologin:x:1001:1001::/home/nologin:
+";
+        assert_eq!(
+            login_shell(passwd, "me"),
+            Some(PathBuf::from("/usr/bin/zsh"))
+        );
+        assert_eq!(login_shell(passwd, "nologin"), None);
+        assert_eq!(login_shell(passwd, "nobody"), None);
+        assert_eq!(
+            detect(&[PathBuf::from("/bin/nu"), PathBuf::from("/usr/bin/fish")]),
+            Some((PathBuf::from("/usr/bin/fish"), Dialect::Fish))
+        );
+        assert_eq!(detect(&[]), None);
+
+        let home = TempDir::new().unwrap();
+        assert!(configured(home.path()).is_empty());
+        fs::write(home.path().join(".zshrc"), "").unwrap();
+        fs::write(home.path().join(".profile"), "").unwrap();
+        assert_eq!(
+            configured(home.path()),
+            vec![Dialect::Posix, Dialect::Zsh, Dialect::Ksh]
+        );
+    }
+
+    #[test]
+    fn test_login_shell_skips_malformed_entries() {
+        let passwd = "me:x:1000\nme:x:1000:1000::/home/me:/bin/sh:extra\nme:x:1000:1000::/home/me:/bin/dash\n";
+        assert_eq!(login_shell(passwd, "me"), Some(PathBuf::from("/bin/dash")));
+        assert_eq!(login_shell("", "me"), None);
+
+        let home = TempDir::new().unwrap();
+        fs::create_dir_all(home.path().join(".config/fish")).unwrap();
+        fs::create_dir(home.path().join(".cshrc")).unwrap();
+        fs::write(home.path().join(".config/fish/config.fish"), "").unwrap();
+        // A directory where a startup file belongs doesn't count.
+        assert_eq!(configured(home.path()), vec![Dialect::Fish]);
+    }
+}
@@ -0,0 +1,473 @@
+use crate::dialect::Dialect;
+use crate::parser::StatementKind;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How deeply `source` includes are followed, guarding against cycles the
+/// visited check misses, such as through symlinks.
+const MAX_DEPTH: usize = 8;
+
+/// Whether a shell is a login shell and whether it's interactive, which
+/// decides the startup files it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    pub login: bool,
+    pub interactive: bool,
+}
+
+impl Default for Mode {
+    /// What a terminal emulator starts.
+    fn default() -> Mode {
+        Mode {
+            login: false,
+            interactive: true,
+        }
+    }
+}
+
+impl Mode {
+    /// The next of the four modes, for cycling through them.
+    pub fn next(self) -> Mode {
+        match (self.login, self.interactive) {
+            (false, true) => Mode {
+                login: true,
+                interactive: true,
+            },
+            (true, true) => Mode {
+                login: true,
+                interactive: false,
+            },
+            (true, false) => Mode {
+                login: false,
+                interactive: false,
+            },
+            (false, false) => Mode::default(),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let login = if self.login { "login" } else { "non-login" };
+        let interactive = if self.interactive {
+            "interactive"
+        } else {
+            "non-interactive"
+        };
+        write!(f, "{} {}", login, interactive)
+    }
+}
+
+/// A file in a startup chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry {
+    pub path: PathBuf,
+    /// How many `source` includes deep the file was reached, 0 for files
+    /// the shell reads itself.
+    pub depth: usize,
+    /// The variables the file exports, with their literal values.
+    pub vars: Vec<(String, String)>,
+}
+
+/// The files a shell speaking `dialect` reads at startup in `mode`, in
+/// order, each followed by the files it sources. Files that don't exist
+/// are left out. `etc` stands in for `/etc`.
+pub fn chain(dialect: Dialect, mode: Mode, home: &Path, etc: &Path) -> Vec<ChainEntry> {
+    let mut entries = Vec::new();
+    for path in shell_files(dialect, mode, home, etc) {
+        visit(&path, 0, dialect, home, &mut entries);
+    }
+    entries
+}
+
+/// The files the shell itself reads, before following includes.
+fn shell_files(dialect: Dialect, mode: Mode, home: &Path, etc: &Path) -> Vec<PathBuf> {
+    let existing = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+        paths.into_iter().filter(|path| path.is_file()).collect()
+    };
+    let first =
+        |paths: Vec<PathBuf>| -> Vec<PathBuf> { existing(paths).into_iter().take(1).collect() };
+    let scripts = |dir: PathBuf, extension: &str| -> Vec<PathBuf> {
+        let mut scripts: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+                    .filter(|path| path.is_file())
+                    .collect()
+            })
+            .unwrap_or_default();
+        scripts.sort();
+        scripts
+    };
+    // `/etc/profile` sources `/etc/profile.d/*.sh` in a loop, which
+    // includes aren't followed through.
+    let etc_profile = || -> Vec<PathBuf> {
+        let profile = etc.join("profile");
+        if !profile.is_file() {
+            return Vec::new();
+        }
+        let mut files = vec![profile];
+        files.extend(scripts(etc.join("profile.d"), "sh"));
+        files
+    };
+    let mut files = Vec::new();
+    match dialect {
+        Dialect::Posix => {
+            if mode.login {
+                files.extend(etc_profile());
+                files.extend(first(vec![
+                    home.join(".bash_profile"),
+                    home.join(".bash_login"),
+                    home.join(".profile"),
+                ]));
+            } else if mode.interactive {
+                files.extend(existing(vec![
+                    etc.join("bash.bashrc"),
+                    etc.join("bashrc"),
+                    home.join(".bashrc"),
+                ]));
+            }
+        }
+        Dialect::Ksh => {
+            if mode.login {
+                files.extend(etc_profile());
+                files.extend(existing(vec![home.join(".profile")]));
+            }
+            if mode.interactive {
+                files.extend(existing(vec![home.join(".kshrc")]));
+            }
+        }
+        Dialect::Zsh => {
+            let pair = |name: &str| {
+                let mut pair = first(vec![etc.join("zsh").join(name), etc.join(name)]);
+                pair.extend(existing(vec![home.join(format!(".{}", name))]));
+                pair
+            };
+            files.extend(pair("zshenv"));
+            if mode.login {
+                files.extend(pair("zprofile"));
+            }
+            if mode.interactive {
+                files.extend(pair("zshrc"));
+            }
+            if mode.login {
+                files.extend(pair("zlogin"));
+            }
+        }
+        Dialect::Csh => {
+            files.extend(existing(vec![etc.join("csh.cshrc")]));
+            if mode.login {
+                files.extend(existing(vec![etc.join("csh.login")]));
+            }
+            files.extend(first(vec![home.join(".tcshrc"), home.join(".cshrc")]));
+            if mode.login {
+                files.extend(existing(vec![home.join(".login")]));
+            }
+        }
+        Dialect::Fish => {
+            // Fish runs `conf.d` snippets by file name, and a user's snippet
+            // hides the system one with the same name.
+            let mut snippets = scripts(home.join(".config/fish/conf.d"), "fish");
+            for snippet in scripts(etc.join("fish/conf.d"), "fish") {
+                if !snippets
+                    .iter()
+                    .any(|path| path.file_name() == snippet.file_name())
+                {
+                    snippets.push(snippet);
+                }
+            }
+            snippets.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+            files.extend(snippets);
+            files.extend(existing(vec![etc.join("fish/config.fish")]));
+            files.extend(existing(vec![home.join(".config/fish/config.fish")]));
+        }
+        Dialect::Environment | Dialect::EnvironmentD | Dialect::Dotenv => {}
+    }
+    files
+}
+
+/// Adds `path` and, in order, the files it sources.
+fn visit(path: &Path, depth: usize, dialect: Dialect, home: &Path, entries: &mut Vec<ChainEntry>) {
+    if depth > MAX_DEPTH || entries.iter().any(|entry| entry.path == path) {
+        return;
+    }
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    let script = dialect.parse(&contents).ok();
+    entries.push(ChainEntry {
+        path: path.to_path_buf(),
+        depth,
+        vars: script
+            .as_ref()
+            .map(|script| script.exported_vars())
+            .unwrap_or_default(),
+    });
+    for statement in script.iter().flat_map(|script| &script.statements) {
+        let StatementKind::Source(word) = &statement.kind else {
+            continue;
+        };
+        let home_text = home.to_string_lossy();
+        let target = word.expand(&|name: &str| (name == "HOME").then(|| home_text.to_string()));
+        if target.is_empty() || target.contains(['*', '?', '[']) {
+            continue;
+        }
+        let target = match target.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            // The shell resolves relative paths against its working
+            // directory, which at startup is the home directory.
+            None => home.join(target),
+        };
+        visit(&target, depth + 1, dialect, home, entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn paths(entries: &[ChainEntry], root: &Path) -> Vec<(String, usize)> {
+        entries
+            .iter()
+            .map(|entry| {
+                let path = entry.path.strip_prefix(root).unwrap();
+                (path.display().to_string(), entry.depth)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bash_chain_by_mode_follows_includes() {
+        let root = TempDir::new().unwrap();
+        let home = root.path().join("home");
+        let etc = root.path().join("etc");
+        fs::create_dir_all(etc.join("profile.d")).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        fs::write(etc.join("profile"), "export PATH=/usr/bin\n").unwrap();
+        fs::write(
+            etc.join("profile.d/java.sh"),
+            "export JAVA_HOME=/opt/java\n",
+        )
+        .unwrap();
+        fs::write(etc.join("bash.bashrc"), "PS1='$ '\n").unwrap();
+        fs::write(
+            home.join(".bash_profile"),
+            "if [ -f ~/.bashrc ]; then\n  . ~/.bashrc\nfi\n",
+        )
+        .unwrap();
+        fs::write(home.join(".profile"), "export UNREAD=1\n").unwrap();
+        fs::write(
+            home.join(".bashrc"),
+            "export EDITOR=vi\nsource \"$HOME/.aliases\"\n",
+        )
+        .unwrap();
+        fs::write(home.join(".aliases"), "export PAGER=less\n. .bashrc\n").unwrap();
+
+        let login = chain(
+            Dialect::Posix,
+            Mode {
+                login: true,
+                interactive: true,
+            },
+            &home,
+            &etc,
+        );
+        let interactive = chain(Dialect::Posix, Mode::default(), &home, &etc);
+
+        assert_eq!(
+            paths(&login, root.path()),
+            vec![
+                ("etc/profile".to_owned(), 0),
+                ("etc/profile.d/java.sh".to_owned(), 0),
+                ("home/.bash_profile".to_owned(), 0),
+                ("home/.bashrc".to_owned(), 1),
+                ("home/.aliases".to_owned(), 2),
+            ]
+        );
+        assert_eq!(login[4].vars, vec![("PAGER".to_owned(), "less".to_owned())]);
+        assert_eq!(
+            paths(&interactive, root.path()),
+            vec![
+                ("etc/bash.bashrc".to_owned(), 0),
+                ("home/.bashrc".to_owned(), 0),
+                ("home/.aliases".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zsh_chain_order() {
+        let root = TempDir::new().unwrap();
+        let home = root.path().join("home");
+        let etc = root.path().join("etc");
+        fs::create_dir_all(etc.join("zsh")).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        for name in ["zshenv", "zprofile", "zshrc", "zlogin"] {
+            fs::write(etc.join("zsh").join(name), "").unwrap();
+            fs::write(home.join(format!(".{}", name)), "").unwrap();
+        }
+
+        let login = chain(
+            Dialect::Zsh,
+            Mode {
+                login: true,
+                interactive: true,
+            },
+            &home,
+            &etc,
+        );
+        let script = chain(
+            Dialect::Zsh,
+            Mode {
+                login: false,
+                interactive: false,
+            },
+            &home,
+            &etc,
+        );
+
+        let names: Vec<String> = paths(&login, root.path())
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "etc/zsh/zshenv",
+                "home/.zshenv",
+                "etc/zsh/zprofile",
+                "home/.zprofile",
+                "etc/zsh/zshrc",
+                "home/.zshrc",
+                "etc/zsh/zlogin",
+                "home/.zlogin",
+            ]
+        );
+        assert_eq!(script.len(), 2);
+    }
+
+    #[test]
+    fn test_mode_cycles_through_all_four() {
+        let mut mode = Mode::default();
+        let mut shown = Vec::new();
+        for _ in 0..4 {
+            shown.push(mode.to_string());
+            mode = mode.next();
+        }
+        assert_eq!(mode, Mode::default());
+        assert_eq!(
+            shown,
+            vec![
+                "non-login interactive",
+                "login interactive",
+                "login non-interactive",
+                "non-login non-interactive",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chain_skips_includes_it_cannot_follow() {
+        let root = TempDir::new().unwrap();
+        let home = root.path().join("home");
+        let etc = root.path().join("etc");
+        fs::create_dir_all(&home).unwrap();
+        fs::write(
+            home.join(".bashrc"),
+            concat!(
+                ". ~/.missing\n",
+                ". ~/.bashrc.d/*.sh\n",
+                ". \"$UNSET\"\n",
+                ". ~/.broken\n",
+                ". ~/.d1\n",
+            ),
+        )
+        .unwrap();
+        fs::write(home.join(".broken"), "export A=\"1\n").unwrap();
+        for i in 1..=10 {
+            fs::write(home.join(format!(".d{}", i)), format!(". ~/.d{}\n", i + 1)).unwrap();
+        }
+
+        let entries = chain(Dialect::Posix, Mode::default(), &home, &etc);
+
+        let names: Vec<(String, usize)> = paths(&entries, root.path());
+        assert_eq!(
+            names[..3],
+            [
+                ("home/.bashrc".to_owned(), 0),
+                ("home/.broken".to_owned(), 1),
+                ("home/.d1".to_owned(), 1),
+            ]
+        );
+        // A file that doesn't parse is listed without variables.
+        assert!(entries[1].vars.is_empty());
+        assert_eq!(names.last().unwrap(), &("home/.d8".to_owned(), MAX_DEPTH));
+    }
+
+    #[test]
+    fn test_chain_by_dialect_and_mode() {
+        let root = TempDir::new().unwrap();
+        let home = root.path().join("home");
+        let etc = root.path().join("etc");
+        fs::create_dir_all(home.join(".config/fish/conf.d")).unwrap();
+        fs::create_dir_all(etc.join("fish/conf.d")).unwrap();
+        for name in [
+            ".profile",
+            ".kshrc",
+            ".cshrc",
+            ".login",
+            ".config/fish/config.fish",
+            ".config/fish/conf.d/b.fish",
+            ".config/fish/conf.d/c.fish",
+            ".config/fish/conf.d/notes.txt",
+        ] {
+            fs::write(home.join(name), "").unwrap();
+        }
+        for name in [
+            "csh.login",
+            "fish/config.fish",
+            "fish/conf.d/a.fish",
+            "fish/conf.d/b.fish",
+        ] {
+            fs::write(etc.join(name), "").unwrap();
+        }
+        let names = |dialect: Dialect, login: bool, interactive: bool| -> Vec<String> {
+            let mode = Mode { login, interactive };
+            paths(&chain(dialect, mode, &home, &etc), root.path())
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect()
+        };
+
+        // Without `.bash_profile` or `.bash_login`, a login bash reads `.profile`.
+        assert_eq!(names(Dialect::Posix, true, false), vec!["home/.profile"]);
+        assert!(names(Dialect::Posix, false, false).is_empty());
+        assert_eq!(
+            names(Dialect::Ksh, true, true),
+            vec!["home/.profile", "home/.kshrc"]
+        );
+        assert_eq!(names(Dialect::Ksh, false, true), vec!["home/.kshrc"]);
+        assert_eq!(
+            names(Dialect::Csh, true, false),
+            vec!["etc/csh.login", "home/.cshrc", "home/.login"]
+        );
+        assert_eq!(names(Dialect::Csh, false, true), vec!["home/.cshrc"]);
+        // `conf.d` snippets come first, by name, with the user's `b.fish`
+        // taking the place of the system one.
+        assert_eq!(
+            names(Dialect::Fish, false, false),
+            vec![
+                "etc/fish/conf.d/a.fish",
+                "home/.config/fish/conf.d/b.fish",
+                "home/.config/fish/conf.d/c.fish",
+                "etc/fish/config.fish",
+                "home/.config/fish/config.fish",
+            ]
+        );
+        assert!(names(Dialect::Environment, true, true).is_empty());
+    }
+}
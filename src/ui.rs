@@ -3,11 +3,16 @@ use ratatui::widgets::block::Title;
 use ratatui::{
     layout::{Constraint, Layout},
     style::Color,
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
     Frame,
 };
 
-use crate::app::App;
+use crate::app::{ActiveList, App, CurrentlyEditing, OriginFilter, Scope};
+use crate::compare::Comparison;
+use crate::error::Diagnostic;
+use crate::escape::{display, escape};
+use crate::search::Search;
+use std::ops::Range;
 
 pub fn render(app: &mut App, f: &mut Frame) {
     let size = f.size();
@@ -23,24 +28,62 @@ pub fn render(app: &mut App, f: &mut Frame) {
         .constraints([Constraint::Percentage(65), Constraint::Percentage(35)].as_ref())
         .split(size);
 
+    let show_pending = !app.pending_diff.is_empty() || !app.install_commands.is_empty();
+    let comparison = app.comparison();
+    let aux_panes: Vec<u32> = [
+        (show_pending, 3),
+        (comparison.is_some(), 2),
+        (app.show_chain, 2),
+        (app.show_capture, 2),
+        (app.picker.is_some(), 2),
+        (app.show_history, 2),
+    ]
+    .into_iter()
+    .filter_map(|(shown, weight)| shown.then_some(weight))
+    .collect();
+    let list_chunks = if aux_panes.is_empty() {
+        Layout::default()
+            .constraints([Constraint::Percentage(100)])
+            .split(chunks[0])
+    } else {
+        Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(chunks[0])
+    };
+
     let sub_chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-        .split(chunks[0]);
+        .split(list_chunks[0]);
 
     let path_items: Vec<ListItem> = app
-        .path_var_dirs
-        .iter()
-        .map(|path| ListItem::new(format!("{:?}", path)))
+        .visible_path_dirs()
+        .into_iter()
+        .map(|i| {
+            let path = &app.path_var_dirs[i];
+            let Some(search) = &app.search else {
+                return ListItem::new(format!("{:?}", path));
+            };
+            let dir = display(path.as_os_str());
+            let mut spans = vec![Span::raw("\"")];
+            spans.extend(highlighted(&dir, search.find(&dir)));
+            spans.push(Span::raw("\""));
+            ListItem::new(Line::from(spans))
+        })
         .collect();
     let mut _path_list = List::new(path_items);
+    let path_title = match app.activated_list {
+        ActiveList::PathList => "Path - insert: i/a, remove: d, move: K/J, dedupe: D",
+        ActiveList::EnvList => "Path",
+    };
 
     let path_list = _path_list
         .clone()
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(Title::from("Path").alignment(Alignment::Center)),
+                .title(Title::from(path_title).alignment(Alignment::Center)),
         )
         .highlight_symbol(">>")
         .highlight_style(
@@ -50,9 +93,32 @@ pub fn render(app: &mut App, f: &mut Frame) {
         );
 
     let env_items: Vec<ListItem> = app
-        .env_vars
-        .iter()
-        .map(|(key, value)| ListItem::new(format!("{}: {}\n", key, value)))
+        .visible_env_vars()
+        .into_iter()
+        .map(|i| {
+            let (key, value) = &app.env_vars[i];
+            let (key, system) = (display(key), key.to_str());
+            let value = display(value);
+            let system = app.scope != Scope::Dotenv
+                && system.is_some_and(|key| app.system_env_vars.contains_key(key));
+            let Some(search) = &app.search else {
+                return match system {
+                    true => ListItem::new(format!("{}: {} [system]\n", key, value)),
+                    false => ListItem::new(format!("{}: {}\n", key, value)),
+                };
+            };
+            let mut spans = highlighted(&key, search.find(&key));
+            spans.push(Span::raw(": "));
+            let value_matches = search
+                .searches_values()
+                .then(|| search.find(&value))
+                .flatten();
+            spans.extend(highlighted(&value, value_matches));
+            if system {
+                spans.push(Span::raw(" [system]"));
+            }
+            ListItem::new(Line::from(spans))
+        })
         .collect();
 
     let _env_list = List::new(env_items);
@@ -62,7 +128,7 @@ pub fn render(app: &mut App, f: &mut Frame) {
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(Title::from("Environment Variables").alignment(Alignment::Center)),
+                .title(Title::from(env_list_title(app)).alignment(Alignment::Center)),
         )
         .highlight_symbol(">>")
         .highlight_style(
@@ -72,18 +138,115 @@ pub fn render(app: &mut App, f: &mut Frame) {
         );
 
     f.render_stateful_widget(env_list, sub_chunks[0], &mut app.env_list_state);
-    f.render_stateful_widget(path_list, sub_chunks[1], &mut app.path_list_state);
+    match app.path_step {
+        Some(step) => f.render_widget(path_step_pane(app, step), sub_chunks[1]),
+        None => f.render_stateful_widget(path_list, sub_chunks[1], &mut app.path_list_state),
+    }
+    if !aux_panes.is_empty() {
+        let total: u32 = aux_panes.iter().sum();
+        let aux_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                aux_panes
+                    .iter()
+                    .map(|&weight| Constraint::Ratio(weight, total))
+                    .collect::<Vec<_>>(),
+            )
+            .split(list_chunks[1]);
+        let mut aux_chunks = aux_chunks.iter().copied();
+        if show_pending {
+            f.render_widget(pending_pane(app), aux_chunks.next().unwrap_or_default());
+        }
+        if let Some(comparison) = &comparison {
+            f.render_widget(
+                comparison_pane(app, comparison),
+                aux_chunks.next().unwrap_or_default(),
+            );
+        }
+        if app.show_chain {
+            f.render_widget(chain_pane(app), aux_chunks.next().unwrap_or_default());
+        }
+        if app.show_capture {
+            f.render_widget(capture_pane(app), aux_chunks.next().unwrap_or_default());
+        }
+        if let Some(selected) = app.picker {
+            f.render_stateful_widget(
+                picker_pane(app),
+                aux_chunks.next().unwrap_or_default(),
+                &mut ListState::default().with_selected(Some(selected)),
+            );
+        }
+        if app.show_history {
+            f.render_widget(history_pane(app), aux_chunks.next().unwrap_or_default());
+        }
+    }
     let overwrite_warning = "This environment variable value will be overwritten";
+    let expands = app
+        .env_vars
+        .get(app.selected_env_var)
+        .is_some_and(|(name, _)| app.expanding.contains(&escape(name)));
+    let edit_title = if app.activated_list == ActiveList::PathList {
+        "Edit Value".to_owned()
+    } else if expands {
+        "Edit Value ($references expand) - write literally: tab".to_owned()
+    } else {
+        "Edit Value (literal) - expand $references: tab".to_owned()
+    };
 
-    let edit_paragraph = if app.editing {
+    let edit_paragraph = if !app.confirm_install.is_empty() {
+        let files: Vec<String> = app
+            .confirm_install
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        Paragraph::new(format!(
+            "{} can only be written as root. Write staged copies and show the commands to install them?",
+            files.join(", ")
+        ))
+        .wrap(Wrap { trim: true })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Insufficient Permissions - stage copies: y, cancel: n"),
+        )
+    } else if let Some(name) = &app.confirm_unset {
+        Paragraph::new(format!(
+            "{} is inherited from outside your shell config. Write an explicit unset for it to {}?",
+            name,
+            app.target_path().display()
+        ))
+        .wrap(Wrap { trim: true })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Delete Variable - unset: y, only remove from list: n, cancel: esc"),
+        )
+    } else if app.confirm_quit {
+        Paragraph::new(format!(
+            "{} pending change(s) haven't been saved. Save them before quitting?",
+            app.pending.len()
+        ))
+        .wrap(Wrap { trim: true })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Quit - save and quit: y, discard and quit: n, cancel: esc"),
+        )
+    } else if let Some(search) = app.search.as_ref().filter(|_| app.searching) {
+        search_bar(search)
+    } else if app.currently_editing.is_some() {
+        new_var_form(app)
+    } else if let Some(name) = &app.rename_from {
+        rename_form(app, name)
+    } else if app.editing {
         if app.overwrite {
             Paragraph::new(app.env_var_value.clone())
-                .block(Block::default().borders(Borders::ALL).title(format!("Warning: {}. Edit Value", overwrite_warning)))
+                .block(Block::default().borders(Borders::ALL).title(format!("Warning: {}. {}", overwrite_warning, edit_title)))
         } else {
         Paragraph::new(app.env_var_value.clone())
-            .block(Block::default().borders(Borders::ALL).title("Edit Value"))}
+            .block(Block::default().borders(Borders::ALL).title(edit_title))}
     } else {
-        Paragraph::new(app.selected_value())
+        Paragraph::new(value_lines(app))
             .block(Block::default().borders(Borders::ALL).title("Value"))
     };
 
@@ -97,10 +260,19 @@ pub fn render(app: &mut App, f: &mut Frame) {
         .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
         .split(footer_chunks[0]);
 
-    let edit_path = Paragraph::new(app.path_var_edit.clone())
-        .block(Block::default().borders(Borders::ALL).title("Edit Value"));
+    let edit_path_title = match app.path_insert {
+        Some(_) => "Insert Directory - insert: enter, cancel: esc",
+        None => "Edit Value",
+    };
+    let edit_path = Paragraph::new(app.path_var_edit.clone()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(edit_path_title),
+    );
     let control_footer =
-        Paragraph::new("switch: ↹ (tab), exit: q/esc, edit: e, save: enter, navigate: ⇵")
+        Paragraph::new(
+            "switch: ↹ (tab), exit: q/esc, edit: e, new: a, delete: d, rename: R, scope: g, stage: enter, apply: s, discard: x, undo: u, redo: r, history: h, startup files: c, PATH steps: p, runtime capture: v, target shells: f, origin filter: o, search: /, next/previous hit: n/N, navigate: ⇵",
+        )
             .block(
                 Block::default()
                     .borders(Borders::ALL)
@@ -110,5 +282,463 @@ pub fn render(app: &mut App, f: &mut Frame) {
 
     f.render_widget(edit_paragraph, editor_chunks[0]);
     f.render_widget(edit_path, editor_chunks[1]);
-    f.render_widget(control_footer, footer_chunks[1]);
+    if app.diagnostics.is_empty() {
+        f.render_widget(control_footer, footer_chunks[1]);
+    } else {
+        let status_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(footer_chunks[1]);
+        f.render_widget(status_pane(app), status_chunks[0]);
+        f.render_widget(control_footer, status_chunks[1]);
+    }
+}
+
+/// Lists the problems envelope is working around, failures in red.
+fn status_pane(app: &App) -> Paragraph<'_> {
+    let lines: Vec<Line> = app
+        .diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let color = match diagnostic {
+                Diagnostic::Failed(_) => Color::Red,
+                diagnostic if diagnostic.read_only() => Color::Yellow,
+                _ => Color::Rgb(185, 185, 220),
+            };
+            Line::styled(diagnostic.to_string(), Style::default().fg(color))
+        })
+        .collect();
+    Paragraph::new(lines).wrap(Wrap { trim: true }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(Title::from("Status").alignment(Alignment::Center)),
+    )
+}
+
+/// Names the scope edits go to and the origin filter, if one is set.
+fn env_list_title(app: &App) -> String {
+    let title = match app.scope {
+        Scope::User => {
+            let mut targets = vec![app.config_path.display().to_string()];
+            targets.extend(
+                app.extra_target_paths()
+                    .iter()
+                    .map(|path| path.display().to_string()),
+            );
+            format!("Environment Variables - user: {}", targets.join(" + "))
+        }
+        Scope::System => format!(
+            "Environment Variables - system: {}",
+            app.system_env_path.display()
+        ),
+        Scope::SessionUser => format!(
+            "Environment Variables - session (user): {}",
+            app.target_path().display()
+        ),
+        Scope::SessionSystem => format!(
+            "Environment Variables - session (system): {}",
+            app.target_path().display()
+        ),
+        Scope::Dotenv => format!("Dotenv - {}", app.dotenv_path.display()),
+    };
+    let title = match &app.origin_filter {
+        None => title,
+        Some(OriginFilter::Inherited) => format!("{} (only inherited)", title),
+        Some(OriginFilter::File(path)) => format!("{} (only from {})", title, path.display()),
+    };
+    match &app.search {
+        Some(search) if !search.query().is_empty() => {
+            format!("{} /{}/", title, search.query())
+        }
+        _ => title,
+    }
+}
+
+/// `text` split into spans with the byte ranges in `matches` highlighted.
+fn highlighted(text: &str, matches: Option<Vec<Range<usize>>>) -> Vec<Span<'static>> {
+    let style = Style::default()
+        .fg(Color::Rgb(240, 200, 90))
+        .add_modifier(Modifier::BOLD);
+    let mut spans = Vec::new();
+    let mut shown = 0;
+    for range in matches.unwrap_or_default() {
+        if range.start > shown {
+            spans.push(Span::raw(text[shown..range.start].to_owned()));
+        }
+        spans.push(Span::styled(text[range.clone()].to_owned(), style));
+        shown = range.end;
+    }
+    if shown < text.len() || spans.is_empty() {
+        spans.push(Span::raw(text[shown..].to_owned()));
+    }
+    spans
+}
+
+/// The search query being typed, with how it matches and why it can't, if
+/// it's an invalid regular expression.
+fn search_bar(search: &Search) -> Paragraph<'_> {
+    let mut lines = vec![Line::raw(format!("/{}", search.query()))];
+    if let Some(error) = search.error() {
+        lines.push(Line::styled(
+            error.lines().last().unwrap_or(error).to_owned(),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    let title = format!(
+        "Search {} {} - regex: tab, values: shift+tab, keep: enter, clear: esc",
+        if search.is_regex() { "regex" } else { "fuzzy" },
+        if search.searches_values() {
+            "names and values"
+        } else {
+            "names"
+        },
+    );
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title))
+}
+
+/// The selected variable's value and where it's defined, followed by its
+/// effective value in graphical sessions and the drop-in that sets it, if
+/// any does.
+fn value_lines(app: &App) -> Vec<Line<'_>> {
+    let mut lines = vec![Line::raw(display(app.selected_value()))];
+    let Some((key, _)) = app.env_vars.get(app.selected_env_var) else {
+        return lines;
+    };
+    lines.push(match app.origin_of(key) {
+        Some(origin) => Line::styled(
+            format!("defined at {}", origin),
+            Style::default().fg(Color::Rgb(185, 185, 220)),
+        ),
+        None => Line::styled(
+            "inherited/unknown origin",
+            Style::default().fg(Color::DarkGray),
+        ),
+    });
+    let resolved = key.to_str().and_then(|key| app.session_env.get(key));
+    if let Some(resolved) = resolved {
+        lines.push(Line::styled(
+            format!(
+                "environment.d: {} (from {}:{})",
+                resolved.value,
+                resolved.path.display(),
+                resolved.line
+            ),
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    lines
+}
+
+/// Renders the staged changes as a colored unified diff, after any commands
+/// needed to install staged copies of files that need root.
+fn pending_pane(app: &App) -> Paragraph<'_> {
+    let mut lines: Vec<Line> = Vec::new();
+    if !app.install_commands.is_empty() {
+        lines.push(Line::styled(
+            "Run as root to install staged files:",
+            Style::default().fg(Color::Yellow),
+        ));
+        lines.extend(
+            app.install_commands
+                .iter()
+                .map(|(_, command)| {
+                    Line::styled(command.as_str(), Style::default().fg(Color::Yellow))
+                }),
+        );
+    }
+    lines.extend(app.pending_diff.lines().map(|line| {
+        let color = if line.starts_with("+++") || line.starts_with("---") {
+            Color::Rgb(185, 185, 220)
+        } else if line.starts_with('+') {
+            Color::Green
+        } else if line.starts_with('-') {
+            Color::Red
+        } else if line.starts_with("@@") {
+            Color::Cyan
+        } else {
+            Color::Reset
+        };
+        Line::styled(line, Style::default().fg(color))
+    }));
+    Paragraph::new(lines).block(
+        Block::default().borders(Borders::ALL).title(
+            Title::from(format!(
+                "Pending Changes ({}) - apply: s, discard: x",
+                app.pending.len()
+            ))
+            .alignment(Alignment::Center),
+        ),
+    )
+}
+
+/// Lists the keys that differ from the reference file, one kind per line.
+fn comparison_pane<'a>(app: &'a App, comparison: &Comparison) -> Paragraph<'a> {
+    let reference = app.compare_path.display();
+    let mut lines = Vec::new();
+    let mut section = |label: String, keys: Vec<&str>, color: Color| {
+        if !keys.is_empty() {
+            lines.push(Line::from(vec![
+                Span::styled(label, Style::default().fg(color).bold()),
+                Span::raw(keys.join(", ")),
+            ]));
+        }
+    };
+    section(
+        format!("Missing (in {}): ", reference),
+        comparison
+            .missing
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect(),
+        Color::Red,
+    );
+    section(
+        format!("Not in {}: ", reference),
+        comparison.extra.iter().map(String::as_str).collect(),
+        Color::Cyan,
+    );
+    section(
+        "Empty: ".to_owned(),
+        comparison.empty.iter().map(String::as_str).collect(),
+        Color::Yellow,
+    );
+    section(
+        "Placeholder values: ".to_owned(),
+        comparison.placeholders.iter().map(String::as_str).collect(),
+        Color::Yellow,
+    );
+    if comparison.is_empty() {
+        lines.push(Line::styled(
+            format!("Every key matches {}", reference),
+            Style::default().fg(Color::Green),
+        ));
+    }
+    Paragraph::new(lines).wrap(Wrap { trim: true }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(Title::from("Compare - add missing: m").alignment(Alignment::Center)),
+    )
+}
+
+/// Renders the name and value fields of a variable being created, with the
+/// active field highlighted and any validation error below.
+fn new_var_form(app: &App) -> Paragraph<'_> {
+    let field = |label: &'static str, value: &str, active: bool| {
+        let style = if active {
+            Style::default().fg(Color::Rgb(185, 185, 220)).bold()
+        } else {
+            Style::default()
+        };
+        Line::from(vec![
+            Span::styled(label, style),
+            Span::raw(value.to_owned()),
+        ])
+    };
+    let naming = app.currently_editing == Some(CurrentlyEditing::EnvVarName);
+    let mut lines = vec![
+        field("Name: ", &app.env_var_key, naming),
+        field("Value: ", &app.env_var_value, !naming),
+    ];
+    if let Some(error) = &app.name_error {
+        lines.push(Line::styled(error.clone(), Style::default().fg(Color::Red)));
+    }
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("New Variable - switch field: tab, create: enter, cancel: esc"),
+    )
+}
+
+/// Renders the new name of a variable being renamed and the lines the
+/// rename will change.
+fn rename_form<'a>(app: &'a App, name: &str) -> Paragraph<'a> {
+    let mut lines = vec![Line::from(vec![
+        Span::styled("New name: ", Style::default().bold()),
+        Span::raw(app.env_var_key.as_str()),
+    ])];
+    if let Some(error) = &app.name_error {
+        lines.push(Line::styled(
+            error.as_str(),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    if app.rename_preview.is_empty() {
+        lines.push(Line::raw(
+            "No startup file mentions it; it will be defined under the new name and the old one unset.",
+        ));
+    } else {
+        lines.extend(
+            app.rename_preview
+                .iter()
+                .map(|line| Line::styled(line.as_str(), Style::default().fg(Color::Cyan))),
+        );
+    }
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Rename {} - rename: enter, cancel: esc", name)),
+    )
+}
+
+/// Renders `PATH` as of one step of its replay through the startup chain:
+/// the responsible statement, then the directories with those the step
+/// added in green and moved in yellow, then those it removed in red.
+fn path_step_pane(app: &App, index: usize) -> Paragraph<'_> {
+    let title = format!(
+        "PATH step {}/{} - previous: ←, next: →, exit: p",
+        (index + 1).min(app.path_steps.len()),
+        app.path_steps.len()
+    );
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Title::from(title).alignment(Alignment::Center));
+    let Some(step) = app.path_steps.get(index) else {
+        return Paragraph::new("No startup file assigns PATH").block(block);
+    };
+    let mut lines = vec![Line::styled(
+        format!("{}:{}: {}", step.path.display(), step.line, step.statement),
+        Style::default().fg(Color::Cyan),
+    )];
+    lines.extend(step.dirs.iter().enumerate().map(|(i, dir)| {
+        let (marker, color) = if step.added.contains(&i) {
+            ("+", Color::Green)
+        } else if step.moved.contains(&i) {
+            ("~", Color::Yellow)
+        } else {
+            (" ", Color::Reset)
+        };
+        Line::styled(format!("{} {}", marker, dir), Style::default().fg(color))
+    }));
+    lines.extend(
+        step.removed
+            .iter()
+            .map(|dir| Line::styled(format!("- {}", dir), Style::default().fg(Color::Red))),
+    );
+    Paragraph::new(lines).block(block)
+}
+
+/// Lists the files the shell reads at startup, sourced files indented
+/// under the file that sources them, with the variables each exports.
+fn chain_pane(app: &App) -> List<'_> {
+    let items: Vec<ListItem> = app
+        .startup_chain
+        .iter()
+        .map(|entry| {
+            let names: Vec<&str> = entry.vars.iter().map(|(name, _)| name.as_str()).collect();
+            ListItem::new(Line::from(vec![
+                Span::raw("  ".repeat(entry.depth)),
+                Span::styled(
+                    entry.path.display().to_string(),
+                    Style::default().fg(Color::Rgb(185, 185, 220)),
+                ),
+                Span::raw(format!(": {}", names.join(", "))),
+            ]))
+        })
+        .collect();
+    List::new(items).block(Block::default().borders(Borders::ALL).title(
+        Title::from(format!("Startup ({}) - mode: l", app.shell_mode)).alignment(Alignment::Center),
+    ))
+}
+
+/// Lists the variables a freshly started shell disagrees about, flagging
+/// those only set at runtime.
+fn capture_pane(app: &App) -> Paragraph<'_> {
+    let mut lines = Vec::new();
+    if app.capturing {
+        lines.push(Line::styled(
+            "Starting the shell…",
+            Style::default().fg(Color::DarkGray),
+        ));
+    } else if let Some(error) = &app.capture_error {
+        lines.push(Line::styled(
+            error.as_str(),
+            Style::default().fg(Color::Red),
+        ));
+    } else if app.runtime_diff.is_empty() {
+        lines.push(Line::styled(
+            "The startup files account for every variable",
+            Style::default().fg(Color::Green),
+        ));
+    }
+    // The last capture's findings are stale while another runs.
+    for diff in app.runtime_diff.iter().filter(|_| !app.capturing) {
+        let (label, color, detail) = if diff.missing_at_runtime() {
+            ("declared, not set at runtime", Color::Red, String::new())
+        } else if diff.runtime_only() {
+            (
+                "only at runtime",
+                Color::Yellow,
+                format!("={}", diff.runtime.as_deref().unwrap_or_default()),
+            )
+        } else {
+            (
+                "differs from current",
+                Color::Cyan,
+                format!(
+                    "={} (currently {})",
+                    diff.runtime.as_deref().unwrap_or_default(),
+                    diff.current.as_deref().unwrap_or("unset")
+                ),
+            )
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}: ", label), Style::default().fg(color).bold()),
+            Span::raw(format!("{}{}", diff.name, detail)),
+        ]));
+    }
+    Paragraph::new(lines).wrap(Wrap { trim: true }).block(
+        Block::default().borders(Borders::ALL).title(
+            Title::from(format!("Runtime ({}) - hide: v", app.shell_mode))
+                .alignment(Alignment::Center),
+        ),
+    )
+}
+
+/// Lists the configured shells, checking those user scope edits go to.
+fn picker_pane(app: &App) -> List<'_> {
+    let items: Vec<ListItem> = app
+        .shell_configs()
+        .into_iter()
+        .map(|(dialect, path)| {
+            let target = dialect == app.dialect || app.extra_targets.contains(&dialect);
+            let mark = if target { "[x]" } else { "[ ]" };
+            let primary = if dialect == app.dialect {
+                " (shell)"
+            } else {
+                ""
+            };
+            ListItem::new(format!("{} {}{}", mark, path.display(), primary))
+        })
+        .collect();
+    List::new(items)
+        .highlight_symbol(">>")
+        .highlight_style(
+            Style::default()
+                .fg(Color::Rgb(185, 185, 220))
+                .bg(Color::Rgb(28, 13, 41)),
+        )
+        .block(
+            Block::default().borders(Borders::ALL).title(
+                Title::from("Targets - toggle: space, close: f").alignment(Alignment::Center),
+            ),
+        )
+}
+
+/// Lists past operations, most recent first, with undone ones dimmed below.
+fn history_pane(app: &App) -> List<'_> {
+    let done = app
+        .history
+        .undo
+        .iter()
+        .rev()
+        .map(|entry| ListItem::new(entry.operation.to_string()));
+    let undone = app.history.redo.iter().rev().map(|entry| {
+        ListItem::new(format!("(undone) {}", entry.operation))
+            .style(Style::default().fg(Color::DarkGray))
+    });
+    List::new(done.chain(undone).collect::<Vec<_>>()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(Title::from("History - undo: u, redo: r").alignment(Alignment::Center)),
+    )
 }
@@ -0,0 +1,370 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// Number of timestamped backups kept next to each config file.
+pub const DEFAULT_BACKUPS: usize = 5;
+
+const BACKUP_MARKER: &str = ".envelope-";
+const BACKUP_SUFFIX: &str = ".bak";
+
+/// Replaces the contents of `path` without ever leaving it half written.
+///
+/// The new contents go to a temporary file in the same directory, which is
+/// fsynced, given the original's permissions and ownership, and renamed over
+/// the original. Before that the original is copied to a timestamped backup,
+/// keeping at most `backups` of them. Symlinks are followed so dotfiles
+/// managed by tools such as stow keep pointing at the right place.
+pub fn write_atomic(path: &Path, contents: &[u8], backups: usize) -> io::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(error) if error.kind() == ErrorKind::NotFound => path.to_path_buf(),
+        Err(error) => return Err(error),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let original = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(error) if error.kind() == ErrorKind::NotFound => None,
+        Err(error) => return Err(error),
+    };
+
+    if original.is_some() && backups > 0 {
+        fs::copy(&path, backup_path(&path)?)?;
+        prune_backups(&path, backups)?;
+    }
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut temp = tempfile::Builder::new()
+        .prefix(&format!(".{}.", file_name.trim_start_matches('.')))
+        .suffix(".tmp")
+        .tempfile_in(&dir)?;
+    temp.write_all(contents)?;
+    if let Some(metadata) = &original {
+        copy_ownership(&temp, metadata)?;
+        fs::set_permissions(temp.path(), metadata.permissions())?;
+    }
+    temp.as_file().sync_all()?;
+    temp.persist(&path).map_err(|error| error.error)?;
+
+    // Make the rename itself durable. Not every platform can open a
+    // directory for syncing, so failures here are not fatal.
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Whether permissions allow `write_atomic` to replace `path`: the file, if
+/// it exists, must be writable and its directory must allow creating the
+/// temporary file. Other problems, such as `path` being a directory, are left
+/// to surface when writing.
+pub fn is_writable(path: &Path) -> bool {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let denied = |result: io::Result<()>| {
+        result.is_err_and(|error| error.kind() == ErrorKind::PermissionDenied)
+    };
+    !denied(fs::OpenOptions::new().append(true).open(path).map(drop))
+        && !denied(NamedTempFile::new_in(dir).map(drop))
+}
+
+/// Writes `contents` meant for `target` to a new private file in the temp
+/// directory, for installing with elevated privileges. Returns its path and
+/// the command that installs it with the target's mode and ownership.
+pub fn write_staged_copy(target: &Path, contents: &[u8]) -> io::Result<(PathBuf, String)> {
+    let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+    let mut staged = tempfile::Builder::new()
+        .prefix(&format!("envelope-{}.", file_name.trim_start_matches('.')))
+        .tempfile()?;
+    staged.write_all(contents)?;
+    staged.as_file().sync_all()?;
+    let (_, staged) = staged.keep().map_err(|error| error.error)?;
+    let command = install_command(&staged, target);
+    Ok((staged, command))
+}
+
+#[cfg(unix)]
+fn install_command(staged: &Path, target: &Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+    let (mode, uid, gid) = fs::metadata(target)
+        .map(|metadata| (metadata.mode() & 0o7777, metadata.uid(), metadata.gid()))
+        .unwrap_or((0o644, 0, 0));
+    format!(
+        "sudo install -m {:o} -o {} -g {} {} {}",
+        mode,
+        uid,
+        gid,
+        shell_quote(&staged.to_string_lossy()),
+        shell_quote(&target.to_string_lossy())
+    )
+}
+
+#[cfg(not(unix))]
+fn install_command(staged: &Path, target: &Path) -> String {
+    format!(
+        "copy {} {}",
+        shell_quote(&staged.to_string_lossy()),
+        shell_quote(&target.to_string_lossy())
+    )
+}
+
+fn shell_quote(text: &str) -> String {
+    if text
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+".contains(c))
+    {
+        text.to_owned()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+/// Existing backups of `path`, oldest first.
+pub fn backups(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(file_name) = path.file_name() else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut prefix = file_name.to_os_string();
+    prefix.push(BACKUP_MARKER);
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    // Ordered by timestamp, then by the `_N` counter of backups taken within
+    // the same millisecond, compared as a number so `_10` follows `_9`.
+    let mut backups: Vec<((String, usize), PathBuf)> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rest = name.strip_prefix(&prefix)?.strip_suffix(BACKUP_SUFFIX)?;
+            let key = match rest.split_once('_') {
+                Some((stamp, counter)) => (stamp.to_owned(), counter.parse().ok()?),
+                None => (rest.to_owned(), 0),
+            };
+            Some((key, entry.path()))
+        })
+        .collect();
+    backups.sort();
+    Ok(backups.into_iter().map(|(_, path)| path).collect())
+}
+
+fn backup_path(path: &Path) -> io::Result<PathBuf> {
+    let mut base: OsString = path.file_name().unwrap_or_default().to_os_string();
+    base.push(BACKUP_MARKER);
+    base.push(timestamp(SystemTime::now()));
+    let base = base.to_string_lossy().into_owned();
+    // Backups taken within the same millisecond get increasing `_N`
+    // suffixes, which `backups` orders by. Counting past the highest one
+    // taken, rather than reusing a free slot, keeps them in order after
+    // older ones are pruned.
+    let taken = backups(path)?
+        .iter()
+        .filter_map(|backup| {
+            let name = backup.file_name()?.to_string_lossy().into_owned();
+            let rest = name.strip_prefix(&base)?.strip_suffix(BACKUP_SUFFIX)?;
+            match rest.strip_prefix('_') {
+                Some(counter) => counter.parse::<usize>().ok(),
+                None if rest.is_empty() => Some(0),
+                None => None,
+            }
+        })
+        .max();
+    let name = match taken {
+        None => format!("{}{}", base, BACKUP_SUFFIX),
+        Some(counter) => format!("{}_{}{}", base, counter + 1, BACKUP_SUFFIX),
+    };
+    Ok(path.with_file_name(name))
+}
+
+fn prune_backups(path: &Path, keep: usize) -> io::Result<()> {
+    let backups = backups(path)?;
+    let excess = backups.len().saturating_sub(keep);
+    for backup in &backups[..excess] {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+/// Formats `time` as a sortable UTC timestamp such as `20240131T235959.123Z`.
+fn timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+#[cfg(unix)]
+fn copy_ownership(temp: &NamedTempFile, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    match std::os::unix::fs::fchown(temp.as_file(), Some(metadata.uid()), Some(metadata.gid())) {
+        // Only root can give files away; an unprivileged user editing their
+        // own dotfiles already owns the temp file.
+        Err(error) if error.kind() == ErrorKind::PermissionDenied => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_ownership(_temp: &NamedTempFile, _metadata: &fs::Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_atomic_replaces_contents_and_backs_up() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "old\n").unwrap();
+
+        write_atomic(&path, b"new\n", 3).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        let backups = backups(&path).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), "old\n");
+        // Only the config and its backup remain; no stray temp files.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_write_atomic_prunes_old_backups() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "0").unwrap();
+
+        for i in 1..=5 {
+            write_atomic(&path, i.to_string().as_bytes(), 2).unwrap();
+        }
+
+        let backups = backups(&path).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), "3");
+        assert_eq!(fs::read_to_string(&backups[1]).unwrap(), "4");
+    }
+
+    #[test]
+    fn test_backups_order_counters_numerically() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".bashrc");
+        fs::write(&path, "0").unwrap();
+        let stamp = "20240131T235959.123Z";
+        for suffix in ["", "_1", "_2", "_9", "_10", "_11"] {
+            let name = format!(".bashrc.envelope-{}{}.bak", stamp, suffix);
+            fs::write(dir.path().join(name), suffix).unwrap();
+        }
+        fs::write(dir.path().join(".bashrc.envelope-notes.txt"), "").unwrap();
+
+        let contents = |backups: Vec<PathBuf>| -> Vec<String> {
+            backups
+                .iter()
+                .map(|backup| fs::read_to_string(backup).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            contents(backups(&path).unwrap()),
+            ["", "_1", "_2", "_9", "_10", "_11"]
+        );
+
+        prune_backups(&path, 2).unwrap();
+        assert_eq!(contents(backups(&path).unwrap()), ["_10", "_11"]);
+    }
+
+    #[test]
+    fn test_write_atomic_creates_missing_file_without_backup() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".zshrc");
+
+        write_atomic(&path, b"export A=1\n", DEFAULT_BACKUPS).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "export A=1\n");
+        assert!(backups(&path).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_preserves_permissions_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("dotfiles.bashrc");
+        let link = dir.path().join(".bashrc");
+        fs::write(&target, "old\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        write_atomic(&link, b"new\n", 0).unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_staged_copy_for_privileged_install() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("environment");
+        fs::write(&target, "A=1\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let (staged, command) = write_staged_copy(&target, b"A=2\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&staged).unwrap(), "A=2\n");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "A=1\n");
+        assert!(command.starts_with("sudo install -m 644 -o "));
+        assert!(command.ends_with(&format!("{} {}", staged.display(), target.display())));
+        fs::remove_file(staged).unwrap();
+
+        assert!(is_writable(&target));
+        assert!(is_writable(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_706_745_599_123);
+        assert_eq!(timestamp(time), "20240131T235959.123Z");
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101T000000.000Z");
+    }
+}